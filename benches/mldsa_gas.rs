@@ -0,0 +1,69 @@
+//! Calibrates the ML-DSA precompile's base costs (`src/precompile.rs`):
+//! times `verify` at each parameter set against libsecp256k1 public key
+//! recovery, which revm's ECRECOVER runs and prices at 3000 gas, and prints
+//! the gas each level costs at the same rate.
+//!
+//! Run with `cargo bench --bench mldsa_gas`.
+
+use pqcrypto_mldsa::{mldsa44, mldsa65, mldsa87};
+use pqcrypto_traits::sign::{DetachedSignature, PublicKey};
+use secp256k1::{Message, Secp256k1, SecretKey};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const ECRECOVER_GAS: f64 = 3000.0;
+const SAMPLES: usize = 51;
+const ITERS: u32 = 200;
+
+/// Median time of one call of `f` over `SAMPLES` runs of `ITERS` calls.
+fn median(mut f: impl FnMut()) -> Duration {
+    for _ in 0..ITERS {
+        f();
+    }
+    let mut samples: Vec<Duration> = (0..SAMPLES)
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..ITERS {
+                f();
+            }
+            start.elapsed() / ITERS
+        })
+        .collect();
+    samples.sort();
+    samples[SAMPLES / 2]
+}
+
+macro_rules! mldsa {
+    ($level:ident, $msg:expr) => {{
+        let (pk, sk) = $level::keypair();
+        let sig = $level::detached_sign($msg, &sk);
+        let (pk, sig) = (pk.as_bytes().to_vec(), sig.as_bytes().to_vec());
+        median(|| {
+            let pk = $level::PublicKey::from_bytes(black_box(&pk)).unwrap();
+            let sig = $level::DetachedSignature::from_bytes(black_box(&sig)).unwrap();
+            $level::verify_detached_signature(&sig, black_box($msg), &pk).unwrap();
+        })
+    }};
+}
+
+fn main() {
+    let hash = [0x42u8; 32];
+    let secp = Secp256k1::new();
+    let msg = Message::from_slice(&hash).unwrap();
+    let sig = secp.sign_ecdsa_recoverable(&msg, &SecretKey::from_slice(&[7u8; 32]).unwrap());
+    let ecrecover = median(|| {
+        secp.recover_ecdsa(black_box(&msg), black_box(&sig))
+            .unwrap();
+    });
+
+    let rate = ECRECOVER_GAS / ecrecover.as_secs_f64();
+    println!("ecrecover  {ecrecover:>10.2?}  {ECRECOVER_GAS:>6} gas");
+    for (name, time) in [
+        ("ML-DSA-44", mldsa!(mldsa44, &hash)),
+        ("ML-DSA-65", mldsa!(mldsa65, &hash)),
+        ("ML-DSA-87", mldsa!(mldsa87, &hash)),
+    ] {
+        let gas = (time.as_secs_f64() * rate).round();
+        println!("{name}  {time:>10.2?}  {gas:>6} gas");
+    }
+}
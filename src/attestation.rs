@@ -0,0 +1,218 @@
+use crate::db::ChainStore;
+use crate::identity::ValidatorSet;
+use crate::types::{AttestationVote, BlockAttestation, SignedAttestation};
+use anyhow::{bail, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tracing::info;
+
+/// Attestations more than this many blocks away from the local head are
+/// dropped.
+const ATTESTATION_WINDOW: u64 = 256;
+
+/// Collects validator attestations of executed blocks until those matching
+/// the local execution reach 2f+1 stake.
+pub struct AttestationPool {
+    store: Arc<ChainStore>,
+    validators: Arc<ValidatorSet>,
+    /// Attestations of locally executed blocks.
+    local: BTreeMap<u64, BlockAttestation>,
+    /// Votes by block number and validator.
+    votes: BTreeMap<u64, HashMap<String, AttestationVote>>,
+}
+
+impl AttestationPool {
+    pub fn new(store: Arc<ChainStore>, validators: Arc<ValidatorSet>) -> Self {
+        Self {
+            store,
+            validators,
+            local: BTreeMap::new(),
+            votes: BTreeMap::new(),
+        }
+    }
+
+    /// Record the outcome of a block executed locally.
+    pub fn executed(&mut self, attestation: BlockAttestation) -> Result<Option<SignedAttestation>> {
+        let number = attestation.number;
+        self.local.insert(number, attestation);
+        let oldest = number.saturating_sub(ATTESTATION_WINDOW);
+        self.local = self.local.split_off(&oldest);
+        self.votes = self.votes.split_off(&oldest);
+        self.try_aggregate(number)
+    }
+
+    /// Add a vote whose signature was already checked.
+    pub fn add(&mut self, vote: AttestationVote) -> Result<Option<SignedAttestation>> {
+        let number = vote.attestation.number;
+        let head = self.local.keys().next_back().copied().unwrap_or(0);
+        let in_window = number.saturating_add(ATTESTATION_WINDOW) >= head
+            && number <= head.saturating_add(ATTESTATION_WINDOW);
+        if !in_window || self.store.get_attestation(number)?.is_some() {
+            return Ok(None);
+        }
+        self.votes
+            .entry(number)
+            .or_default()
+            .entry(vote.validator.clone())
+            .or_insert(vote);
+        self.try_aggregate(number)
+    }
+
+    /// Store and return the signed attestation of block `number` once votes
+    /// matching the local attestation reach 2f+1 stake. Fails if 2f+1 stake
+    /// attested another outcome: local execution diverged and the node must
+    /// halt.
+    fn try_aggregate(&mut self, number: u64) -> Result<Option<SignedAttestation>> {
+        let (Some(local), Some(votes)) = (self.local.get(&number), self.votes.get(&number)) else {
+            return Ok(None);
+        };
+
+        let mut stake = 0;
+        let mut conflicting = 0;
+        for vote in votes.values() {
+            if vote.attestation == *local {
+                stake += self.validators.stake(&vote.validator);
+            } else {
+                conflicting += self.validators.stake(&vote.validator);
+            }
+        }
+        if conflicting >= self.validators.quorum() {
+            bail!(
+                "state divergence: validators attested a different outcome of block {number} than local execution (state root 0x{})",
+                hex::encode(local.state_root)
+            );
+        }
+        if stake < self.validators.quorum() {
+            return Ok(None);
+        }
+
+        let signed = SignedAttestation {
+            attestation: local.clone(),
+            signatures: votes
+                .values()
+                .filter(|vote| vote.attestation == *local)
+                .map(|vote| (vote.validator.clone(), vote.signature.clone()))
+                .collect(),
+        };
+        self.store.put_attestation(&signed)?;
+        self.votes.remove(&number);
+        info!(number, signers = signed.signatures.len(), "block attested");
+        Ok(Some(signed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ValidatorConfig;
+    use crate::testing::{open_store, TempPath};
+    use revm::primitives::B256;
+
+    /// Validators a..d with stakes 2, 1, 1, 1: the quorum is 4.
+    fn pool() -> (TempPath, AttestationPool) {
+        let (dir, store) = open_store("attestation");
+        let validators: Vec<ValidatorConfig> = [("a", 2), ("b", 1), ("c", 1), ("d", 1)]
+            .into_iter()
+            .map(|(id, stake)| ValidatorConfig {
+                id: id.to_string(),
+                stake,
+                pq_pubkey_hex: String::new(),
+                fee_recipient: Default::default(),
+            })
+            .collect();
+        let validators = Arc::new(ValidatorSet::new(1337, &validators).unwrap());
+        assert_eq!(validators.quorum(), 4);
+        (dir, AttestationPool::new(store, validators))
+    }
+
+    fn attestation(number: u64, block_hash: u8) -> BlockAttestation {
+        BlockAttestation {
+            chain_id: 1337,
+            number,
+            block_hash: B256::repeat_byte(block_hash),
+            state_root: B256::repeat_byte(0x55),
+            receipts_root: B256::repeat_byte(0x66),
+        }
+    }
+
+    fn vote(validator: &str, attestation: BlockAttestation) -> AttestationVote {
+        AttestationVote {
+            attestation,
+            validator: validator.to_string(),
+            signature: validator.as_bytes().to_vec(),
+        }
+    }
+
+    fn signers(signed: &SignedAttestation) -> Vec<&str> {
+        let mut ids: Vec<&str> = signed
+            .signatures
+            .iter()
+            .map(|(id, _)| id.as_str())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn aggregates_at_exactly_the_quorum() {
+        let (_dir, mut pool) = pool();
+        assert!(pool.executed(attestation(7, 1)).unwrap().is_none());
+        assert!(pool.add(vote("a", attestation(7, 1))).unwrap().is_none());
+        assert!(pool.add(vote("b", attestation(7, 1))).unwrap().is_none());
+
+        let signed = pool.add(vote("c", attestation(7, 1))).unwrap().unwrap();
+        assert_eq!(signed.attestation, attestation(7, 1));
+        assert_eq!(signers(&signed), ["a", "b", "c"]);
+        assert_eq!(pool.store.get_attestation(7).unwrap(), Some(signed));
+        // Later votes are not aggregated again.
+        assert!(pool.add(vote("d", attestation(7, 1))).unwrap().is_none());
+    }
+
+    #[test]
+    fn counts_each_validator_once() {
+        let (_dir, mut pool) = pool();
+        pool.executed(attestation(7, 1)).unwrap();
+        assert!(pool.add(vote("a", attestation(7, 1))).unwrap().is_none());
+        assert!(pool.add(vote("a", attestation(7, 1))).unwrap().is_none());
+        assert!(pool.add(vote("b", attestation(7, 1))).unwrap().is_none());
+
+        let signed = pool.add(vote("d", attestation(7, 1))).unwrap().unwrap();
+        assert_eq!(signers(&signed), ["a", "b", "d"]);
+    }
+
+    #[test]
+    fn conflicting_votes_do_not_count() {
+        let (_dir, mut pool) = pool();
+        pool.executed(attestation(7, 1)).unwrap();
+        for id in ["a", "b"] {
+            assert!(pool.add(vote(id, attestation(7, 2))).unwrap().is_none());
+        }
+        // Agreeing stake stays at 2 of 4, conflicting at 3 of 4.
+        for id in ["c", "d"] {
+            assert!(pool.add(vote(id, attestation(7, 1))).unwrap().is_none());
+        }
+        assert!(pool.store.get_attestation(7).unwrap().is_none());
+    }
+
+    #[test]
+    fn halts_when_a_quorum_conflicts() {
+        let (_dir, mut pool) = pool();
+        pool.executed(attestation(7, 1)).unwrap();
+        for id in ["a", "b"] {
+            assert!(pool.add(vote(id, attestation(7, 2))).unwrap().is_none());
+        }
+        assert!(pool.add(vote("c", attestation(7, 2))).is_err());
+    }
+
+    #[test]
+    fn keeps_votes_for_blocks_not_executed_yet() {
+        let (_dir, mut pool) = pool();
+        pool.executed(attestation(6, 1)).unwrap();
+        for id in ["a", "b", "c"] {
+            assert!(pool.add(vote(id, attestation(7, 1))).unwrap().is_none());
+        }
+
+        let signed = pool.executed(attestation(7, 1)).unwrap().unwrap();
+        assert_eq!(signers(&signed), ["a", "b", "c"]);
+    }
+}
@@ -1,77 +1,649 @@
-use crate::types::Block;
-use crate::config::BridgeConfig;
-use anyhow::Result;
+mod aptos;
+mod bcs;
+pub mod contract;
+#[cfg(test)]
+mod mock;
+mod outbox;
+mod solana;
+mod sui;
+
+pub use outbox::{DeliveryState, OutboxEntry};
+
+use crate::config::{BridgeConfig, ChainAdapterConfig, FinalityPolicy};
+use crate::db::ChainStore;
+use crate::evm;
+use crate::merkle;
+use crate::types::{Receipt, SignedAttestation};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use contract::Deposit;
+use outbox::{now_ms, Outbox, CONFIRM_POLL, CONFIRM_TIMEOUT, FINALITY_TIMEOUT};
 use reqwest::Client;
-use serde_json::json;
-use tracing::info;
+use revm::primitives::{Address, Bytes, B256};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often the outbox is scanned for due deliveries.
+const OUTBOX_TICK: Duration = Duration::from_secs(1);
+/// How often finalized deliveries past their retention are pruned.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Payload relayed to destination chains.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum BridgeMessage {
+    /// A block's outcome, signed by 2f+1 of the validator stake.
+    Attestation(SignedAttestation),
+    /// Tokens locked here, to be minted on the destination.
+    Transfer(Box<Transfer>),
+}
+
+/// A deposit together with what a destination needs to check it on its
+/// own: the attestation of its block, and a Merkle proof of the receipt
+/// holding the `Deposit` log against the attested receipts root.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transfer {
+    pub attestation: SignedAttestation,
+    pub deposit: Deposit,
+    /// `Receipt::encode` of the receipt, i.e. the Merkle leaf data.
+    pub receipt: Bytes,
+    pub receipt_index: u64,
+    /// Position of the `Deposit` log within the receipt.
+    pub log_index: u64,
+    /// Sibling hashes from the leaf up, as `merkle::proof` returns them.
+    pub proof: Vec<B256>,
+}
+
+impl BridgeMessage {
+    /// Binary form for destination programs. A tag byte (0 attestation,
+    /// 1 transfer), then the attestation fields laid out as validators sign
+    /// them (`identity::attestation_message` without the domain) and, if
+    /// `signatures`, the signatures as a count followed by length-prefixed
+    /// validator ids and signatures. A transfer goes on with the deposit's
+    /// destination chain, recipient, token, amount and nonce, the receipt
+    /// and log index, the length-prefixed receipt and the proof as a count
+    /// of hashes. Counts and lengths are big-endian u64s.
+    fn encode(&self, signatures: bool) -> Vec<u8> {
+        let (tag, signed) = match self {
+            BridgeMessage::Attestation(signed) => (0u8, signed),
+            BridgeMessage::Transfer(transfer) => (1u8, &transfer.attestation),
+        };
+        let attestation = &signed.attestation;
+        let mut out = vec![tag];
+        out.extend_from_slice(&attestation.chain_id.to_be_bytes());
+        out.extend_from_slice(&attestation.number.to_be_bytes());
+        out.extend_from_slice(attestation.block_hash.as_slice());
+        out.extend_from_slice(attestation.state_root.as_slice());
+        out.extend_from_slice(attestation.receipts_root.as_slice());
+        if signatures {
+            out.extend_from_slice(&(signed.signatures.len() as u64).to_be_bytes());
+            for entry in self.signature_entries() {
+                out.extend_from_slice(&entry);
+            }
+        }
+        if let BridgeMessage::Transfer(transfer) = self {
+            let deposit = &transfer.deposit;
+            out.extend_from_slice(&deposit.dest_chain.to_be_bytes());
+            out.extend_from_slice(deposit.recipient.as_slice());
+            out.extend_from_slice(deposit.token.as_slice());
+            out.extend_from_slice(&deposit.amount.to_be_bytes::<32>());
+            out.extend_from_slice(&deposit.nonce.to_be_bytes::<32>());
+            out.extend_from_slice(&transfer.receipt_index.to_be_bytes());
+            out.extend_from_slice(&transfer.log_index.to_be_bytes());
+            out.extend_from_slice(&(transfer.receipt.len() as u64).to_be_bytes());
+            out.extend_from_slice(&transfer.receipt);
+            out.extend_from_slice(&(transfer.proof.len() as u64).to_be_bytes());
+            for hash in &transfer.proof {
+                out.extend_from_slice(hash.as_slice());
+            }
+        }
+        out
+    }
+
+    /// The attestation's signatures as `encode` lays each of them out, for
+    /// destinations that take them apart from the message.
+    fn signature_entries(&self) -> Vec<Vec<u8>> {
+        let signed = match self {
+            BridgeMessage::Attestation(signed) => signed,
+            BridgeMessage::Transfer(transfer) => &transfer.attestation,
+        };
+        signed
+            .signatures
+            .iter()
+            .map(|(validator, sig)| {
+                let mut entry = Vec::with_capacity(16 + validator.len() + sig.len());
+                entry.extend_from_slice(&(validator.len() as u64).to_be_bytes());
+                entry.extend_from_slice(validator.as_bytes());
+                entry.extend_from_slice(&(sig.len() as u64).to_be_bytes());
+                entry.extend_from_slice(sig);
+                entry
+            })
+            .collect()
+    }
+}
+
+/// Finds the `Deposit` logs of the bridge contract in an attested block's
+/// receipts.
+pub struct DepositWatcher {
+    contract: Address,
+}
+
+impl DepositWatcher {
+    pub fn new(contract: Address) -> Self {
+        Self { contract }
+    }
+
+    /// Transfers for every deposit in `receipts`, the receipts of the block
+    /// `signed` attests to.
+    pub fn transfers(&self, signed: &SignedAttestation, receipts: &[Receipt]) -> Vec<Transfer> {
+        let leaves = evm::receipt_leaves(receipts);
+        let mut transfers = Vec::new();
+        for (receipt_index, receipt) in receipts.iter().enumerate() {
+            if !receipt.success {
+                continue;
+            }
+            for (log_index, log) in receipt.logs.iter().enumerate() {
+                let Some(deposit) = Deposit::from_log(self.contract, log) else {
+                    continue;
+                };
+                transfers.push(Transfer {
+                    attestation: signed.clone(),
+                    deposit,
+                    receipt: receipt.encode().into(),
+                    receipt_index: receipt_index as u64,
+                    log_index: log_index as u64,
+                    proof: merkle::proof(&leaves, receipt_index).expect("index in range"),
+                });
+            }
+        }
+        transfers
+    }
+}
+
+/// Where a submitted message stands on the destination chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeliveryStatus {
+    /// Not (yet) known to the destination chain.
+    Unknown,
+    /// Accepted but not yet executed.
+    Pending,
+    /// Executed successfully.
+    Confirmed,
+    /// Executed successfully and final: the destination will not revert it.
+    Finalized,
+    /// Executed and failed.
+    Failed(String),
+}
+
+/// A message the destination can never accept, e.g. because it exceeds a
+/// size limit of the chain. It is dead-lettered at once, not retried.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct Undeliverable(pub String);
+
+/// An error object a JSON-RPC endpoint answered with.
+#[derive(Debug, thiserror::Error)]
+#[error("{method} failed: {error}")]
+struct RpcError {
+    method: String,
+    error: Value,
+}
 
+/// A destination chain the bridge relays to.
+#[async_trait]
+pub trait ChainAdapter: Send + Sync {
+    /// Name the adapter was registered under.
+    fn name(&self) -> &str;
+
+    /// Deliver a message; returns the destination transaction id. `key`
+    /// is the same on every retry of `msg` and must be passed along so the
+    /// destination can drop duplicates. Fails with `Undeliverable` if no
+    /// retry can succeed.
+    async fn submit(&self, key: &str, msg: &BridgeMessage) -> Result<String>;
+
+    /// Status of a transaction returned by `submit`.
+    async fn status(&self, tx_id: &str) -> Result<DeliveryStatus>;
+
+    /// `tx_id` was not confirmed in time and its message is submitted
+    /// again; drop any state that assumed it would commit.
+    async fn abandon(&self, _tx_id: &str) {}
+
+    /// Whether the destination endpoint is reachable and in sync.
+    async fn health(&self) -> Result<()>;
+}
+
+/// POST a JSON-RPC 2.0 request and return its `result`.
+async fn json_rpc(client: &Client, url: &str, method: &str, params: Value) -> Result<Value> {
+    let payload = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    });
+    let mut resp: Value = client
+        .post(url)
+        .json(&payload)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    if let Some(error) = resp.get_mut("error") {
+        return Err(RpcError {
+            method: method.to_string(),
+            error: error.take(),
+        }
+        .into());
+    }
+    Ok(resp
+        .get_mut("result")
+        .map(Value::take)
+        .unwrap_or(Value::Null))
+}
+
+/// Adapters registered from `BridgeConfig`, fed from a persistent outbox.
 pub struct BridgeManager {
-    cfg: BridgeConfig,
-    client: Client,
+    adapters: Vec<Arc<dyn ChainAdapter>>,
+    /// Adapter name by the id deposits use for its chain.
+    destinations: HashMap<u64, String>,
+    /// Finality policy by adapter name.
+    finality: HashMap<String, FinalityPolicy>,
+    /// Source of the certified head that finality depths count from.
+    store: Arc<ChainStore>,
+    /// `None` if the chain has no bridge contract.
+    watcher: Option<DepositWatcher>,
+    outbox: Outbox,
 }
 
 impl BridgeManager {
-    pub fn new(cfg: BridgeConfig) -> Self {
-        Self {
-            cfg,
-            client: Client::new(),
-        }
-    }
-
-    /// Example: notify Solana about a committed block (dummy payload).
-    pub async fn notify_solana(&self, block: &Block) -> Result<()> {
-        let payload = json!({
-            "jsonrpc": "2.0",
-            "id": 1,
-            "method": "bridge_notifyEthBlock",
-            "params": [{
-                "number": block.header.number,
-                "hash": format!("0x{}", hex::encode(block.header.hash.0)),
-            }]
-        });
-
-        let resp = self.client
-            .post(&self.cfg.solana_rpc_url)
-            .json(&payload)
-            .send()
-            .await?;
-
-        info!("Solana bridge response: {:?}", resp.status());
+    pub fn new(
+        cfg: BridgeConfig,
+        store: Arc<ChainStore>,
+        bridge_address: Option<Address>,
+    ) -> Result<Self> {
+        let client = Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+        let mut adapters: Vec<Arc<dyn ChainAdapter>> = Vec::new();
+        let mut destinations = HashMap::new();
+        let mut finality = HashMap::new();
+        for adapter in cfg.adapters.into_iter().filter(|a| a.enabled) {
+            info!(finality = ?adapter.finality, "Bridging to {}", adapter.name);
+            if let Some(id) = adapter.destination_id {
+                if let Some(other) = destinations.insert(id, adapter.name.clone()) {
+                    return Err(anyhow!(
+                        "bridges {other} and {} share destination id {id}",
+                        adapter.name
+                    ));
+                }
+            }
+            finality.insert(adapter.name.clone(), adapter.finality);
+            let name = adapter.name;
+            let adapter: Arc<dyn ChainAdapter> = match adapter.chain {
+                ChainAdapterConfig::Solana(cfg) => {
+                    Arc::new(solana::SolanaAdapter::new(name, cfg, client.clone())?)
+                }
+                ChainAdapterConfig::Sui(cfg) => {
+                    Arc::new(sui::SuiAdapter::new(name, cfg, client.clone())?)
+                }
+                ChainAdapterConfig::Aptos(cfg) => {
+                    Arc::new(aptos::AptosAdapter::new(name, cfg, client.clone())?)
+                }
+            };
+            adapters.push(adapter);
+        }
+        let outbox = Outbox::new(
+            store.clone(),
+            cfg.max_attempts.max(1),
+            Duration::from_secs(cfg.max_backoff_secs),
+            Duration::from_secs(cfg.retention_secs),
+        );
+        Ok(Self {
+            adapters,
+            destinations,
+            finality,
+            store,
+            watcher: bridge_address.map(DepositWatcher::new),
+            outbox,
+        })
+    }
+
+    /// Log destinations that cannot be reached; the node runs regardless.
+    pub async fn check_health(&self) {
+        for adapter in &self.adapters {
+            if let Err(e) = adapter.health().await {
+                warn!("{} bridge endpoint is unhealthy: {e:#}", adapter.name());
+            }
+        }
+    }
+
+    /// Persist an attestation that reached quorum for delivery to every
+    /// destination, along with the deposits of its block for theirs. Each
+    /// destination gets them once the block is final under its policy.
+    pub fn relay(&self, signed: &SignedAttestation, receipts: &[Receipt]) -> Result<()> {
+        let number = signed.attestation.number;
+        let attestation = BridgeMessage::Attestation(signed.clone());
+        for adapter in &self.adapters {
+            let final_at = self.final_at(adapter.name(), number);
+            self.outbox
+                .enqueue(adapter.name(), &attestation, final_at)?;
+        }
+
+        let Some(watcher) = &self.watcher else {
+            return Ok(());
+        };
+        for transfer in watcher.transfers(signed, receipts) {
+            let deposit = &transfer.deposit;
+            let Some(adapter) = self.destinations.get(&deposit.dest_chain) else {
+                warn!(
+                    nonce = %deposit.nonce,
+                    "deposit for unknown destination {} is not relayed",
+                    deposit.dest_chain
+                );
+                continue;
+            };
+            info!(nonce = %deposit.nonce, "relaying deposit to {adapter}");
+            let final_at = self.final_at(adapter, number);
+            self.outbox.enqueue(
+                adapter,
+                &BridgeMessage::Transfer(Box::new(transfer)),
+                final_at,
+            )?;
+        }
         Ok(())
     }
 
-    pub async fn notify_sui(&self, block: &Block) -> Result<()> {
-        let payload = json!({
-            "jsonrpc": "2.0",
-            "id": 1,
-            "method": "bridge_notifyEthBlock",
-            "params": [{
-                "number": block.header.number,
-                "hash": format!("0x{}", hex::encode(block.header.hash.0)),
-            }]
-        });
-
-        let resp = self.client
-            .post(&self.cfg.sui_rpc_url)
-            .json(&payload)
-            .send()
-            .await?;
-
-        info!("Sui bridge response: {:?}", resp.status());
+    /// Certified head at which block `number` is final for `adapter`;
+    /// `None` if it is final already.
+    fn final_at(&self, adapter: &str, number: u64) -> Option<u64> {
+        match self.finality.get(adapter)? {
+            FinalityPolicy::Certificate => None,
+            FinalityPolicy::Depth { blocks } => Some(number + blocks),
+        }
+    }
+
+    /// Deliver queued messages until the node shuts down. Destinations are
+    /// served concurrently so one outage does not hold up the others.
+    pub async fn run(self: Arc<Self>) {
+        let mut tick = tokio::time::interval(OUTBOX_TICK);
+        let mut prune = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = tick.tick() => {}
+                _ = prune.tick() => {
+                    match self.outbox.prune() {
+                        Ok(0) => {}
+                        Ok(pruned) => info!("pruned {pruned} finalized bridge deliveries"),
+                        Err(e) => error!("pruning the bridge outbox failed: {e:#}"),
+                    }
+                    continue;
+                }
+            }
+            let rounds = self.adapters.iter().map(|adapter| self.deliver(adapter));
+            for (adapter, result) in self
+                .adapters
+                .iter()
+                .zip(futures::future::join_all(rounds).await)
+            {
+                if let Err(e) = result {
+                    error!("{} bridge outbox failed: {e:#}", adapter.name());
+                }
+            }
+        }
+    }
+
+    /// Advance every due entry of one destination by a step.
+    async fn deliver(&self, adapter: &Arc<dyn ChainAdapter>) -> Result<()> {
+        let head = self.store.get_certified_head()?;
+        for mut entry in self.outbox.due(adapter.name())? {
+            let old_key = entry.store_key();
+            if let DeliveryState::AwaitingFinality { final_at } = entry.state {
+                if head < final_at {
+                    continue;
+                }
+                entry.state = DeliveryState::Queued;
+            }
+            match entry.state.clone() {
+                DeliveryState::Queued => match adapter.submit(&entry.key, &entry.message).await {
+                    Ok(tx_id) => {
+                        entry.state = DeliveryState::Submitted {
+                            tx_id,
+                            at_ms: now_ms(),
+                        };
+                        self.poll_later(&old_key, entry)?;
+                    }
+                    Err(e) => {
+                        let permanent = e.is::<Undeliverable>();
+                        self.failed(entry, format!("submit: {e:#}"), permanent)?
+                    }
+                },
+                DeliveryState::Submitted { tx_id, at_ms } => {
+                    let status = adapter.status(&tx_id).await;
+                    if let Err(e) = &status {
+                        warn!("{} bridge could not check {tx_id}: {e:#}", entry.adapter);
+                    }
+                    match status {
+                        Ok(DeliveryStatus::Confirmed) => {
+                            info!("{} bridge delivered {}: {tx_id}", entry.adapter, entry.key);
+                            entry.state = DeliveryState::Delivered {
+                                tx_id,
+                                at_ms: now_ms(),
+                            };
+                            self.poll_later(&old_key, entry)?;
+                        }
+                        Ok(DeliveryStatus::Finalized) => self.finalized(&old_key, entry, tx_id)?,
+                        Ok(DeliveryStatus::Failed(e)) => {
+                            self.failed(entry, format!("{tx_id} failed: {e}"), false)?
+                        }
+                        _ if now_ms().saturating_sub(at_ms)
+                            > CONFIRM_TIMEOUT.as_millis() as u64 =>
+                        {
+                            adapter.abandon(&tx_id).await;
+                            let e = format!("{tx_id} not confirmed within {CONFIRM_TIMEOUT:?}");
+                            self.failed(entry, e, false)?
+                        }
+                        // Pending, unknown yet, or the status query failed.
+                        _ => self.poll_later(&old_key, entry)?,
+                    }
+                }
+                DeliveryState::Delivered { tx_id, at_ms } => {
+                    let status = adapter.status(&tx_id).await;
+                    if let Err(e) = &status {
+                        warn!("{} bridge could not check {tx_id}: {e:#}", entry.adapter);
+                    }
+                    match status {
+                        Ok(DeliveryStatus::Finalized) => self.finalized(&old_key, entry, tx_id)?,
+                        Ok(DeliveryStatus::Failed(e)) => {
+                            self.failed(entry, format!("{tx_id} failed: {e}"), false)?
+                        }
+                        _ if now_ms().saturating_sub(at_ms)
+                            > FINALITY_TIMEOUT.as_millis() as u64 =>
+                        {
+                            let e = format!("{tx_id} not final within {FINALITY_TIMEOUT:?}");
+                            self.failed(entry, e, false)?
+                        }
+                        // Confirmed but not final yet, or dropped by a fork
+                        // and possibly back after a reorg.
+                        _ => self.poll_later(&old_key, entry)?,
+                    }
+                }
+                DeliveryState::AwaitingFinality { .. }
+                | DeliveryState::Finalized { .. }
+                | DeliveryState::DeadLetter => {}
+            }
+        }
         Ok(())
     }
 
-    pub async fn notify_aptos(&self, block: &Block) -> Result<()> {
-        // Aptos tends to use REST+JSON; we fake a simple endpoint.
-        let url = format!("{}/bridge/eth_block", self.cfg.aptos_rpc_url);
-        let payload = json!({
-            "number": block.header.number,
-            "hash": format!("0x{}", hex::encode(block.header.hash.0)),
-        });
+    /// Check on a submitted message again after `CONFIRM_POLL`.
+    fn poll_later(&self, old_key: &[u8], mut entry: OutboxEntry) -> Result<()> {
+        entry.next_attempt_ms = now_ms() + CONFIRM_POLL.as_millis() as u64;
+        self.outbox.update(old_key, &entry)
+    }
+
+    fn finalized(&self, old_key: &[u8], mut entry: OutboxEntry, tx_id: String) -> Result<()> {
+        info!("{} bridge finalized {}: {tx_id}", entry.adapter, entry.key);
+        entry.state = DeliveryState::Finalized {
+            tx_id,
+            at_ms: now_ms(),
+        };
+        self.outbox.update(old_key, &entry)
+    }
 
-        let resp = self.client.post(&url).json(&payload).send().await?;
-        info!("Aptos bridge response: {:?}", resp.status());
+    fn failed(&self, entry: OutboxEntry, error: String, permanent: bool) -> Result<()> {
+        let entry = self.outbox.fail(entry, error, permanent)?;
+        let error = entry.last_error.as_deref().unwrap_or_default();
+        if entry.state == DeliveryState::DeadLetter {
+            error!(
+                "{} bridge gave up on {} after {} attempts: {error}",
+                entry.adapter, entry.key, entry.attempts
+            );
+        } else {
+            warn!(
+                attempt = entry.attempts,
+                "{} bridge delivery of {} failed: {error}", entry.adapter, entry.key
+            );
+        }
         Ok(())
     }
+
+    /// Delivery record of a message to one destination.
+    pub fn delivery(&self, adapter: &str, key: &str) -> Result<Option<OutboxEntry>> {
+        self.outbox.get(adapter, key)
+    }
+
+    /// Messages that exhausted their retries.
+    pub fn dead_letters(&self) -> Result<Vec<OutboxEntry>> {
+        self.outbox.dead_letters()
+    }
+
+    /// Requeue a dead letter; false if there is none under that key.
+    pub fn retry_dead_letter(&self, adapter: &str, key: &str) -> Result<bool> {
+        self.outbox.retry_dead_letter(adapter, key)
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{BlockAttestation, CommitCertificate};
+    use outbox::message_key;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// A destination whose endpoint fails the first submission and whose
+    /// transaction then goes through `statuses`.
+    #[derive(Default)]
+    struct FakeChain {
+        submitted: Mutex<Vec<String>>,
+        statuses: Mutex<VecDeque<DeliveryStatus>>,
+    }
+
+    #[async_trait]
+    impl ChainAdapter for FakeChain {
+        fn name(&self) -> &str {
+            "fake"
+        }
+
+        async fn submit(&self, key: &str, _msg: &BridgeMessage) -> Result<String> {
+            let mut submitted = self.submitted.lock().unwrap();
+            submitted.push(key.to_string());
+            if submitted.len() == 1 {
+                return Err(anyhow!("endpoint down"));
+            }
+            Ok("tx-1".to_string())
+        }
+
+        async fn status(&self, _tx_id: &str) -> Result<DeliveryStatus> {
+            let mut statuses = self.statuses.lock().unwrap();
+            Ok(statuses.pop_front().unwrap_or(DeliveryStatus::Unknown))
+        }
+
+        async fn health(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn certify(store: &ChainStore, number: u64) {
+        let cert = CommitCertificate {
+            number,
+            block_hash: B256::ZERO,
+            votes: vec![],
+        };
+        store.put_certificate(&cert).unwrap();
+    }
+
+    #[tokio::test]
+    async fn delivers_through_every_state() {
+        let (_dir, store) = crate::testing::open_store("bridge");
+        let chain = Arc::new(FakeChain::default());
+        chain.statuses.lock().unwrap().extend([
+            DeliveryStatus::Pending,
+            DeliveryStatus::Confirmed,
+            DeliveryStatus::Finalized,
+        ]);
+        let adapter: Arc<dyn ChainAdapter> = chain.clone();
+        let manager = BridgeManager {
+            adapters: vec![adapter.clone()],
+            destinations: HashMap::new(),
+            finality: HashMap::from([("fake".to_string(), FinalityPolicy::Depth { blocks: 2 })]),
+            store: store.clone(),
+            watcher: None,
+            // No backoff, so the failed submission is due again at once.
+            outbox: Outbox::new(store.clone(), 3, Duration::ZERO, Duration::from_secs(60)),
+        };
+        let signed = SignedAttestation {
+            attestation: BlockAttestation {
+                chain_id: 1337,
+                number: 5,
+                block_hash: B256::repeat_byte(5),
+                state_root: B256::ZERO,
+                receipts_root: B256::ZERO,
+            },
+            signatures: vec![],
+        };
+        let key = message_key(&BridgeMessage::Attestation(signed.clone()));
+        let state = || manager.delivery("fake", &key).unwrap().unwrap().state;
+        // Skip the wait for the next status poll.
+        let poll_now = || {
+            let mut entry = manager.delivery("fake", &key).unwrap().unwrap();
+            entry.next_attempt_ms = 0;
+            manager.outbox.update(&entry.store_key(), &entry).unwrap();
+        };
+
+        manager.relay(&signed, &[]).unwrap();
+        assert_eq!(state(), DeliveryState::AwaitingFinality { final_at: 7 });
+        // Committed blocks do not count towards the depth, certified ones do.
+        store.put_head(9, [9; 32]).unwrap();
+        certify(&store, 6);
+        manager.deliver(&adapter).await.unwrap();
+        assert_eq!(state(), DeliveryState::AwaitingFinality { final_at: 7 });
+
+        certify(&store, 7);
+        manager.deliver(&adapter).await.unwrap();
+        assert_eq!(state(), DeliveryState::Queued);
+        assert_eq!(manager.delivery("fake", &key).unwrap().unwrap().attempts, 1);
+
+        manager.deliver(&adapter).await.unwrap();
+        assert!(matches!(state(), DeliveryState::Submitted { tx_id, .. } if tx_id == "tx-1"));
+        assert_eq!(*chain.submitted.lock().unwrap(), [key.clone(), key.clone()]);
+
+        poll_now();
+        manager.deliver(&adapter).await.unwrap();
+        assert!(matches!(state(), DeliveryState::Submitted { .. }));
+        poll_now();
+        manager.deliver(&adapter).await.unwrap();
+        assert!(matches!(state(), DeliveryState::Delivered { tx_id, .. } if tx_id == "tx-1"));
+        poll_now();
+        manager.deliver(&adapter).await.unwrap();
+        assert!(matches!(state(), DeliveryState::Finalized { tx_id, .. } if tx_id == "tx-1"));
+
+        // Finalized deliveries are no longer due.
+        poll_now();
+        manager.deliver(&adapter).await.unwrap();
+        assert!(matches!(state(), DeliveryState::Finalized { .. }));
+        assert_eq!(chain.submitted.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,390 @@
+use super::bcs::{address, bytes, number, uleb128};
+use super::{BridgeMessage, ChainAdapter, DeliveryStatus, Undeliverable};
+use crate::config::AptosConfig;
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use ed25519_dalek::{Signer, SigningKey};
+use reqwest::{header::CONTENT_TYPE, Client, StatusCode};
+use serde_json::Value;
+use sha3::{Digest, Sha3_256};
+use std::sync::Mutex;
+
+/// Content type of a BCS-encoded `SignedTransaction`.
+const SIGNED_TRANSACTION_BCS: &str = "application/x.aptos.signed_transaction+bcs";
+/// Largest transaction a validator accepts.
+const MAX_TRANSACTION_SIZE: usize = 64 * 1024;
+/// How long after the ledger's current time a transaction may be committed.
+const EXPIRATION_SECS: u64 = 60;
+/// Authentication key scheme of single Ed25519 keys.
+const ED25519_SCHEME: u8 = 0x00;
+
+/// Sends bridge messages as transactions calling the entry function
+/// `{module_address}::{module}::{function}(relayer, key, message)`, where
+/// `key` and `message` are `vector<u8>`.
+pub struct AptosAdapter {
+    name: String,
+    cfg: AptosConfig,
+    client: Client,
+    signer: SigningKey,
+    sender: [u8; 32],
+    module_address: [u8; 32],
+    /// Sequence number after the last submitted transaction, which may be
+    /// ahead of the committed one `/accounts` reports.
+    next_sequence: Mutex<u64>,
+}
+
+impl AptosAdapter {
+    pub fn new(name: String, cfg: AptosConfig, client: Client) -> Result<Self> {
+        let path = &cfg.private_key_path;
+        let raw =
+            std::fs::read_to_string(path).with_context(|| format!("reading Aptos key {path}"))?;
+        let digits = raw.trim();
+        let digits = digits.strip_prefix("ed25519-priv-").unwrap_or(digits);
+        let secret: [u8; 32] = hex::decode(digits.trim_start_matches("0x"))
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| anyhow!("Aptos key {path} is not a 32-byte hex Ed25519 key"))?;
+        let signer = SigningKey::from_bytes(&secret);
+
+        // Address of an account whose key was never rotated: its
+        // authentication key.
+        let mut hasher = Sha3_256::new();
+        hasher.update(signer.verifying_key().as_bytes());
+        hasher.update([ED25519_SCHEME]);
+        let sender = hasher.finalize().into();
+
+        Ok(Self {
+            module_address: address(&cfg.module_address)?,
+            name,
+            cfg,
+            client,
+            signer,
+            sender,
+            next_sequence: Mutex::new(0),
+        })
+    }
+
+    async fn get(&self, path: &str) -> Result<Value> {
+        let url = format!("{}{path}", self.cfg.rest_url);
+        Ok(self
+            .client
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    /// BCS of a `RawTransaction` with an entry function payload.
+    fn raw_transaction(
+        &self,
+        sequence_number: u64,
+        gas_unit_price: u64,
+        expiration_secs: u64,
+        chain_id: u8,
+        args: [&[u8]; 2],
+    ) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&self.sender);
+        out.extend_from_slice(&sequence_number.to_le_bytes());
+
+        uleb128(&mut out, 2); // TransactionPayload::EntryFunction
+        out.extend_from_slice(&self.module_address);
+        bytes(&mut out, self.cfg.module.as_bytes());
+        bytes(&mut out, self.cfg.function.as_bytes());
+        uleb128(&mut out, 0); // no type arguments
+        uleb128(&mut out, args.len() as u64);
+        for arg in args {
+            // Each argument is the BCS of a vector<u8>, itself as bytes.
+            let mut bcs = Vec::with_capacity(arg.len() + 4);
+            bytes(&mut bcs, arg);
+            bytes(&mut out, &bcs);
+        }
+
+        out.extend_from_slice(&self.cfg.max_gas_amount.to_le_bytes());
+        out.extend_from_slice(&gas_unit_price.to_le_bytes());
+        out.extend_from_slice(&expiration_secs.to_le_bytes());
+        out.push(chain_id);
+        out
+    }
+
+    /// BCS of a `SignedTransaction`: the raw transaction followed by an
+    /// Ed25519 authenticator over its signing message.
+    fn sign(&self, raw: Vec<u8>) -> Vec<u8> {
+        let mut message = Sha3_256::digest(b"APTOS::RawTransaction").to_vec();
+        message.extend_from_slice(&raw);
+        let signature = self.signer.sign(&message);
+
+        let mut out = raw;
+        uleb128(&mut out, 0); // TransactionAuthenticator::Ed25519
+        bytes(&mut out, self.signer.verifying_key().as_bytes());
+        bytes(&mut out, &signature.to_bytes());
+        out
+    }
+
+    /// Next sequence number to use: the account's, unless transactions
+    /// submitted since have not been committed yet.
+    async fn sequence_number(&self) -> Result<u64> {
+        let account = self
+            .get(&format!("/accounts/0x{}", hex::encode(self.sender)))
+            .await?;
+        let committed = number(&account["sequence_number"]).ok_or_else(|| {
+            anyhow!(
+                "account 0x{} has no sequence number",
+                hex::encode(self.sender)
+            )
+        })?;
+        Ok(committed.max(*self.next_sequence.lock().unwrap()))
+    }
+
+    /// Take the committed sequence number again next time, once a
+    /// transaction was dropped or expired and left a gap that would park
+    /// every later one in the mempool.
+    fn resync(&self) {
+        *self.next_sequence.lock().unwrap() = 0;
+    }
+}
+
+#[async_trait]
+impl ChainAdapter for AptosAdapter {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn submit(&self, key: &str, msg: &BridgeMessage) -> Result<String> {
+        let key = hex::decode(key)?;
+        let message = msg.encode(true);
+
+        let ledger = self.get("/").await?;
+        let chain_id = ledger["chain_id"]
+            .as_u64()
+            .and_then(|id| u8::try_from(id).ok())
+            .ok_or_else(|| anyhow!("ledger info has no chain id"))?;
+        let now_secs = number(&ledger["ledger_timestamp"])
+            .ok_or_else(|| anyhow!("ledger info has no timestamp"))?
+            / 1_000_000;
+        let gas_unit_price = self.get("/estimate_gas_price").await?["gas_estimate"]
+            .as_u64()
+            .ok_or_else(|| anyhow!("no gas price estimate"))?;
+        let sequence_number = self.sequence_number().await?;
+
+        let raw = self.raw_transaction(
+            sequence_number,
+            gas_unit_price,
+            now_secs + EXPIRATION_SECS,
+            chain_id,
+            [&key, &message],
+        );
+        let signed = self.sign(raw);
+        if signed.len() > MAX_TRANSACTION_SIZE {
+            return Err(Undeliverable(format!(
+                "transaction of {} bytes exceeds Aptos' {MAX_TRANSACTION_SIZE}",
+                signed.len()
+            ))
+            .into());
+        }
+
+        let url = format!("{}/transactions", self.cfg.rest_url);
+        let resp = self
+            .client
+            .post(&url)
+            .header(CONTENT_TYPE, SIGNED_TRANSACTION_BCS)
+            .body(signed)
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            self.resync();
+            let status = resp.status();
+            bail!("submission rejected ({status}): {}", resp.text().await?);
+        }
+        *self.next_sequence.lock().unwrap() = sequence_number + 1;
+
+        let pending: Value = resp.json().await?;
+        pending["hash"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("submission returned no transaction hash"))
+    }
+
+    async fn status(&self, tx_id: &str) -> Result<DeliveryStatus> {
+        let url = format!("{}/transactions/by_hash/{tx_id}", self.cfg.rest_url);
+        let resp = self.client.get(&url).send().await?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            // Neither pending nor committed: dropped, or expired.
+            self.resync();
+            return Ok(DeliveryStatus::Unknown);
+        }
+        let tx: Value = resp.error_for_status()?.json().await?;
+        if tx["type"] == "pending_transaction" {
+            return Ok(DeliveryStatus::Pending);
+        }
+        // Committed transactions are final; Aptos does not fork.
+        Ok(match tx["success"].as_bool() {
+            Some(true) => DeliveryStatus::Finalized,
+            _ => DeliveryStatus::Failed(tx["vm_status"].to_string()),
+        })
+    }
+
+    async fn abandon(&self, _tx_id: &str) {
+        self.resync();
+    }
+
+    async fn health(&self) -> Result<()> {
+        let url = format!("{}/-/healthy", self.cfg.rest_url);
+        self.client.get(&url).send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bridge::mock::MockEndpoint;
+    use crate::testing::TempPath;
+    use crate::types::{BlockAttestation, SignedAttestation};
+    use revm::primitives::B256;
+    use serde_json::json;
+
+    /// `SignedTransaction` of `golden_transaction` with key `[7; 32]`, as
+    /// the Aptos SDKs serialize it.
+    const GOLDEN: &str = concat!(
+        "cc405722b15c00a19d37e51d9a756de1e61b780ad0935f3363bc7fce64edbdad",
+        "0700000000000000020000000000000000000000000000000000000000000000",
+        "000000000000000001066272696467650572656c617900020201aa0302bbbbd0",
+        "0700000000000064000000000000003cf1536500000000040020ea4a6c63e29c",
+        "520abef5507b132ec5f9954776aebebe7b92421eea691446d22c400633a900ac",
+        "e209f8614fb2b3f1c935338c799538e3b78fa7be51dff5225ee6f813d30ec433",
+        "474a383f70dd5081748459e1f32f097976f34d27fbe02266039008",
+    );
+    const LEDGER_SECS: u64 = 1_700_000_000;
+
+    fn adapter(rest_url: &str) -> AptosAdapter {
+        // The key is read by `new`, so the file can go when this returns.
+        let key = TempPath::new("aptos.key");
+        std::fs::write(key.path(), format!("0x{}", hex::encode([7; 32]))).unwrap();
+        let cfg = AptosConfig {
+            rest_url: rest_url.to_string(),
+            module_address: "0x1".to_string(),
+            module: "bridge".to_string(),
+            function: "relay".to_string(),
+            private_key_path: key.to_str().to_string(),
+            max_gas_amount: 2_000,
+        };
+        let client = Client::builder().no_proxy().build().unwrap();
+        AptosAdapter::new("aptos".to_string(), cfg, client).unwrap()
+    }
+
+    fn message() -> BridgeMessage {
+        BridgeMessage::Attestation(SignedAttestation {
+            attestation: BlockAttestation {
+                chain_id: 1337,
+                number: 5,
+                block_hash: B256::repeat_byte(5),
+                state_root: B256::repeat_byte(6),
+                receipts_root: B256::repeat_byte(7),
+            },
+            signatures: vec![("validator-0".to_string(), vec![0xee; 3309])],
+        })
+    }
+
+    /// A REST API whose account has committed sequence number 7.
+    async fn fullnode() -> MockEndpoint {
+        MockEndpoint::start(|request| match request.path.as_str() {
+            "/" => (
+                200,
+                json!({
+                    "chain_id": 4,
+                    "ledger_timestamp": (LEDGER_SECS * 1_000_000).to_string(),
+                }),
+            ),
+            "/estimate_gas_price" => (200, json!({ "gas_estimate": 100 })),
+            path if path.starts_with("/accounts/0x") => (200, json!({ "sequence_number": "7" })),
+            "/transactions" => {
+                let sequence = u64::from_le_bytes(request.raw[32..40].try_into().unwrap());
+                (202, json!({ "hash": format!("0x{sequence}") }))
+            }
+            "/transactions/by_hash/0xpending" => (200, json!({ "type": "pending_transaction" })),
+            "/transactions/by_hash/0xok" => {
+                (200, json!({ "type": "user_transaction", "success": true }))
+            }
+            "/transactions/by_hash/0xfailed" => (
+                200,
+                json!({
+                    "type": "user_transaction",
+                    "success": false,
+                    "vm_status": "Move abort",
+                }),
+            ),
+            "/-/healthy" => (200, json!({ "message": "aptos-node:ok" })),
+            _ => (404, json!({ "error_code": "transaction_not_found" })),
+        })
+        .await
+    }
+
+    #[test]
+    fn golden_transaction() {
+        let adapter = adapter("http://127.0.0.1:1");
+        let raw = adapter.raw_transaction(7, 100, LEDGER_SECS + 60, 4, [&[0xaa], &[0xbb, 0xbb]]);
+        assert_eq!(hex::encode(adapter.sender), &GOLDEN[..64]);
+        assert_eq!(hex::encode(&raw), &GOLDEN[..240]);
+        assert_eq!(hex::encode(adapter.sign(raw)), GOLDEN);
+    }
+
+    #[tokio::test]
+    async fn submits_and_tracks_transactions() {
+        let node = fullnode().await;
+        let adapter = adapter(&node.url);
+        let key = hex::encode([3; 32]);
+        let msg = message();
+
+        assert_eq!(adapter.submit(&key, &msg).await.unwrap(), "0x7");
+        let posted = node.requests().pop().unwrap();
+        assert_eq!(
+            (posted.method.as_str(), posted.path.as_str()),
+            ("POST", "/transactions")
+        );
+        let raw = adapter.raw_transaction(
+            7,
+            100,
+            LEDGER_SECS + EXPIRATION_SECS,
+            4,
+            [&[3; 32], &msg.encode(true)],
+        );
+        assert_eq!(posted.raw, adapter.sign(raw));
+        // The next one queues behind the uncommitted first.
+        assert_eq!(adapter.submit(&key, &msg).await.unwrap(), "0x8");
+
+        for (tx_id, status) in [
+            ("0xpending", DeliveryStatus::Pending),
+            ("0xok", DeliveryStatus::Finalized),
+        ] {
+            assert_eq!(adapter.status(tx_id).await.unwrap(), status, "{tx_id}");
+        }
+        assert!(matches!(
+            adapter.status("0xfailed").await.unwrap(),
+            DeliveryStatus::Failed(_)
+        ));
+        adapter.health().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn dropped_transactions_reset_the_sequence_number() {
+        let node = fullnode().await;
+        let adapter = adapter(&node.url);
+        let key = hex::encode([3; 32]);
+        let msg = message();
+
+        assert_eq!(adapter.submit(&key, &msg).await.unwrap(), "0x7");
+        // Expired without committing: the node no longer knows it.
+        assert_eq!(
+            adapter.status("0x7").await.unwrap(),
+            DeliveryStatus::Unknown
+        );
+        assert_eq!(adapter.submit(&key, &msg).await.unwrap(), "0x7");
+
+        // Or the bridge gave up waiting for it.
+        adapter.abandon("0x7").await;
+        assert_eq!(adapter.submit(&key, &msg).await.unwrap(), "0x7");
+    }
+}
@@ -0,0 +1,37 @@
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+/// ULEB128, BCS's encoding of lengths and enum variants.
+pub fn uleb128(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// A byte vector: ULEB128 length, then the bytes.
+pub fn bytes(out: &mut Vec<u8>, data: &[u8]) {
+    uleb128(out, data.len() as u64);
+    out.extend_from_slice(data);
+}
+
+/// Decode a 0x-hex Move address or object id, left-padded to 32 bytes.
+pub fn address(addr: &str) -> Result<[u8; 32]> {
+    let digits = addr.trim_start_matches("0x");
+    hex::decode(format!("{digits:0>64}"))
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| anyhow!("{addr:?} is not a 32-byte address"))
+}
+
+/// A u64 from a JSON API that sends large numbers as strings.
+pub fn number(value: &Value) -> Option<u64> {
+    value
+        .as_u64()
+        .or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+}
@@ -0,0 +1,606 @@
+use crate::types::{InboundTransfer, SYSTEM_ADDRESS};
+use revm::primitives::{address, keccak256, Address, Bytes, Log, B256, U256};
+use serde::{Deserialize, Serialize};
+
+/// Where the dev genesis allocates the bridge contract.
+pub const BRIDGE_ADDRESS: Address = address!("0000000000000000000000000000000000000b1d");
+
+pub const DEPOSIT_EVENT: &str = "Deposit(uint64,bytes32,address,uint256,uint256)";
+const DEPOSIT_FN: &str = "deposit(uint64,bytes32)";
+const DEPOSIT_TOKEN_FN: &str = "depositToken(uint64,bytes32,address,uint256)";
+const TRANSFER_FROM_FN: &str = "transferFrom(address,address,uint256)";
+pub const RELEASED_EVENT: &str = "Released(uint64,uint64,address,address,uint256)";
+const RELEASE_FN: &str = "release(uint64,uint64,address,address,uint256,bool)";
+const TRANSFER_FN: &str = "transfer(address,uint256)";
+const MINT_FN: &str = "mint(address,uint256)";
+
+/// A lock on this chain, to be minted on `dest_chain`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deposit {
+    pub dest_chain: u64,
+    /// Destination-chain account, left-aligned if shorter than 32 bytes.
+    pub recipient: B256,
+    /// `Address::ZERO` for native ETH.
+    pub token: Address,
+    pub amount: U256,
+    pub nonce: U256,
+}
+
+impl Deposit {
+    /// Decode a `Deposit` log emitted by the contract at `contract`.
+    pub fn from_log(contract: Address, log: &Log) -> Option<Self> {
+        if log.address != contract
+            || log.topics.len() != 3
+            || log.topics[0] != keccak256(DEPOSIT_EVENT)
+            || log.data.len() != 96
+        {
+            return None;
+        }
+        let dest_chain = U256::from_be_bytes(log.topics[1].0);
+        let word = |i: usize| U256::from_be_slice(&log.data[i * 32..(i + 1) * 32]);
+        if word(0) >> 160 != U256::ZERO {
+            return None;
+        }
+        Some(Self {
+            dest_chain: dest_chain.try_into().ok()?,
+            recipient: log.topics[2],
+            token: Address::from_slice(&log.data[12..32]),
+            amount: word(1),
+            nonce: word(2),
+        })
+    }
+}
+
+fn selector(signature: &str) -> [u8; 4] {
+    keccak256(signature)[..4].try_into().expect("4 bytes")
+}
+
+/// Storage slot `release` sets once the transfer with `nonce` from
+/// `source_chain` has been applied: `keccak256(sourceChain . nonce)`.
+pub fn used_slot(source_chain: u64, nonce: u64) -> U256 {
+    let word = |value: u64| U256::from(value).to_be_bytes::<32>();
+    keccak256([word(source_chain), word(nonce)].concat()).into()
+}
+
+/// Calldata of the system call applying an inbound transfer.
+pub fn release_call(transfer: &InboundTransfer) -> Bytes {
+    let word = |value: U256| value.to_be_bytes::<32>();
+    let address = |address: Address| address.into_word().0;
+    [
+        selector(RELEASE_FN).as_slice(),
+        &word(U256::from(transfer.source_chain)),
+        &word(U256::from(transfer.nonce)),
+        &address(transfer.token),
+        &address(transfer.recipient),
+        &word(transfer.amount),
+        &word(U256::from(transfer.mint as u8)),
+    ]
+    .concat()
+    .into()
+}
+
+mod op {
+    pub const STOP: u8 = 0x00;
+    pub const ADD: u8 = 0x01;
+    pub const LT: u8 = 0x10;
+    pub const EQ: u8 = 0x14;
+    pub const ISZERO: u8 = 0x15;
+    pub const SHR: u8 = 0x1c;
+    pub const SHA3: u8 = 0x20;
+    pub const ADDRESS: u8 = 0x30;
+    pub const CALLER: u8 = 0x33;
+    pub const CALLVALUE: u8 = 0x34;
+    pub const CALLDATALOAD: u8 = 0x35;
+    pub const CALLDATASIZE: u8 = 0x36;
+    pub const EXTCODESIZE: u8 = 0x3b;
+    pub const RETURNDATASIZE: u8 = 0x3d;
+    pub const MLOAD: u8 = 0x51;
+    pub const MSTORE: u8 = 0x52;
+    pub const SLOAD: u8 = 0x54;
+    pub const SSTORE: u8 = 0x55;
+    pub const JUMP: u8 = 0x56;
+    pub const JUMPI: u8 = 0x57;
+    pub const GAS: u8 = 0x5a;
+    pub const JUMPDEST: u8 = 0x5b;
+    pub const PUSH1: u8 = 0x60;
+    pub const DUP1: u8 = 0x80;
+    pub const SWAP1: u8 = 0x90;
+    pub const LOG3: u8 = 0xa3;
+    pub const CALL: u8 = 0xf1;
+    pub const REVERT: u8 = 0xfd;
+}
+
+/// Just enough of an assembler to write the contract readably: opcodes,
+/// minimal pushes and jumps to labels resolved at the end.
+#[derive(Default)]
+struct Asm {
+    code: Vec<u8>,
+    labels: Vec<Option<u16>>,
+    fixups: Vec<(usize, usize)>,
+}
+
+impl Asm {
+    fn ops(&mut self, ops: &[u8]) -> &mut Self {
+        self.code.extend_from_slice(ops);
+        self
+    }
+
+    fn push(&mut self, value: &[u8]) -> &mut Self {
+        let start = value
+            .iter()
+            .position(|b| *b != 0)
+            .unwrap_or(value.len() - 1);
+        let value = &value[start..];
+        self.code.push(op::PUSH1 + value.len() as u8 - 1);
+        self.code.extend_from_slice(value);
+        self
+    }
+
+    fn push_u8(&mut self, value: u8) -> &mut Self {
+        self.push(&[value])
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    fn place(&mut self, label: usize) -> &mut Self {
+        self.labels[label] = Some(self.code.len() as u16);
+        self.ops(&[op::JUMPDEST])
+    }
+
+    /// Push the label's offset as a PUSH2, patched in `finish`.
+    fn push_label(&mut self, label: usize) -> &mut Self {
+        self.code.push(op::PUSH1 + 1);
+        self.fixups.push((self.code.len(), label));
+        self.ops(&[0, 0])
+    }
+
+    fn jump_if(&mut self, label: usize) -> &mut Self {
+        self.push_label(label).ops(&[op::JUMPI])
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        for (at, label) in &self.fixups {
+            let offset = self.labels[*label].expect("label placed");
+            self.code[*at..*at + 2].copy_from_slice(&offset.to_be_bytes());
+        }
+        self.code
+    }
+}
+
+/// Runtime bytecode of the bridge contract, a system contract allocated at
+/// genesis that takes custody of native ETH or ERC-20 tokens and logs a
+/// `Deposit` for the destination chain to mint against.
+///
+/// - `deposit(uint64 destChain, bytes32 recipient)` payable: locks
+///   `msg.value` of native ETH, logged with token `address(0)`.
+/// - `depositToken(uint64 destChain, bytes32 recipient, address token,
+///   uint256 amount)`: pulls `amount` of `token` from the caller with
+///   `transferFrom`; the caller must have approved the contract.
+///
+/// Both log `Deposit(uint64 indexed destChain, bytes32 indexed recipient,
+/// address token, uint256 amount, uint256 nonce)`, the nonce counting
+/// deposits from 0 in storage slot 0.
+///
+/// The way back is `release(uint64 sourceChain, uint64 nonce, address token,
+/// address recipient, uint256 amount, bool mint)`, callable only by
+/// `SYSTEM_ADDRESS` once guardians signed the transfer. It sends native ETH
+/// (token `address(0)`, never minted) or calls the token's `transfer` or,
+/// with `mint`, its `mint(address,uint256)`, which the token must allow the
+/// bridge to call. Slot `keccak256(sourceChain . nonce)` marks the nonce as
+/// used, so each transfer is applied once. Logs `Released(uint64 indexed
+/// sourceChain, uint64 indexed nonce, address token, address recipient,
+/// uint256 amount)`.
+#[rustfmt::skip]
+pub fn runtime_code() -> Bytes {
+    use op::*;
+
+    let mut asm = Asm::default();
+    let (revert, emit, deposit, deposit_token, token_ok) =
+        (asm.label(), asm.label(), asm.label(), asm.label(), asm.label());
+    let (release, release_token, mint_selector, call_token, released) =
+        (asm.label(), asm.label(), asm.label(), asm.label(), asm.label());
+
+    // Dispatch on the selector, left on the stack.
+    asm.push_u8(0).ops(&[CALLDATALOAD]).push_u8(0xe0).ops(&[SHR]);
+    asm.ops(&[DUP1]).push(&selector(DEPOSIT_FN)).ops(&[EQ]).jump_if(deposit);
+    asm.ops(&[DUP1]).push(&selector(DEPOSIT_TOKEN_FN)).ops(&[EQ]).jump_if(deposit_token);
+    asm.ops(&[DUP1]).push(&selector(RELEASE_FN)).ops(&[EQ]).jump_if(release);
+    asm.place(revert).push_u8(0).ops(&[DUP1, REVERT]);
+
+    // deposit: mem[0..64] = (address(0), msg.value)
+    asm.place(deposit);
+    asm.push_u8(4 + 2 * 32).ops(&[CALLDATASIZE, LT]).jump_if(revert);
+    asm.ops(&[CALLVALUE, ISZERO]).jump_if(revert);
+    asm.push_u8(0).push_u8(0).ops(&[MSTORE]);
+    asm.ops(&[CALLVALUE]).push_u8(32).ops(&[MSTORE]);
+    asm.push_label(emit).ops(&[JUMP]);
+
+    // depositToken: token.transferFrom(msg.sender, this, amount), then
+    // mem[0..64] = (token, amount)
+    asm.place(deposit_token);
+    asm.push_u8(4 + 4 * 32).ops(&[CALLDATASIZE, LT]).jump_if(revert);
+    asm.ops(&[CALLVALUE]).jump_if(revert);
+    // token: a clean, non-zero address with code
+    asm.push_u8(4 + 2 * 32).ops(&[CALLDATALOAD]);
+    asm.ops(&[DUP1]).push_u8(160).ops(&[SHR]).jump_if(revert);
+    asm.ops(&[DUP1, EXTCODESIZE, ISZERO]).jump_if(revert);
+    // amount: non-zero
+    asm.push_u8(4 + 3 * 32).ops(&[CALLDATALOAD]);
+    asm.ops(&[DUP1, ISZERO]).jump_if(revert);
+    // stack: selector, token, amount
+    let mut transfer_from = [0u8; 32];
+    transfer_from[..4].copy_from_slice(&selector(TRANSFER_FROM_FN));
+    asm.push(&transfer_from).push_u8(0).ops(&[MSTORE]);
+    asm.ops(&[CALLER]).push_u8(4).ops(&[MSTORE]);
+    asm.ops(&[ADDRESS]).push_u8(36).ops(&[MSTORE]);
+    asm.ops(&[DUP1]).push_u8(68).ops(&[MSTORE]);
+    // call(gas, token, 0, 0, 100, 0, 32)
+    asm.push_u8(32).push_u8(0).push_u8(100).push_u8(0).push_u8(0);
+    asm.ops(&[DUP1 + 6, GAS, CALL, ISZERO]).jump_if(revert);
+    // Accept tokens returning nothing or `true`.
+    asm.ops(&[RETURNDATASIZE, ISZERO]).jump_if(token_ok);
+    asm.push_u8(32).ops(&[RETURNDATASIZE, LT]).jump_if(revert);
+    asm.push_u8(0).ops(&[MLOAD]).push_u8(1).ops(&[EQ, ISZERO]).jump_if(revert);
+    asm.place(token_ok);
+    asm.push_u8(32).ops(&[MSTORE]);
+    asm.push_u8(0).ops(&[MSTORE]);
+    asm.push_label(emit).ops(&[JUMP]);
+
+    // emit: mem[64..96] = nonce++, then
+    // log3(0, 96, Deposit, destChain, recipient)
+    asm.place(emit);
+    asm.push_u8(0).ops(&[SLOAD, DUP1]).push_u8(64).ops(&[MSTORE]);
+    asm.push_u8(1).ops(&[ADD]).push_u8(0).ops(&[SSTORE]);
+    asm.push_u8(36).ops(&[CALLDATALOAD]);
+    asm.push_u8(4).ops(&[CALLDATALOAD]);
+    asm.ops(&[DUP1]).push_u8(64).ops(&[SHR]).jump_if(revert);
+    asm.push(keccak256(DEPOSIT_EVENT).as_slice());
+    asm.push_u8(96).push_u8(0).ops(&[LOG3, STOP]);
+
+    // release: only from SYSTEM_ADDRESS, each (sourceChain, nonce) once
+    asm.place(release);
+    asm.push_u8(4 + 6 * 32).ops(&[CALLDATASIZE, LT]).jump_if(revert);
+    asm.ops(&[CALLVALUE]).jump_if(revert);
+    asm.ops(&[CALLER]).push(SYSTEM_ADDRESS.as_slice()).ops(&[EQ, ISZERO]).jump_if(revert);
+    asm.push_u8(4).ops(&[CALLDATALOAD]).push_u8(0).ops(&[MSTORE]);
+    asm.push_u8(36).ops(&[CALLDATALOAD]).push_u8(32).ops(&[MSTORE]);
+    asm.push_u8(64).push_u8(0).ops(&[SHA3]);
+    asm.ops(&[DUP1, SLOAD]).jump_if(revert);
+    asm.push_u8(1).ops(&[SWAP1, SSTORE]);
+    // stack: selector, amount, recipient, token
+    asm.push_u8(4 + 4 * 32).ops(&[CALLDATALOAD]);
+    asm.push_u8(4 + 3 * 32).ops(&[CALLDATALOAD]);
+    asm.ops(&[DUP1]).push_u8(160).ops(&[SHR]).jump_if(revert);
+    asm.push_u8(4 + 2 * 32).ops(&[CALLDATALOAD]);
+    asm.ops(&[DUP1]).jump_if(release_token);
+    // native ETH: call(gas, recipient, amount, 0, 0, 0, 0)
+    asm.push_u8(4 + 5 * 32).ops(&[CALLDATALOAD]).jump_if(revert);
+    asm.push_u8(0).ops(&[DUP1, DUP1, DUP1]);
+    asm.ops(&[DUP1 + 6, DUP1 + 6, GAS, CALL, ISZERO]).jump_if(revert);
+    asm.push_label(released).ops(&[JUMP]);
+
+    // ERC-20: mem[0..68] = (transfer or mint selector, recipient, amount)
+    asm.place(release_token);
+    asm.ops(&[DUP1]).push_u8(160).ops(&[SHR]).jump_if(revert);
+    asm.ops(&[DUP1, EXTCODESIZE, ISZERO]).jump_if(revert);
+    let (mut transfer, mut mint) = ([0u8; 32], [0u8; 32]);
+    transfer[..4].copy_from_slice(&selector(TRANSFER_FN));
+    mint[..4].copy_from_slice(&selector(MINT_FN));
+    asm.push_u8(4 + 5 * 32).ops(&[CALLDATALOAD]).jump_if(mint_selector);
+    asm.push(&transfer).push_label(call_token).ops(&[JUMP]);
+    asm.place(mint_selector).push(&mint);
+    asm.place(call_token).push_u8(0).ops(&[MSTORE]);
+    asm.ops(&[DUP1 + 1]).push_u8(4).ops(&[MSTORE]);
+    asm.ops(&[DUP1 + 2]).push_u8(36).ops(&[MSTORE]);
+    // call(gas, token, 0, 0, 68, 0, 32)
+    asm.push_u8(32).push_u8(0).push_u8(68).push_u8(0).push_u8(0);
+    asm.ops(&[DUP1 + 5, GAS, CALL, ISZERO]).jump_if(revert);
+    asm.ops(&[RETURNDATASIZE, ISZERO]).jump_if(released);
+    asm.push_u8(32).ops(&[RETURNDATASIZE, LT]).jump_if(revert);
+    asm.push_u8(0).ops(&[MLOAD]).push_u8(1).ops(&[EQ, ISZERO]).jump_if(revert);
+
+    // released: log3(0, 96, Released, sourceChain, nonce) with data
+    // (token, recipient, amount)
+    asm.place(released);
+    asm.push_u8(0).ops(&[MSTORE]);
+    asm.push_u8(32).ops(&[MSTORE]);
+    asm.push_u8(64).ops(&[MSTORE]);
+    asm.push_u8(36).ops(&[CALLDATALOAD]);
+    asm.push_u8(4).ops(&[CALLDATALOAD]);
+    asm.push(keccak256(RELEASED_EVENT).as_slice());
+    asm.push_u8(96).push_u8(0).ops(&[LOG3, STOP]);
+
+    asm.finish().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm::StateDb;
+    use revm::{
+        db::{CacheDB, EmptyDB},
+        primitives::{AccountInfo, Bytecode, Env, ExecutionResult, TransactTo},
+        Database, DatabaseCommit, EVM,
+    };
+
+    const USER: Address = address!("00000000000000000000000000000000000000a1");
+    const TOKEN: Address = address!("00000000000000000000000000000000000000c1");
+    const RECIPIENT: B256 = B256::repeat_byte(0x7e);
+    const ETHER: u64 = 1_000_000_000_000_000_000;
+
+    /// A minimal ERC-20: balances in the slot named by the holder address,
+    /// no allowances. `transfer` and `transferFrom` return `true`, `mint`
+    /// returns nothing, as some tokens do.
+    #[rustfmt::skip]
+    fn token_code() -> Bytes {
+        use op::*;
+        const SUB: u8 = 0x03;
+        const GT: u8 = 0x11;
+        const RETURN: u8 = 0xf3;
+
+        let mut asm = Asm::default();
+        let (revert, transfer_from, transfer, mint, debit, credit) =
+            (asm.label(), asm.label(), asm.label(), asm.label(), asm.label(), asm.label());
+        asm.push_u8(0).ops(&[CALLDATALOAD]).push_u8(0xe0).ops(&[SHR]);
+        asm.ops(&[DUP1]).push(&selector(TRANSFER_FROM_FN)).ops(&[EQ]).jump_if(transfer_from);
+        asm.ops(&[DUP1]).push(&selector(TRANSFER_FN)).ops(&[EQ]).jump_if(transfer);
+        asm.ops(&[DUP1]).push(&selector(MINT_FN)).ops(&[EQ]).jump_if(mint);
+        asm.place(revert).push_u8(0).ops(&[DUP1, REVERT]);
+
+        // stack for debit: to, amount, from
+        asm.place(transfer_from);
+        asm.push_u8(36).ops(&[CALLDATALOAD]).push_u8(68).ops(&[CALLDATALOAD]);
+        asm.push_u8(4).ops(&[CALLDATALOAD]).push_label(debit).ops(&[JUMP]);
+        asm.place(transfer);
+        asm.push_u8(4).ops(&[CALLDATALOAD]).push_u8(36).ops(&[CALLDATALOAD]);
+        asm.ops(&[CALLER]).push_label(debit).ops(&[JUMP]);
+
+        asm.place(debit);
+        asm.ops(&[DUP1, SLOAD, DUP1, DUP1 + 3, GT]).jump_if(revert);
+        asm.ops(&[DUP1 + 2, SWAP1, SUB, SWAP1, SSTORE]);
+        asm.ops(&[DUP1 + 1, SLOAD, ADD, SWAP1, SSTORE]);
+        asm.push_u8(1).push_u8(0).ops(&[MSTORE]);
+        asm.push_u8(32).push_u8(0).ops(&[RETURN]);
+
+        asm.place(mint);
+        asm.push_u8(4).ops(&[CALLDATALOAD]).push_u8(36).ops(&[CALLDATALOAD]);
+        asm.place(credit);
+        asm.ops(&[DUP1 + 1, SLOAD, ADD, SWAP1, SSTORE, STOP]);
+
+        asm.finish().into()
+    }
+
+    fn token_word() -> [u8; 32] {
+        TOKEN.into_word().0
+    }
+
+    fn word(value: u64) -> [u8; 32] {
+        U256::from(value).to_be_bytes()
+    }
+
+    fn call_data(signature: &str, words: &[[u8; 32]]) -> Bytes {
+        let mut data = selector(signature).to_vec();
+        for word in words {
+            data.extend_from_slice(word);
+        }
+        data.into()
+    }
+
+    fn deploy(db: &mut StateDb, address: Address, code: Bytes, balance: U256) {
+        let code = Bytecode::new_raw(code);
+        let info = AccountInfo::new(balance, 1, code.hash_slow(), code);
+        db.insert_account_info(address, info);
+    }
+
+    /// The bridge, a token in which `USER` holds 1000 and an account with
+    /// 10 ETH at `USER`.
+    fn chain() -> StateDb {
+        let mut db = CacheDB::new(EmptyDB::default());
+        deploy(&mut db, BRIDGE_ADDRESS, runtime_code(), U256::ZERO);
+        deploy(&mut db, TOKEN, token_code(), U256::ZERO);
+        db.insert_account_storage(TOKEN, USER.into_word().into(), U256::from(1000))
+            .unwrap();
+        let info = AccountInfo {
+            balance: U256::from(10 * ETHER),
+            ..Default::default()
+        };
+        db.insert_account_info(USER, info);
+        db
+    }
+
+    fn call(db: &mut StateDb, caller: Address, value: U256, data: Bytes) -> ExecutionResult {
+        let mut env = Env::default();
+        env.tx.caller = caller;
+        env.tx.transact_to = TransactTo::Call(BRIDGE_ADDRESS);
+        env.tx.value = value;
+        env.tx.data = data;
+        env.tx.gas_limit = 1_000_000;
+        let mut evm = EVM::with_env(env);
+        evm.database(&mut *db);
+        let out = evm.transact().expect("valid transaction");
+        db.commit(out.state);
+        out.result
+    }
+
+    fn by_user(db: &mut StateDb, value: u64, data: Bytes) -> ExecutionResult {
+        call(db, USER, U256::from(value), data)
+    }
+
+    fn by_system(db: &mut StateDb, data: Bytes) -> ExecutionResult {
+        call(db, SYSTEM_ADDRESS, U256::ZERO, data)
+    }
+
+    fn reverted(result: &ExecutionResult) -> bool {
+        matches!(result, ExecutionResult::Revert { .. })
+    }
+
+    fn logs(result: ExecutionResult) -> Vec<Log> {
+        match result {
+            ExecutionResult::Success { logs, .. } => logs,
+            other => panic!("call failed: {other:?}"),
+        }
+    }
+
+    fn balance(db: &mut StateDb, address: Address) -> U256 {
+        db.basic(address).unwrap().unwrap_or_default().balance
+    }
+
+    fn token_balance(db: &mut StateDb, holder: Address) -> U256 {
+        db.storage(TOKEN, holder.into_word().into()).unwrap()
+    }
+
+    fn nonce(db: &mut StateDb) -> U256 {
+        db.storage(BRIDGE_ADDRESS, U256::ZERO).unwrap()
+    }
+
+    fn deposit(dest_chain: u64) -> Bytes {
+        call_data(DEPOSIT_FN, &[word(dest_chain), RECIPIENT.0])
+    }
+
+    fn deposit_token(token: [u8; 32], amount: u64) -> Bytes {
+        let words = [word(2), RECIPIENT.0, token, word(amount)];
+        call_data(DEPOSIT_TOKEN_FN, &words)
+    }
+
+    fn release(nonce: u64, token: Address, amount: u64, mint: bool) -> Bytes {
+        release_call(&InboundTransfer {
+            source_chain: 3,
+            nonce,
+            token,
+            recipient: USER,
+            amount: U256::from(amount),
+            mint,
+        })
+    }
+
+    fn release_eth(nonce: u64, mint: bool) -> Bytes {
+        release(nonce, Address::ZERO, ETHER, mint)
+    }
+
+    #[test]
+    fn deposits_lock_eth_and_log_counted_deposits() {
+        let mut db = chain();
+        for nonce in 0..2u64 {
+            let logs = logs(by_user(&mut db, ETHER, deposit(1)));
+            assert_eq!(logs.len(), 1);
+            let deposit = Deposit::from_log(BRIDGE_ADDRESS, &logs[0]).expect("a Deposit log");
+            assert_eq!(
+                deposit,
+                Deposit {
+                    dest_chain: 1,
+                    recipient: RECIPIENT,
+                    token: Address::ZERO,
+                    amount: U256::from(ETHER),
+                    nonce: U256::from(nonce),
+                }
+            );
+            // Only the bridge's own logs decode.
+            assert_eq!(Deposit::from_log(TOKEN, &logs[0]), None);
+        }
+        assert_eq!(nonce(&mut db), U256::from(2));
+        assert_eq!(balance(&mut db, BRIDGE_ADDRESS), U256::from(2 * ETHER));
+    }
+
+    #[test]
+    fn token_deposits_pull_the_tokens() {
+        let mut db = chain();
+        let logs = logs(by_user(&mut db, 0, deposit_token(token_word(), 400)));
+        let deposit = Deposit::from_log(BRIDGE_ADDRESS, &logs[0]).expect("a Deposit log");
+        assert_eq!(deposit.token, TOKEN);
+        assert_eq!(deposit.amount, U256::from(400));
+        assert_eq!(deposit.nonce, U256::ZERO);
+        assert_eq!(token_balance(&mut db, USER), U256::from(600));
+        assert_eq!(token_balance(&mut db, BRIDGE_ADDRESS), U256::from(400));
+
+        // More than the caller holds: the token reverts, and so the deposit.
+        let result = by_user(&mut db, 0, deposit_token(token_word(), 601));
+        assert!(reverted(&result));
+        assert_eq!(nonce(&mut db), U256::from(1));
+    }
+
+    #[test]
+    fn malformed_deposits_revert() {
+        let mut db = chain();
+        let mut dirty = token_word();
+        dirty[0] = 1;
+        let short = deposit(1).slice(..4 + 32);
+        let cases = [
+            ("zero value", 0, deposit(1)),
+            ("short calldata", ETHER, short),
+            (
+                "destChain over 64 bits",
+                ETHER,
+                call_data(DEPOSIT_FN, &[[1; 32], RECIPIENT.0]),
+            ),
+            ("unknown selector", ETHER, call_data("withdraw()", &[])),
+            ("dirty token address", 0, deposit_token(dirty, 400)),
+            (
+                "token without code",
+                0,
+                deposit_token(USER.into_word().0, 400),
+            ),
+            ("native token", 0, deposit_token([0; 32], 400)),
+            ("zero amount", 0, deposit_token(token_word(), 0)),
+            ("value with a token", 1, deposit_token(token_word(), 400)),
+        ];
+        for (case, value, data) in cases {
+            let result = by_user(&mut db, value, data);
+            assert!(reverted(&result), "{case}: {result:?}");
+        }
+        assert_eq!(nonce(&mut db), U256::ZERO);
+        assert_eq!(balance(&mut db, BRIDGE_ADDRESS), U256::ZERO);
+        assert_eq!(token_balance(&mut db, USER), U256::from(1000));
+    }
+
+    #[test]
+    fn releases_eth_once_and_only_for_the_system() {
+        let mut db = chain();
+        logs(by_user(&mut db, 2 * ETHER, deposit(1)));
+        let before = balance(&mut db, USER);
+
+        let result = by_user(&mut db, 0, release_eth(7, false));
+        assert!(reverted(&result));
+
+        let logs = logs(by_system(&mut db, release_eth(7, false)));
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].topics[0], keccak256(RELEASED_EVENT));
+        assert_eq!(logs[0].topics[1], B256::from(word(3)));
+        assert_eq!(logs[0].topics[2], B256::from(word(7)));
+        assert_eq!(balance(&mut db, USER), before + U256::from(ETHER));
+        assert_eq!(balance(&mut db, BRIDGE_ADDRESS), U256::from(ETHER));
+        let used =
+            |db: &mut StateDb, nonce| db.storage(BRIDGE_ADDRESS, used_slot(3, nonce)).unwrap();
+        assert_eq!(used(&mut db, 7), U256::from(1));
+        assert_eq!(used(&mut db, 8), U256::ZERO);
+
+        // Replays of the same (sourceChain, nonce), and mints of native ETH.
+        for data in [release_eth(7, false), release_eth(8, true)] {
+            let result = by_system(&mut db, data);
+            assert!(reverted(&result));
+        }
+        assert_eq!(balance(&mut db, BRIDGE_ADDRESS), U256::from(ETHER));
+    }
+
+    #[test]
+    fn releases_transfer_or_mint_tokens() {
+        let mut db = chain();
+        logs(by_user(&mut db, 0, deposit_token(token_word(), 400)));
+
+        logs(by_system(&mut db, release(1, TOKEN, 150, false)));
+        assert_eq!(token_balance(&mut db, BRIDGE_ADDRESS), U256::from(250));
+        assert_eq!(token_balance(&mut db, USER), U256::from(750));
+
+        logs(by_system(&mut db, release(2, TOKEN, 50, true)));
+        assert_eq!(token_balance(&mut db, BRIDGE_ADDRESS), U256::from(250));
+        assert_eq!(token_balance(&mut db, USER), U256::from(800));
+
+        let result = by_system(&mut db, release(2, TOKEN, 50, true));
+        assert!(reverted(&result));
+        let result = by_user(&mut db, 0, release(3, TOKEN, 50, true));
+        assert!(reverted(&result));
+        assert_eq!(token_balance(&mut db, USER), U256::from(800));
+    }
+}
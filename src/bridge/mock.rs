@@ -0,0 +1,93 @@
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+/// A request the mock endpoint received.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub raw: Vec<u8>,
+    /// `raw` parsed as JSON, `Null` if it is not.
+    pub body: Value,
+}
+
+/// A local HTTP endpoint standing in for a destination chain's node in
+/// adapter tests. Every request is answered with the status and JSON body
+/// `respond` returns for it; requests are kept for inspection.
+pub struct MockEndpoint {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockEndpoint {
+    pub async fn start<F>(respond: F) -> Self
+    where
+        F: Fn(&Request) -> (u16, Value) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let respond = Arc::new(respond);
+        let log = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (respond, log) = (respond.clone(), log.clone());
+                tokio::spawn(async move {
+                    let (read, mut write) = stream.into_split();
+                    let mut read = BufReader::new(read);
+                    // Keep-alive: serve requests until the client hangs up.
+                    while let Some(request) = read_request(&mut read).await {
+                        let (status, body) = respond(&request);
+                        log.lock().unwrap().push(request);
+                        let body = body.to_string();
+                        let response = format!(
+                            "HTTP/1.1 {status} Mock\r\ncontent-type: application/json\r\n\
+                             content-length: {}\r\n\r\n{body}",
+                            body.len()
+                        );
+                        if write.write_all(response.as_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        Self { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn read_request<R: AsyncBufReadExt + Unpin>(read: &mut R) -> Option<Request> {
+    let mut line = String::new();
+    read.read_line(&mut line).await.ok().filter(|n| *n > 0)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+    let mut length = 0;
+    loop {
+        line.clear();
+        read.read_line(&mut line).await.ok()?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok()?;
+            }
+        }
+    }
+    let mut raw = vec![0; length];
+    read.read_exact(&mut raw).await.ok()?;
+    Some(Request {
+        method,
+        path,
+        body: serde_json::from_slice(&raw).unwrap_or(Value::Null),
+        raw,
+    })
+}
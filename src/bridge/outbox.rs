@@ -0,0 +1,333 @@
+use super::BridgeMessage;
+use crate::db::ChainStore;
+use anyhow::Result;
+use rand::Rng;
+use revm::primitives::keccak256;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Key prefixes of the outbox column family, one per kind of entry.
+const QUEUED: &[u8] = b"q/";
+const DEAD: &[u8] = b"d/";
+const DELIVERED: &[u8] = b"x/";
+
+/// How often a submitted message's status is polled.
+pub const CONFIRM_POLL: Duration = Duration::from_secs(2);
+/// Submitted messages not confirmed within this time are submitted again.
+pub const CONFIRM_TIMEOUT: Duration = Duration::from_secs(120);
+/// Confirmed messages not final on the destination within this time, e.g.
+/// because a fork dropped them, are submitted again.
+pub const FINALITY_TIMEOUT: Duration = Duration::from_secs(600);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeliveryState {
+    /// Waiting for the source block to become final under the
+    /// destination's policy, i.e. for the certified head to reach `final_at`.
+    AwaitingFinality { final_at: u64 },
+    /// Waiting for its next submission attempt.
+    Queued,
+    /// Accepted by the destination endpoint, waiting for confirmation.
+    Submitted { tx_id: String, at_ms: u64 },
+    /// Executed on the destination chain, waiting for it to be final there.
+    Delivered { tx_id: String, at_ms: u64 },
+    /// Final on the destination chain; pruned once the retention period
+    /// has passed.
+    Finalized { tx_id: String, at_ms: u64 },
+    /// Retries exhausted; kept until retried over RPC.
+    DeadLetter,
+}
+
+/// One message on its way to one destination chain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub adapter: String,
+    /// Idempotency key, the same on every attempt, so a destination that
+    /// saw an earlier attempt can drop the duplicate.
+    pub key: String,
+    pub message: BridgeMessage,
+    pub state: DeliveryState,
+    /// Failed attempts so far.
+    pub attempts: u32,
+    /// Unix time (ms) of the next submission or status poll.
+    pub next_attempt_ms: u64,
+    pub last_error: Option<String>,
+}
+
+impl OutboxEntry {
+    pub(super) fn store_key(&self) -> Vec<u8> {
+        let prefix = match self.state {
+            DeliveryState::AwaitingFinality { .. }
+            | DeliveryState::Queued
+            | DeliveryState::Submitted { .. }
+            | DeliveryState::Delivered { .. } => QUEUED,
+            DeliveryState::Finalized { .. } => DELIVERED,
+            DeliveryState::DeadLetter => DEAD,
+        };
+        entry_key(prefix, &self.adapter, &self.key)
+    }
+}
+
+fn entry_key(prefix: &[u8], adapter: &str, key: &str) -> Vec<u8> {
+    [prefix, adapter.as_bytes(), b"/", key.as_bytes()].concat()
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Idempotency key of a message: the same for every destination and every
+/// attempt. Messages are keyed by what they attest to, not by who signed
+/// them, so relays of the same block or deposit from different nodes share
+/// a key.
+pub fn message_key(msg: &BridgeMessage) -> String {
+    let bytes = match msg {
+        BridgeMessage::Attestation(signed) => bincode::serialize(&signed.attestation),
+        BridgeMessage::Transfer(transfer) => bincode::serialize(&transfer.deposit),
+    }
+    .expect("bridge message serializes");
+    hex::encode(keccak256(bytes))
+}
+
+/// Delivery state of bridge messages, persisted in `ChainStore` so it
+/// survives restarts.
+pub struct Outbox {
+    store: Arc<ChainStore>,
+    max_attempts: u32,
+    max_backoff: Duration,
+    retention: Duration,
+}
+
+impl Outbox {
+    pub fn new(
+        store: Arc<ChainStore>,
+        max_attempts: u32,
+        max_backoff: Duration,
+        retention: Duration,
+    ) -> Self {
+        Self {
+            store,
+            max_attempts,
+            max_backoff,
+            retention,
+        }
+    }
+
+    fn load(&self, key: &[u8]) -> Result<Option<OutboxEntry>> {
+        self.store
+            .get_outbox(key)?
+            .map(|bytes| Ok(bincode::deserialize(&bytes)?))
+            .transpose()
+    }
+
+    fn load_prefix(&self, prefix: &[u8]) -> Result<Vec<OutboxEntry>> {
+        self.store
+            .outbox_with_prefix(prefix)?
+            .into_iter()
+            .map(|(_, bytes)| Ok(bincode::deserialize(&bytes)?))
+            .collect()
+    }
+
+    /// Queue `msg` for `adapter`, held back until the certified head reaches
+    /// `final_at` if given, unless it was queued, delivered or dead-lettered
+    /// before.
+    pub fn enqueue(&self, adapter: &str, msg: &BridgeMessage, final_at: Option<u64>) -> Result<()> {
+        let key = message_key(msg);
+        for prefix in [QUEUED, DELIVERED, DEAD] {
+            if self
+                .store
+                .get_outbox(&entry_key(prefix, adapter, &key))?
+                .is_some()
+            {
+                return Ok(());
+            }
+        }
+        let entry = OutboxEntry {
+            adapter: adapter.to_string(),
+            key,
+            message: msg.clone(),
+            state: final_at.map_or(DeliveryState::Queued, |final_at| {
+                DeliveryState::AwaitingFinality { final_at }
+            }),
+            attempts: 0,
+            next_attempt_ms: now_ms(),
+            last_error: None,
+        };
+        self.store
+            .put_outbox(&entry.store_key(), None, &bincode::serialize(&entry)?)
+    }
+
+    /// Entries of `adapter` still in flight whose next step is due.
+    pub fn due(&self, adapter: &str) -> Result<Vec<OutboxEntry>> {
+        let now = now_ms();
+        let prefix = entry_key(QUEUED, adapter, "");
+        Ok(self
+            .load_prefix(&prefix)?
+            .into_iter()
+            .filter(|entry| entry.next_attempt_ms <= now)
+            .collect())
+    }
+
+    /// Persist a state change, moving the entry between prefixes if needed.
+    pub fn update(&self, old_key: &[u8], entry: &OutboxEntry) -> Result<()> {
+        self.store
+            .put_outbox(&entry.store_key(), Some(old_key), &bincode::serialize(entry)?)
+    }
+
+    /// Count a failed attempt: back off, or give up after `max_attempts`,
+    /// or right away if the failure is `permanent`.
+    pub fn fail(
+        &self,
+        mut entry: OutboxEntry,
+        error: String,
+        permanent: bool,
+    ) -> Result<OutboxEntry> {
+        let old_key = entry.store_key();
+        entry.attempts += 1;
+        entry.last_error = Some(error);
+        if permanent || entry.attempts >= self.max_attempts {
+            entry.state = DeliveryState::DeadLetter;
+        } else {
+            entry.state = DeliveryState::Queued;
+            entry.next_attempt_ms = now_ms() + self.backoff(entry.attempts).as_millis() as u64;
+        }
+        self.update(&old_key, &entry)?;
+        Ok(entry)
+    }
+
+    /// Exponential backoff with full jitter over the upper half.
+    fn backoff(&self, attempts: u32) -> Duration {
+        let exp = INITIAL_BACKOFF.saturating_mul(1 << attempts.min(20));
+        let cap = exp.min(self.max_backoff);
+        cap / 2 + cap.mul_f64(rand::thread_rng().gen_range(0.0..0.5))
+    }
+
+    pub fn get(&self, adapter: &str, key: &str) -> Result<Option<OutboxEntry>> {
+        for prefix in [QUEUED, DELIVERED, DEAD] {
+            if let Some(entry) = self.load(&entry_key(prefix, adapter, key))? {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn dead_letters(&self) -> Result<Vec<OutboxEntry>> {
+        self.load_prefix(DEAD)
+    }
+
+    /// Put a dead letter back in the queue with a fresh attempt budget.
+    pub fn retry_dead_letter(&self, adapter: &str, key: &str) -> Result<bool> {
+        let old_key = entry_key(DEAD, adapter, key);
+        let Some(mut entry) = self.load(&old_key)? else {
+            return Ok(false);
+        };
+        entry.state = DeliveryState::Queued;
+        entry.attempts = 0;
+        entry.next_attempt_ms = now_ms();
+        self.update(&old_key, &entry)?;
+        Ok(true)
+    }
+
+    /// Delete finalized entries older than the retention period; returns
+    /// how many were deleted. A pruned key no longer stops `enqueue` of the
+    /// same message, but messages are enqueued when their block is
+    /// attested, well within the retention period.
+    pub fn prune(&self) -> Result<usize> {
+        let cutoff = now_ms().saturating_sub(self.retention.as_millis() as u64);
+        let mut expired = Vec::new();
+        for (key, bytes) in self.store.outbox_with_prefix(DELIVERED)? {
+            let entry: OutboxEntry = bincode::deserialize(&bytes)?;
+            if let DeliveryState::Finalized { at_ms, .. } = entry.state {
+                if at_ms < cutoff {
+                    expired.push(key);
+                }
+            }
+        }
+        self.store.delete_outbox(&expired)?;
+        Ok(expired.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::open_store;
+    use crate::types::{BlockAttestation, SignedAttestation};
+    use revm::primitives::B256;
+
+    fn attestation(number: u64) -> BridgeMessage {
+        BridgeMessage::Attestation(SignedAttestation {
+            attestation: BlockAttestation {
+                chain_id: 1337,
+                number,
+                block_hash: B256::with_last_byte(number as u8),
+                state_root: B256::ZERO,
+                receipts_root: B256::ZERO,
+            },
+            signatures: vec![],
+        })
+    }
+
+    #[test]
+    fn prunes_finalized_entries_past_retention() {
+        let (_dir, store) = open_store("outbox");
+        let outbox = Outbox::new(store, 3, Duration::from_secs(60), Duration::from_secs(3600));
+        for number in 1..=3 {
+            outbox.enqueue("sui", &attestation(number), None).unwrap();
+        }
+        let finalized_at = [now_ms() - 7_200_000, now_ms(), now_ms() - 7_200_000];
+        for (mut entry, at_ms) in outbox.due("sui").unwrap().into_iter().zip(finalized_at) {
+            let old_key = entry.store_key();
+            entry.state = DeliveryState::Finalized {
+                tx_id: entry.key.clone(),
+                at_ms,
+            };
+            outbox.update(&old_key, &entry).unwrap();
+        }
+
+        assert_eq!(outbox.prune().unwrap(), 2);
+        assert_eq!(outbox.prune().unwrap(), 0);
+        let kept: Vec<_> = (1..=3)
+            .filter_map(|n| outbox.get("sui", &message_key(&attestation(n))).unwrap())
+            .collect();
+        assert_eq!(kept.len(), 1);
+        assert!(matches!(
+            kept[0].state,
+            DeliveryState::Finalized { at_ms, .. } if at_ms > now_ms() - 60_000
+        ));
+    }
+
+    #[test]
+    fn dead_letters_are_kept_and_retried() {
+        let (_dir, store) = open_store("outbox");
+        let outbox = Outbox::new(store, 1, Duration::from_secs(60), Duration::ZERO);
+        outbox.enqueue("aptos", &attestation(1), None).unwrap();
+        let entry = outbox.due("aptos").unwrap().remove(0);
+        let entry = outbox.fail(entry, "rejected".to_string(), false).unwrap();
+        assert_eq!(entry.state, DeliveryState::DeadLetter);
+
+        outbox.prune().unwrap();
+        assert_eq!(outbox.dead_letters().unwrap().len(), 1);
+        assert!(outbox.retry_dead_letter("aptos", &entry.key).unwrap());
+        assert!(outbox.dead_letters().unwrap().is_empty());
+        let requeued = outbox.get("aptos", &entry.key).unwrap().unwrap();
+        assert_eq!(requeued.state, DeliveryState::Queued);
+        assert_eq!(requeued.attempts, 0);
+    }
+
+    #[test]
+    fn permanent_failures_skip_the_retries() {
+        let (_dir, store) = open_store("outbox");
+        let outbox = Outbox::new(store, 5, Duration::from_secs(60), Duration::ZERO);
+        outbox.enqueue("sui", &attestation(1), None).unwrap();
+        let entry = outbox.due("sui").unwrap().remove(0);
+        let entry = outbox.fail(entry, "timeout".to_string(), false).unwrap();
+        assert_eq!(entry.state, DeliveryState::Queued);
+        let entry = outbox.fail(entry, "too large".to_string(), true).unwrap();
+        assert_eq!(entry.state, DeliveryState::DeadLetter);
+        assert_eq!(outbox.dead_letters().unwrap().len(), 1);
+    }
+}
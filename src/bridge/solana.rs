@@ -0,0 +1,342 @@
+use super::{json_rpc, BridgeMessage, ChainAdapter, DeliveryStatus, Undeliverable};
+use crate::config::SolanaConfig;
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use ed25519_dalek::{Signer, SigningKey};
+use reqwest::Client;
+use serde_json::{json, Value};
+
+/// Largest serialized transaction a Solana node accepts.
+const PACKET_DATA_SIZE: usize = 1232;
+
+/// Sends bridge messages as transactions calling the bridge program, paid
+/// for and signed by a relayer keypair. Only runs with `trusted_relayer`:
+/// see `instruction_data`.
+pub struct SolanaAdapter {
+    name: String,
+    cfg: SolanaConfig,
+    client: Client,
+    payer: SigningKey,
+    program_id: [u8; 32],
+    bridge_account: [u8; 32],
+}
+
+impl SolanaAdapter {
+    pub fn new(name: String, cfg: SolanaConfig, client: Client) -> Result<Self> {
+        if !cfg.trusted_relayer {
+            bail!(
+                "Solana bridge {name} cannot carry validator signatures; set \
+                 `trusted_relayer` to have the program trust the relayer key instead"
+            );
+        }
+        let path = &cfg.keypair_path;
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("reading Solana keypair {path}"))?;
+        // The Solana CLI format: a JSON array of the 32 secret bytes followed
+        // by the 32 public key bytes.
+        let bytes: Vec<u8> =
+            serde_json::from_str(&raw).with_context(|| format!("parsing Solana keypair {path}"))?;
+        let bytes: [u8; 64] = bytes
+            .try_into()
+            .map_err(|_| anyhow!("Solana keypair {path} is not 64 bytes"))?;
+        let payer = SigningKey::from_keypair_bytes(&bytes)
+            .map_err(|e| anyhow!("Solana keypair {path}: {e}"))?;
+        Ok(Self {
+            program_id: pubkey(&cfg.program_id)?,
+            bridge_account: pubkey(&cfg.bridge_account)?,
+            name,
+            cfg,
+            client,
+            payer,
+        })
+    }
+
+    async fn latest_blockhash(&self) -> Result<[u8; 32]> {
+        let result = json_rpc(
+            &self.client,
+            &self.cfg.rpc_url,
+            "getLatestBlockhash",
+            json!([{ "commitment": "confirmed" }]),
+        )
+        .await?;
+        let blockhash = result["value"]["blockhash"]
+            .as_str()
+            .ok_or_else(|| anyhow!("getLatestBlockhash returned no blockhash"))?;
+        pubkey(blockhash)
+    }
+
+    /// Legacy transaction with a single instruction to the bridge program,
+    /// passing the payer and the bridge's state account.
+    fn transaction(&self, blockhash: [u8; 32], data: &[u8]) -> Vec<u8> {
+        let payer = self.payer.verifying_key().to_bytes();
+
+        // Header: one signature (the payer), no read-only signers, and the
+        // program as the only read-only account.
+        let mut message = vec![1, 0, 1];
+        compact_len(&mut message, 3);
+        for key in [&payer, &self.bridge_account, &self.program_id] {
+            message.extend_from_slice(key);
+        }
+        message.extend_from_slice(&blockhash);
+
+        compact_len(&mut message, 1);
+        message.push(2);
+        compact_len(&mut message, 2);
+        message.extend_from_slice(&[0, 1]);
+        compact_len(&mut message, data.len());
+        message.extend_from_slice(data);
+
+        let mut tx = Vec::with_capacity(1 + 64 + message.len());
+        compact_len(&mut tx, 1);
+        tx.extend_from_slice(&self.payer.sign(&message).to_bytes());
+        tx.extend_from_slice(&message);
+        tx
+    }
+}
+
+/// Decode a base58 public key or hash.
+fn pubkey(base58: &str) -> Result<[u8; 32]> {
+    bs58::decode(base58)
+        .into_vec()
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| anyhow!("{base58:?} is not a base58 public key"))
+}
+
+/// Solana's "compact-u16" length prefix: 7 bits per byte, low bits first.
+fn compact_len(out: &mut Vec<u8>, mut len: usize) {
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Instruction data: the 32-byte idempotency key, which the program records
+/// to drop duplicates, then the message without validator signatures. At
+/// 2420 bytes and up an ML-DSA signature does not fit in a Solana
+/// transaction, so the program authorizes the relayer's key instead. This
+/// is a trust downgrade from destinations checking the signatures
+/// themselves, and `trusted_relayer` has to opt into it.
+fn instruction_data(key: &str, msg: &BridgeMessage) -> Result<Vec<u8>> {
+    let mut data = hex::decode(key)?;
+    data.extend_from_slice(&msg.encode(false));
+    Ok(data)
+}
+
+#[async_trait]
+impl ChainAdapter for SolanaAdapter {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn submit(&self, key: &str, msg: &BridgeMessage) -> Result<String> {
+        let data = instruction_data(key, msg)?;
+        let tx = self.transaction(self.latest_blockhash().await?, &data);
+        if tx.len() > PACKET_DATA_SIZE {
+            return Err(Undeliverable(format!(
+                "transaction of {} bytes exceeds Solana's {PACKET_DATA_SIZE}",
+                tx.len()
+            ))
+            .into());
+        }
+        let result = json_rpc(
+            &self.client,
+            &self.cfg.rpc_url,
+            "sendTransaction",
+            json!([
+                BASE64.encode(&tx),
+                { "encoding": "base64", "preflightCommitment": "confirmed" }
+            ]),
+        )
+        .await?;
+        result
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("sendTransaction returned {result} instead of a signature"))
+    }
+
+    async fn status(&self, tx_id: &str) -> Result<DeliveryStatus> {
+        let result = json_rpc(
+            &self.client,
+            &self.cfg.rpc_url,
+            "getSignatureStatuses",
+            json!([[tx_id], { "searchTransactionHistory": true }]),
+        )
+        .await?;
+        let status = &result["value"][0];
+        if status.is_null() {
+            return Ok(DeliveryStatus::Unknown);
+        }
+        if !status["err"].is_null() {
+            return Ok(DeliveryStatus::Failed(status["err"].to_string()));
+        }
+        Ok(match status["confirmationStatus"].as_str() {
+            Some("confirmed") => DeliveryStatus::Confirmed,
+            Some("finalized") => DeliveryStatus::Finalized,
+            _ => DeliveryStatus::Pending,
+        })
+    }
+
+    async fn health(&self) -> Result<()> {
+        match json_rpc(&self.client, &self.cfg.rpc_url, "getHealth", json!([])).await? {
+            Value::String(s) if s == "ok" => Ok(()),
+            other => Err(anyhow!("unhealthy: {other}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bridge::mock::MockEndpoint;
+    use crate::testing::TempPath;
+    use crate::types::{BlockAttestation, SignedAttestation};
+    use ed25519_dalek::{Signature, Verifier};
+    use revm::primitives::B256;
+
+    const BLOCKHASH: [u8; 32] = [9; 32];
+
+    /// The config and its keypair file, which goes with the returned guard.
+    fn config(rpc_url: &str, trusted_relayer: bool) -> (TempPath, SolanaConfig) {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let path = TempPath::new("solana.json");
+        let keypair = serde_json::to_string(&key.to_keypair_bytes().to_vec()).unwrap();
+        std::fs::write(path.path(), keypair).unwrap();
+        let cfg = SolanaConfig {
+            rpc_url: rpc_url.to_string(),
+            program_id: bs58::encode([1; 32]).into_string(),
+            bridge_account: bs58::encode([2; 32]).into_string(),
+            keypair_path: path.to_str().to_string(),
+            trusted_relayer,
+        };
+        (path, cfg)
+    }
+
+    fn adapter(rpc_url: &str) -> SolanaAdapter {
+        let client = Client::builder().no_proxy().build().unwrap();
+        let (_key, cfg) = config(rpc_url, true);
+        SolanaAdapter::new("solana".to_string(), cfg, client).unwrap()
+    }
+
+    fn message() -> BridgeMessage {
+        BridgeMessage::Attestation(SignedAttestation {
+            attestation: BlockAttestation {
+                chain_id: 1337,
+                number: 5,
+                block_hash: B256::repeat_byte(5),
+                state_root: B256::repeat_byte(6),
+                receipts_root: B256::repeat_byte(7),
+            },
+            signatures: vec![("validator-0".to_string(), vec![0xee; 2420])],
+        })
+    }
+
+    #[test]
+    fn compact_lengths() {
+        for (len, bytes) in [
+            (0, vec![0]),
+            (127, vec![0x7f]),
+            (128, vec![0x80, 0x01]),
+            (300, vec![0xac, 0x02]),
+        ] {
+            let mut out = Vec::new();
+            compact_len(&mut out, len);
+            assert_eq!(out, bytes, "{len}");
+        }
+    }
+
+    #[test]
+    fn builds_signed_legacy_transactions() {
+        let adapter = adapter("http://127.0.0.1:1");
+        let payer = adapter.payer.verifying_key();
+        let tx = adapter.transaction(BLOCKHASH, &[0xaa; 3]);
+
+        let mut message = vec![1, 0, 1, 3];
+        message.extend_from_slice(payer.as_bytes());
+        message.extend_from_slice(&[2; 32]);
+        message.extend_from_slice(&[1; 32]);
+        message.extend_from_slice(&BLOCKHASH);
+        // One instruction: program index 2, accounts [0, 1], 3 data bytes.
+        message.extend_from_slice(&[1, 2, 2, 0, 1, 3, 0xaa, 0xaa, 0xaa]);
+        assert_eq!(tx.len(), 1 + 64 + message.len());
+        assert_eq!(tx[0], 1);
+        assert_eq!(&tx[65..], message.as_slice());
+        let signature = Signature::from_slice(&tx[1..65]).unwrap();
+        payer.verify(&message, &signature).unwrap();
+    }
+
+    #[test]
+    fn needs_an_opt_in_to_trust_the_relayer() {
+        let (_key, cfg) = config("http://127.0.0.1:1", false);
+        let err = SolanaAdapter::new("solana".to_string(), cfg, Client::new())
+            .err()
+            .expect("refused");
+        assert!(err.to_string().contains("trusted_relayer"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn submits_and_tracks_transactions() {
+        let node = MockEndpoint::start(|request| {
+            let params = &request.body["params"];
+            let result = match request.body["method"].as_str().unwrap() {
+                "getLatestBlockhash" => json!({
+                    "context": { "slot": 1 },
+                    "value": {
+                        "blockhash": bs58::encode(BLOCKHASH).into_string(),
+                        "lastValidBlockHeight": 100,
+                    },
+                }),
+                "sendTransaction" => json!("5sig"),
+                "getSignatureStatuses" => {
+                    let status = match params[0][0].as_str().unwrap() {
+                        "unknown" => Value::Null,
+                        "processed" => json!({ "err": null, "confirmationStatus": "processed" }),
+                        "confirmed" => json!({ "err": null, "confirmationStatus": "confirmed" }),
+                        "finalized" => json!({ "err": null, "confirmationStatus": "finalized" }),
+                        _ => json!({ "err": { "InstructionError": [0, { "Custom": 1 }] } }),
+                    };
+                    json!({ "context": { "slot": 1 }, "value": [status] })
+                }
+                "getHealth" => json!("ok"),
+                method => return (200, json!({ "error": format!("no {method}") })),
+            };
+            (200, json!({ "jsonrpc": "2.0", "id": 1, "result": result }))
+        })
+        .await;
+        let adapter = adapter(&node.url);
+        let key = hex::encode([3; 32]);
+        let msg = message();
+
+        assert_eq!(adapter.submit(&key, &msg).await.unwrap(), "5sig");
+        let requests = node.requests();
+        let sent = &requests[1].body["params"];
+        assert_eq!(sent[1]["encoding"], "base64");
+        let tx = BASE64.decode(sent[0].as_str().unwrap()).unwrap();
+        let data = instruction_data(&key, &msg).unwrap();
+        assert_eq!(&tx[65 + 4 + 3 * 32..65 + 4 + 4 * 32], BLOCKHASH.as_slice());
+        assert!(tx.ends_with(&data));
+        // No validator signature travels; the key leads the data.
+        assert_eq!(&data[..32], &[3; 32]);
+        assert_eq!(&data[32..], msg.encode(false).as_slice());
+
+        for (tx_id, status) in [
+            ("unknown", DeliveryStatus::Unknown),
+            ("processed", DeliveryStatus::Pending),
+            ("confirmed", DeliveryStatus::Confirmed),
+            ("finalized", DeliveryStatus::Finalized),
+        ] {
+            assert_eq!(adapter.status(tx_id).await.unwrap(), status, "{tx_id}");
+        }
+        assert!(matches!(
+            adapter.status("failed").await.unwrap(),
+            DeliveryStatus::Failed(_)
+        ));
+        adapter.health().await.unwrap();
+    }
+}
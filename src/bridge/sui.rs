@@ -0,0 +1,548 @@
+use super::bcs::{address, bytes, number, uleb128};
+use super::{json_rpc, BridgeMessage, ChainAdapter, DeliveryStatus, RpcError, Undeliverable};
+use crate::config::SuiConfig;
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use blake2::{digest::consts::U32, Blake2b, Digest};
+use ed25519_dalek::{Signer, SigningKey};
+use reqwest::Client;
+use serde_json::{json, Value};
+use tracing::info;
+
+type Blake2b256 = Blake2b<U32>;
+
+/// Signature scheme flag of Ed25519 keys and signatures.
+const ED25519_FLAG: u8 = 0x00;
+/// Intent prefix of transaction data: scope, version and app id, all 0.
+const TRANSACTION_INTENT: [u8; 3] = [0, 0, 0];
+/// Largest pure argument a transaction may carry.
+const MAX_PURE_ARGUMENT_SIZE: usize = 16 * 1024;
+/// Largest transaction a fullnode accepts.
+const MAX_TX_SIZE: usize = 128 * 1024;
+/// Start of the error a fullnode answers with for a digest it does not know.
+const NOT_FOUND: &str = "Could not find the referenced transaction";
+
+/// A `(id, version, digest)` object reference.
+type ObjectRef = ([u8; 32], u64, [u8; 32]);
+
+/// Sends bridge messages as programmable transactions that call
+/// `{package}::{module}::{function}(bridge, key, message, signatures)`,
+/// where `bridge` is the shared bridge object, `key` and `message` (without
+/// signatures) are `vector<u8>` and `signatures` is a `vector<vector<u8>>`
+/// of the signature entries of `BridgeMessage::encode`. Each entry is a
+/// pure argument of its own, gathered by a `MakeMoveVec` command, as a
+/// quorum of ML-DSA signatures overflows a single one.
+pub struct SuiAdapter {
+    name: String,
+    cfg: SuiConfig,
+    client: Client,
+    signer: SigningKey,
+    sender: [u8; 32],
+    package: [u8; 32],
+    bridge_object: [u8; 32],
+}
+
+impl SuiAdapter {
+    pub fn new(name: String, cfg: SuiConfig, client: Client) -> Result<Self> {
+        let path = &cfg.keypair_path;
+        let raw =
+            std::fs::read_to_string(path).with_context(|| format!("reading Sui key {path}"))?;
+        // A `sui.keystore` entry: base64 of the scheme flag and the secret.
+        let bytes = BASE64
+            .decode(raw.trim())
+            .with_context(|| format!("decoding Sui key {path}"))?;
+        let secret: [u8; 32] = match bytes.split_first() {
+            Some((&ED25519_FLAG, secret)) => secret
+                .try_into()
+                .map_err(|_| anyhow!("Sui key {path} is not 32 bytes"))?,
+            _ => bail!("Sui key {path} is not an Ed25519 key"),
+        };
+        let signer = SigningKey::from_bytes(&secret);
+
+        let mut hasher = Blake2b256::new();
+        hasher.update([ED25519_FLAG]);
+        hasher.update(signer.verifying_key().as_bytes());
+        let sender = hasher.finalize().into();
+
+        Ok(Self {
+            package: address(&cfg.package_id)?,
+            bridge_object: address(&cfg.bridge_object)?,
+            name,
+            cfg,
+            client,
+            signer,
+            sender,
+        })
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value> {
+        json_rpc(&self.client, &self.cfg.rpc_url, method, params).await
+    }
+
+    /// The sender's largest SUI coin, if it covers the gas budget.
+    async fn gas_coin(&self) -> Result<ObjectRef> {
+        let result = self
+            .call(
+                "suix_getCoins",
+                json!([hex0x(self.sender), "0x2::sui::SUI", null, null]),
+            )
+            .await?;
+        let coins = result["data"].as_array().cloned().unwrap_or_default();
+        let balance = |coin: &Value| number(&coin["balance"]).unwrap_or(0);
+        let coin = coins
+            .iter()
+            .max_by_key(|coin| balance(coin))
+            .filter(|coin| balance(coin) >= self.cfg.gas_budget)
+            .ok_or_else(|| {
+                anyhow!(
+                    "no SUI coin of 0x{} covers the gas budget of {}",
+                    hex::encode(self.sender),
+                    self.cfg.gas_budget
+                )
+            })?;
+        let id = address(coin["coinObjectId"].as_str().unwrap_or_default())?;
+        let version = number(&coin["version"]).ok_or_else(|| anyhow!("coin has no version"))?;
+        let digest = bs58::decode(coin["digest"].as_str().unwrap_or_default())
+            .into_vec()
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| anyhow!("coin has no digest"))?;
+        Ok((id, version, digest))
+    }
+
+    /// Version at which the bridge object became shared.
+    async fn initial_shared_version(&self) -> Result<u64> {
+        let result = self
+            .call(
+                "sui_getObject",
+                json!([hex0x(self.bridge_object), { "showOwner": true }]),
+            )
+            .await?;
+        number(&result["data"]["owner"]["Shared"]["initial_shared_version"])
+            .ok_or_else(|| anyhow!("bridge object {} is not shared", self.cfg.bridge_object))
+    }
+
+    /// BCS of `TransactionData::V1` with a programmable transaction over
+    /// `pure`, the BCS of the key, the message and each signature entry.
+    fn transaction_data(
+        &self,
+        shared_version: u64,
+        gas: ObjectRef,
+        gas_price: u64,
+        pure: &[Vec<u8>],
+    ) -> Vec<u8> {
+        let mut out = Vec::new();
+        // TransactionData::V1, TransactionKind::ProgrammableTransaction
+        uleb128(&mut out, 0);
+        uleb128(&mut out, 0);
+
+        // inputs: the shared bridge object (mutable), then the pure args
+        uleb128(&mut out, 1 + pure.len() as u64);
+        uleb128(&mut out, 1); // CallArg::Object
+        uleb128(&mut out, 1); // ObjectArg::SharedObject
+        out.extend_from_slice(&self.bridge_object);
+        out.extend_from_slice(&shared_version.to_le_bytes());
+        out.push(1);
+        for arg in pure {
+            uleb128(&mut out, 0); // CallArg::Pure
+            bytes(&mut out, arg);
+        }
+
+        uleb128(&mut out, 2);
+        // MakeMoveVec of `vector<u8>` over the signature inputs, from 3 on
+        uleb128(&mut out, 5);
+        out.extend_from_slice(&[1, 6, 1]); // Some(TypeTag::Vector(U8))
+        uleb128(&mut out, pure.len() as u64 - 2);
+        for input in 3..=pure.len() as u16 {
+            argument(&mut out, 1, input); // Argument::Input
+        }
+        // MoveCall on the bridge object, key, message and that vector
+        uleb128(&mut out, 0);
+        out.extend_from_slice(&self.package);
+        bytes(&mut out, self.cfg.module.as_bytes());
+        bytes(&mut out, self.cfg.function.as_bytes());
+        uleb128(&mut out, 0); // no type arguments
+        uleb128(&mut out, 4);
+        for input in 0..3 {
+            argument(&mut out, 1, input);
+        }
+        argument(&mut out, 2, 0); // Argument::Result
+
+        out.extend_from_slice(&self.sender);
+
+        // gas data: payment, owner, price, budget
+        let (id, version, digest) = gas;
+        uleb128(&mut out, 1);
+        out.extend_from_slice(&id);
+        out.extend_from_slice(&version.to_le_bytes());
+        bytes(&mut out, &digest);
+        out.extend_from_slice(&self.sender);
+        out.extend_from_slice(&gas_price.to_le_bytes());
+        out.extend_from_slice(&self.cfg.gas_budget.to_le_bytes());
+
+        uleb128(&mut out, 0); // TransactionExpiration::None
+        out
+    }
+
+    /// Serialized signature over the intent message of `tx_data`: flag,
+    /// signature and public key.
+    fn sign(&self, tx_data: &[u8]) -> Vec<u8> {
+        let mut hasher = Blake2b256::new();
+        hasher.update(TRANSACTION_INTENT);
+        hasher.update(tx_data);
+        let signature = self.signer.sign(&hasher.finalize());
+
+        let mut out = vec![ED25519_FLAG];
+        out.extend_from_slice(&signature.to_bytes());
+        out.extend_from_slice(self.signer.verifying_key().as_bytes());
+        out
+    }
+}
+
+/// A command argument: its variant and index.
+fn argument(out: &mut Vec<u8>, variant: u64, index: u16) {
+    uleb128(out, variant);
+    out.extend_from_slice(&index.to_le_bytes());
+}
+
+/// BCS of `vector<u8>` arguments, or `Undeliverable` if one of them does
+/// not fit a pure argument.
+fn pure_args<'a>(args: impl IntoIterator<Item = &'a [u8]>) -> Result<Vec<Vec<u8>>> {
+    args.into_iter()
+        .map(|arg| {
+            let mut pure = Vec::with_capacity(arg.len() + 4);
+            bytes(&mut pure, arg);
+            if pure.len() > MAX_PURE_ARGUMENT_SIZE {
+                return Err(Undeliverable(format!(
+                    "argument of {} bytes exceeds Sui's pure argument limit of \
+                     {MAX_PURE_ARGUMENT_SIZE}",
+                    pure.len()
+                ))
+                .into());
+            }
+            Ok(pure)
+        })
+        .collect()
+}
+
+/// Whether `e` is a fullnode's answer for a digest it does not know.
+fn not_found(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<RpcError>()
+        .and_then(|e| e.error["message"].as_str())
+        .is_some_and(|message| message.starts_with(NOT_FOUND))
+}
+
+fn hex0x(bytes: [u8; 32]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+/// `Failed` for a transaction whose effects report a failure. Successful
+/// ones are final once a checkpoint includes them.
+fn effects_status(result: &Value) -> Option<DeliveryStatus> {
+    let status = &result["effects"]["status"];
+    match status["status"].as_str()? {
+        "success" if result["checkpoint"].is_null() => Some(DeliveryStatus::Confirmed),
+        "success" => Some(DeliveryStatus::Finalized),
+        _ => Some(DeliveryStatus::Failed(status["error"].to_string())),
+    }
+}
+
+#[async_trait]
+impl ChainAdapter for SuiAdapter {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn submit(&self, key: &str, msg: &BridgeMessage) -> Result<String> {
+        let key = hex::decode(key)?;
+        let message = msg.encode(false);
+        let signatures = msg.signature_entries();
+        let pure = pure_args(
+            [key.as_slice(), message.as_slice()]
+                .into_iter()
+                .chain(signatures.iter().map(Vec::as_slice)),
+        )?;
+
+        let gas = self.gas_coin().await?;
+        let gas_price = number(&self.call("suix_getReferenceGasPrice", json!([])).await?)
+            .ok_or_else(|| anyhow!("suix_getReferenceGasPrice returned no price"))?;
+        let shared_version = self.initial_shared_version().await?;
+        let tx_data = self.transaction_data(shared_version, gas, gas_price, &pure);
+        if tx_data.len() > MAX_TX_SIZE {
+            return Err(Undeliverable(format!(
+                "transaction of {} bytes exceeds Sui's {MAX_TX_SIZE}",
+                tx_data.len()
+            ))
+            .into());
+        }
+
+        let result = self
+            .call(
+                "sui_executeTransactionBlock",
+                json!([
+                    BASE64.encode(&tx_data),
+                    [BASE64.encode(self.sign(&tx_data))],
+                    { "showEffects": true, "showEvents": true },
+                    "WaitForLocalExecution"
+                ]),
+            )
+            .await?;
+        let digest = result["digest"]
+            .as_str()
+            .ok_or_else(|| anyhow!("sui_executeTransactionBlock returned no digest"))?
+            .to_string();
+        if let Some(DeliveryStatus::Failed(error)) = effects_status(&result) {
+            bail!("transaction {digest} failed: {error}");
+        }
+        Ok(digest)
+    }
+
+    async fn status(&self, tx_id: &str) -> Result<DeliveryStatus> {
+        let result = match self
+            .call(
+                "sui_getTransactionBlock",
+                json!([tx_id, { "showEffects": true, "showEvents": true }]),
+            )
+            .await
+        {
+            Ok(result) => result,
+            // Unknown digests come back as a JSON-RPC error.
+            Err(e) if not_found(&e) => return Ok(DeliveryStatus::Unknown),
+            Err(e) => return Err(e),
+        };
+        let status = effects_status(&result).unwrap_or(DeliveryStatus::Pending);
+        if matches!(
+            status,
+            DeliveryStatus::Confirmed | DeliveryStatus::Finalized
+        ) {
+            let events: Vec<&str> = result["events"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|event| event["type"].as_str())
+                .collect();
+            info!("{}: transaction {tx_id} emitted {events:?}", self.name);
+        }
+        Ok(status)
+    }
+
+    async fn health(&self) -> Result<()> {
+        self.call("sui_getLatestCheckpointSequenceNumber", json!([]))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bridge::mock::MockEndpoint;
+    use crate::testing::TempPath;
+    use crate::types::{BlockAttestation, SignedAttestation};
+    use ed25519_dalek::{Signature, Verifier};
+    use revm::primitives::B256;
+
+    const GAS: ObjectRef = ([3; 32], 4, [5; 32]);
+    /// Size of an ML-DSA-65 signature.
+    const SIGNATURE_SIZE: usize = 3309;
+
+    fn adapter(rpc_url: &str) -> SuiAdapter {
+        // The key is read by `new`, so the file can go when this returns.
+        let key = TempPath::new("sui.key");
+        let secret = [[ED25519_FLAG].as_slice(), &[7; 32]].concat();
+        std::fs::write(key.path(), BASE64.encode(secret)).unwrap();
+        let cfg = SuiConfig {
+            rpc_url: rpc_url.to_string(),
+            package_id: "0x1".to_string(),
+            module: "bridge".to_string(),
+            function: "relay".to_string(),
+            bridge_object: "0x2".to_string(),
+            keypair_path: key.to_str().to_string(),
+            gas_budget: 1_000,
+        };
+        let client = Client::builder().no_proxy().build().unwrap();
+        SuiAdapter::new("sui".to_string(), cfg, client).unwrap()
+    }
+
+    fn message(signers: usize, signature_size: usize) -> BridgeMessage {
+        BridgeMessage::Attestation(SignedAttestation {
+            attestation: BlockAttestation {
+                chain_id: 1337,
+                number: 5,
+                block_hash: B256::repeat_byte(5),
+                state_root: B256::repeat_byte(6),
+                receipts_root: B256::repeat_byte(7),
+            },
+            signatures: (0..signers)
+                .map(|i| (format!("validator-{i}"), vec![0xee; signature_size]))
+                .collect(),
+        })
+    }
+
+    fn fullnode() -> impl Fn(&crate::bridge::mock::Request) -> (u16, Value) {
+        |request| {
+            let params = &request.body["params"];
+            let result = match request.body["method"].as_str().unwrap() {
+                "suix_getCoins" => json!({
+                    "data": [
+                        {
+                            "coinObjectId": hex0x([9; 32]),
+                            "version": "1",
+                            "digest": bs58::encode([9; 32]).into_string(),
+                            "balance": "10",
+                        },
+                        {
+                            "coinObjectId": hex0x(GAS.0),
+                            "version": GAS.1.to_string(),
+                            "digest": bs58::encode(GAS.2).into_string(),
+                            "balance": "5000",
+                        },
+                    ],
+                }),
+                "suix_getReferenceGasPrice" => json!("750"),
+                "sui_getObject" => json!({
+                    "data": { "owner": { "Shared": { "initial_shared_version": 11 } } },
+                }),
+                "sui_executeTransactionBlock" => json!({
+                    "digest": "D1",
+                    "effects": { "status": { "status": "success" } },
+                    "checkpoint": null,
+                }),
+                "sui_getTransactionBlock" => {
+                    let effects = |status: Value| json!({ "effects": { "status": status } });
+                    match params[0].as_str().unwrap() {
+                        "unknown" => {
+                            let message = format!("{NOT_FOUND} [TransactionDigest(unknown)].");
+                            let error = json!({ "code": -32602, "message": message });
+                            return (200, json!({ "jsonrpc": "2.0", "id": 1, "error": error }));
+                        }
+                        "outage" => return (503, Value::Null),
+                        "pending" => json!({}),
+                        "executed" => effects(json!({ "status": "success" })),
+                        "checkpointed" => {
+                            let mut result = effects(json!({ "status": "success" }));
+                            result["checkpoint"] = json!("12");
+                            result
+                        }
+                        _ => effects(json!({ "status": "failure", "error": "MoveAbort" })),
+                    }
+                }
+                "sui_getLatestCheckpointSequenceNumber" => json!("12"),
+                method => {
+                    let error = json!({ "code": -32601, "message": format!("no {method}") });
+                    return (200, json!({ "jsonrpc": "2.0", "id": 1, "error": error }));
+                }
+            };
+            (200, json!({ "jsonrpc": "2.0", "id": 1, "result": result }))
+        }
+    }
+
+    #[test]
+    fn builds_programmable_transactions() {
+        let adapter = adapter("http://127.0.0.1:1");
+        let pure = pure_args([[0xaa].as_slice(), &[0xbb, 0xbb], &[0xcc], &[0xdd]]).unwrap();
+        let tx_data = adapter.transaction_data(11, GAS, 750, &pure);
+
+        let mut expected = vec![0, 0, 5, 1, 1];
+        expected.extend_from_slice(&address("0x2").unwrap());
+        expected.extend_from_slice(&11u64.to_le_bytes());
+        expected.push(1);
+        expected.extend_from_slice(&[0, 2, 1, 0xaa, 0, 3, 2, 0xbb, 0xbb]);
+        expected.extend_from_slice(&[0, 2, 1, 0xcc, 0, 2, 1, 0xdd]);
+        // MakeMoveVec<vector<u8>>(Input(3), Input(4)), then the MoveCall.
+        expected.extend_from_slice(&[2, 5, 1, 6, 1, 2, 1, 3, 0, 1, 4, 0, 0]);
+        expected.extend_from_slice(&address("0x1").unwrap());
+        expected.extend_from_slice(b"\x06bridge\x05relay\x00\x04");
+        expected.extend_from_slice(&[1, 0, 0, 1, 1, 0, 1, 2, 0, 2, 0, 0]);
+        expected.extend_from_slice(&adapter.sender);
+        expected.push(1);
+        expected.extend_from_slice(&GAS.0);
+        expected.extend_from_slice(&GAS.1.to_le_bytes());
+        expected.push(32);
+        expected.extend_from_slice(&GAS.2);
+        expected.extend_from_slice(&adapter.sender);
+        expected.extend_from_slice(&750u64.to_le_bytes());
+        expected.extend_from_slice(&1_000u64.to_le_bytes());
+        expected.push(0);
+        assert_eq!(tx_data, expected);
+
+        let signature = adapter.sign(&tx_data);
+        let public = adapter.signer.verifying_key();
+        assert_eq!(signature[0], ED25519_FLAG);
+        assert_eq!(&signature[65..], public.as_bytes());
+        let digest = Blake2b256::new()
+            .chain_update(TRANSACTION_INTENT)
+            .chain_update(&tx_data)
+            .finalize();
+        let ed25519 = Signature::from_slice(&signature[1..65]).unwrap();
+        public.verify(&digest, &ed25519).unwrap();
+    }
+
+    #[tokio::test]
+    async fn splits_signatures_across_pure_arguments() {
+        let node = MockEndpoint::start(fullnode()).await;
+        let adapter = adapter(&node.url);
+        let key = hex::encode([3; 32]);
+        // Seven ML-DSA-65 signatures overflow a single pure argument.
+        let msg = message(7, SIGNATURE_SIZE);
+        assert!(msg.encode(true).len() > MAX_PURE_ARGUMENT_SIZE);
+
+        assert_eq!(adapter.submit(&key, &msg).await.unwrap(), "D1");
+        let requests = node.requests();
+        let params = &requests.last().unwrap().body["params"];
+        let tx_data = BASE64.decode(params[0].as_str().unwrap()).unwrap();
+        let signatures = msg.signature_entries();
+        let pure = pure_args(
+            [[3; 32].as_slice(), &msg.encode(false)]
+                .into_iter()
+                .chain(signatures.iter().map(Vec::as_slice)),
+        )
+        .unwrap();
+        assert_eq!(pure.len(), 9);
+        assert_eq!(tx_data, adapter.transaction_data(11, GAS, 750, &pure));
+        let signature = BASE64.decode(params[1][0].as_str().unwrap()).unwrap();
+        assert_eq!(signature, adapter.sign(&tx_data));
+    }
+
+    #[tokio::test]
+    async fn oversized_messages_are_undeliverable() {
+        let node = MockEndpoint::start(fullnode()).await;
+        let adapter = adapter(&node.url);
+        let key = hex::encode([3; 32]);
+
+        let err = adapter.submit(&key, &message(1, 20_000)).await.unwrap_err();
+        assert!(err.is::<Undeliverable>(), "{err}");
+        assert!(node.requests().is_empty());
+
+        let err = adapter
+            .submit(&key, &message(40, SIGNATURE_SIZE))
+            .await
+            .unwrap_err();
+        assert!(err.is::<Undeliverable>(), "{err}");
+        assert!(node
+            .requests()
+            .iter()
+            .all(|request| request.body["method"] != "sui_executeTransactionBlock"));
+    }
+
+    #[tokio::test]
+    async fn reports_status_and_outages() {
+        let node = MockEndpoint::start(fullnode()).await;
+        let adapter = adapter(&node.url);
+        for (tx_id, status) in [
+            ("unknown", DeliveryStatus::Unknown),
+            ("pending", DeliveryStatus::Pending),
+            ("executed", DeliveryStatus::Confirmed),
+            ("checkpointed", DeliveryStatus::Finalized),
+        ] {
+            assert_eq!(adapter.status(tx_id).await.unwrap(), status, "{tx_id}");
+        }
+        assert!(matches!(
+            adapter.status("failed").await.unwrap(),
+            DeliveryStatus::Failed(_)
+        ));
+        assert!(adapter.status("outage").await.is_err());
+        adapter.health().await.unwrap();
+    }
+}
@@ -1,15 +1,50 @@
+use revm::primitives::Address;
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeConfig {
+    /// Derives the libp2p identity; takes precedence over `node_key_path`.
     pub node_key_seed: Option<String>,
+    /// libp2p identity key file (protobuf encoded), created if missing.
+    pub node_key_path: Option<String>,
+    /// Id of the validator this node runs as, in `validators`.
+    pub validator_id: String,
+    /// Hex-encoded ML-DSA secret key matching this validator's `pq_pubkey_hex`.
+    pub pq_secret_key_path: Option<String>,
     pub libp2p_listen: String,
+    /// Entry points into the DHT (`/ip4/.../tcp/.../p2p/<peer id>`),
+    /// dialled while the node has no peers.
+    pub bootnodes: Vec<String>,
+    /// Peers kept connected at all times, same format as `bootnodes`.
+    pub static_peers: Vec<String>,
+    /// Discover peers on the local network via mDNS.
+    pub enable_mdns: bool,
     pub rpc_listen: SocketAddr,
+    /// Serves the state-changing `admin_*` methods; keep it on a loopback
+    /// or otherwise private interface. Disabled if unset.
+    #[serde(default)]
+    pub admin_rpc_listen: Option<SocketAddr>,
     pub rocksdb_path: String,
+    /// Genesis spec to initialise / check the database against.
+    pub genesis_path: Option<String>,
     pub chain_id: u64,
+    /// Fork activations overriding the genesis schedule.
+    #[serde(default)]
+    pub fork_overrides: ForkOverrides,
     pub target_tps: u64,
     pub block_time_ms: u64,
+    /// Gas limit of the dev genesis; a genesis file's `gasLimit` replaces it,
+    /// as every validator must pack blocks to the same limit.
+    pub block_gas_limit: u64,
+    /// Snappy-compress large gossip messages.
+    pub gossip_compression: bool,
+    /// Gossiped txs with a lower `max_fee_per_gas` are not relayed.
+    pub min_gas_price: u64,
+    /// Threads used to execute a block's transactions in parallel.
+    pub execution_workers: usize,
+    /// Recent blocks whose pre-state is kept for `debug_trace*`; 0 disables.
+    pub trace_history_blocks: usize,
     pub validators: Vec<ValidatorConfig>,
     pub bridges: BridgeConfig,
 }
@@ -20,32 +55,293 @@ pub struct ValidatorConfig {
     pub stake: u64,
     /// ML-DSA public key (bytes, hex encoded in config)
     pub pq_pubkey_hex: String,
+    /// Address credited with priority fees for blocks this validator proposes.
+    #[serde(default)]
+    pub fee_recipient: Address,
+}
+
+/// Activations that take precedence over the genesis `config`, in the
+/// spirit of Geth's `--override.*` flags. Every validator must run with the
+/// same values before the earliest of them is reached.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ForkOverrides {
+    #[serde(default)]
+    pub shanghai_time: Option<u64>,
+    #[serde(default)]
+    pub cancun_time: Option<u64>,
+    #[serde(default)]
+    pub shanghai_block: Option<u64>,
+    #[serde(default)]
+    pub cancun_block: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BridgeConfig {
-    pub solana_rpc_url: String,
-    pub sui_rpc_url: String,
-    pub aptos_rpc_url: String,
+    /// Destination chains committed blocks are relayed to.
+    pub adapters: Vec<AdapterConfig>,
+    /// Failed deliveries before a message is moved to the dead-letter queue.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// Upper bound on the delay between two delivery attempts.
+    #[serde(default = "default_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+    /// How long delivery records are kept once final on their destination.
+    #[serde(default = "default_retention_secs")]
+    pub retention_secs: u64,
+    /// Guardians of inbound transfers for a devnet genesis; a genesis file
+    /// names its own.
+    #[serde(default)]
+    pub guardians: Option<GuardianSetConfig>,
+}
+
+/// Who signs transfers from other chains into this one, and how many of
+/// them must.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuardianSetConfig {
+    pub guardians: Vec<GuardianConfig>,
+    /// Distinct guardian signatures an inbound transfer needs.
+    pub threshold: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuardianConfig {
+    pub id: String,
+    /// ML-DSA public key (bytes, hex encoded in config)
+    pub pq_pubkey_hex: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdapterConfig {
+    /// Unique name of the target, used in logs and delivery records.
+    pub name: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Id `Deposit` events name this chain by; deposits are relayed only
+    /// to adapters that have one.
+    #[serde(default)]
+    pub destination_id: Option<u64>,
+    /// When a block counts as final for this target; nothing from a block
+    /// is relayed before.
+    #[serde(default)]
+    pub finality: FinalityPolicy,
+    #[serde(flatten)]
+    pub chain: ChainAdapterConfig,
+}
+
+/// When a block counts as final, selected by `mode`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum FinalityPolicy {
+    /// As soon as its commit certificate is backed by an attestation of
+    /// the executed outcome with quorum.
+    #[default]
+    Certificate,
+    /// Once the certified head is `blocks` past it, i.e. that many more
+    /// blocks have commit certificates on top of it. The chain has no
+    /// epochs, so depths are counted in blocks only.
+    Depth { blocks: u64 },
+}
+
+/// Chain-specific settings, selected by `kind`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ChainAdapterConfig {
+    Solana(SolanaConfig),
+    Sui(SuiConfig),
+    Aptos(AptosConfig),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SolanaConfig {
+    pub rpc_url: String,
+    /// Bridge program called by relayed transactions (base58).
+    pub program_id: String,
+    /// State account of the bridge program (base58).
+    pub bridge_account: String,
+    /// Relayer keypair paying for and signing transactions, in the Solana
+    /// CLI's JSON format.
+    pub keypair_path: String,
+    /// Relay messages without the validators' ML-DSA signatures, which do
+    /// not fit in a Solana transaction. The bridge program must then trust
+    /// the relayer key that the messages are final, instead of checking it
+    /// itself: a weaker bridge, so it has to be opted into.
+    #[serde(default)]
+    pub trusted_relayer: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuiConfig {
+    pub rpc_url: String,
+    /// Package of the Move bridge module (0x-hex).
+    pub package_id: String,
+    #[serde(default = "default_sui_module")]
+    pub module: String,
+    /// Entry function taking the bridge object, the idempotency key, the
+    /// message and its signatures.
+    #[serde(default = "default_sui_function")]
+    pub function: String,
+    /// Shared object holding the bridge's state (0x-hex).
+    pub bridge_object: String,
+    /// Relayer key in `sui.keystore` form (base64 of flag and secret); its
+    /// address pays for gas.
+    pub keypair_path: String,
+    /// Gas budget of each transaction, in MIST.
+    #[serde(default = "default_sui_gas_budget")]
+    pub gas_budget: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AptosConfig {
+    /// REST API base, e.g. `https://fullnode.testnet.aptoslabs.com/v1`.
+    pub rest_url: String,
+    /// Account the Move bridge module is published under (0x-hex).
+    pub module_address: String,
+    #[serde(default = "default_aptos_module")]
+    pub module: String,
+    /// Entry function taking the relayer's signer, the idempotency key and
+    /// the message.
+    #[serde(default = "default_aptos_function")]
+    pub function: String,
+    /// Relayer's Ed25519 private key (hex, as `aptos init` writes it); its
+    /// account pays for gas.
+    pub private_key_path: String,
+    /// Gas units each transaction may use.
+    #[serde(default = "default_aptos_max_gas_amount")]
+    pub max_gas_amount: u64,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_sui_module() -> String {
+    "bridge".to_string()
+}
+
+fn default_sui_function() -> String {
+    "relay".to_string()
+}
+
+fn default_sui_gas_budget() -> u64 {
+    50_000_000
+}
+
+fn default_aptos_module() -> String {
+    "bridge".to_string()
+}
+
+fn default_aptos_function() -> String {
+    "relay".to_string()
+}
+
+fn default_aptos_max_gas_amount() -> u64 {
+    200_000
+}
+
+fn default_max_attempts() -> u32 {
+    8
+}
+
+fn default_max_backoff_secs() -> u64 {
+    300
+}
+
+fn default_retention_secs() -> u64 {
+    7 * 24 * 3600
 }
 
 impl Default for NodeConfig {
     fn default() -> Self {
         Self {
             node_key_seed: None,
+            node_key_path: Some("data/node.key".to_string()),
+            validator_id: "validator-0".to_string(),
+            pq_secret_key_path: None,
             libp2p_listen: "/ip4/0.0.0.0/tcp/7000".to_string(),
+            bootnodes: vec![],
+            static_peers: vec![],
+            enable_mdns: false,
             rpc_listen: "0.0.0.0:8545".parse().unwrap(),
+            admin_rpc_listen: Some("127.0.0.1:8546".parse().unwrap()),
             rocksdb_path: "data/chain.db".to_string(),
+            genesis_path: None,
             chain_id: 1337,
+            fork_overrides: ForkOverrides::default(),
             target_tps: 10_000,
             block_time_ms: 100, // 100ms * ~1000 tx/block ≈ 10k TPS target
+            block_gas_limit: 30_000_000,
+            gossip_compression: true,
+            min_gas_price: 0,
+            execution_workers: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            trace_history_blocks: 32,
             validators: vec![],
             bridges: BridgeConfig {
-                solana_rpc_url: "https://api.devnet.solana.com".to_string(),
-                sui_rpc_url: "https://fullnode.testnet.sui.io:443".to_string(),
-                aptos_rpc_url: "https://fullnode.testnet.aptoslabs.com/v1".to_string(),
+                adapters: vec![
+                    AdapterConfig {
+                        name: "solana".to_string(),
+                        // Needs a deployed bridge program and a funded keypair.
+                        enabled: false,
+                        destination_id: Some(1),
+                        finality: FinalityPolicy::Certificate,
+                        chain: ChainAdapterConfig::Solana(SolanaConfig {
+                            rpc_url: "https://api.devnet.solana.com".to_string(),
+                            program_id: String::new(),
+                            bridge_account: String::new(),
+                            keypair_path: "data/solana-relayer.json".to_string(),
+                            trusted_relayer: false,
+                        }),
+                    },
+                    AdapterConfig {
+                        name: "sui".to_string(),
+                        // Needs a published bridge package and a funded key.
+                        enabled: false,
+                        destination_id: Some(2),
+                        finality: FinalityPolicy::Certificate,
+                        chain: ChainAdapterConfig::Sui(SuiConfig {
+                            rpc_url: "https://fullnode.testnet.sui.io:443".to_string(),
+                            package_id: String::new(),
+                            module: default_sui_module(),
+                            function: default_sui_function(),
+                            bridge_object: String::new(),
+                            keypair_path: "data/sui-relayer.key".to_string(),
+                            gas_budget: default_sui_gas_budget(),
+                        }),
+                    },
+                    AdapterConfig {
+                        name: "aptos".to_string(),
+                        // Needs a published bridge module and a funded account.
+                        enabled: false,
+                        destination_id: Some(3),
+                        finality: FinalityPolicy::Certificate,
+                        chain: ChainAdapterConfig::Aptos(AptosConfig {
+                            rest_url: "https://fullnode.testnet.aptoslabs.com/v1".to_string(),
+                            module_address: String::new(),
+                            module: default_aptos_module(),
+                            function: default_aptos_function(),
+                            private_key_path: "data/aptos-relayer.key".to_string(),
+                            max_gas_amount: default_aptos_max_gas_amount(),
+                        }),
+                    },
+                ],
+                max_attempts: default_max_attempts(),
+                max_backoff_secs: default_max_backoff_secs(),
+                retention_secs: default_retention_secs(),
+                guardians: None,
             },
         }
     }
 }
 
+
+impl NodeConfig {
+    /// Fee recipient configured for the validator with the given id.
+    pub fn fee_recipient(&self, validator_id: &str) -> Address {
+        self.validators
+            .iter()
+            .find(|v| v.id == validator_id)
+            .map(|v| v.fee_recipient)
+            .unwrap_or_default()
+    }
+}
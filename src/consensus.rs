@@ -1,26 +1,51 @@
-use crate::crypto::verify_pq_tx;
+use crate::config::ValidatorConfig;
+use crate::crypto::verify_tx_signatures;
+use crate::identity::{quorum_stake, ValidatorKey};
+use crate::inbound::MAX_SYSTEM_TXS_PER_BLOCK;
+use crate::merkle;
 use crate::types::{
-    Block, BlockHeader, ConsensusInput, ConsensusOutput, HybridTx, NarwhalBatch,
+    Block, BlockHeader, CommitCertificate, CommitVote, ConsensusInput, ConsensusOutput, HybridTx,
+    NarwhalBatch,
 };
 use crate::db::ChainStore;
+use crate::wire::GossipMessage;
 use anyhow::Result;
-use revm::primitives::{B256, U256};
+use revm::primitives::{Address, B256, U256};
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, HashSet},
     sync::Arc,
     time::{SystemTime, UNIX_EPOCH},
 };
 use tokio::sync::mpsc::{Receiver, Sender};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Commit votes are kept for blocks at most this far behind the head.
+const VOTE_WINDOW: u64 = 256;
 
 pub struct NarwhalBullsharkEngine {
     store: Arc<ChainStore>,
     input_rx: Receiver<ConsensusInput>,
     output_tx: Sender<ConsensusOutput>,
+    /// Locally built batches and commit votes are published through here.
+    gossip_tx: Sender<GossipMessage>,
     validator_id: String,
     target_tps: u64,
     block_time_ms: u64,
+    block_gas_limit: u64,
+    /// Fallback coinbase when no validator set is configured.
+    coinbase: Address,
+    validators: Vec<ValidatorConfig>,
+    /// Signs local batches and commit votes; `None` on non-validator nodes.
+    validator_key: Option<Arc<ValidatorKey>>,
+    /// Commit votes by block number and validator, until a certificate forms.
+    commit_votes: BTreeMap<u64, HashMap<String, CommitVote>>,
+    quorum: u64,
     dag: HashMap<u64, Vec<NarwhalBatch>>, // round -> batches
     pending_txs: Vec<HybridTx>,
+    /// Hashes of `pending_txs`, so a tx resubmitted or gossiped again is
+    /// pooled once.
+    pending_hashes: HashSet<B256>,
 }
 
 impl NarwhalBullsharkEngine {
@@ -29,19 +54,32 @@ impl NarwhalBullsharkEngine {
         store: Arc<ChainStore>,
         input_rx: Receiver<ConsensusInput>,
         output_tx: Sender<ConsensusOutput>,
+        gossip_tx: Sender<GossipMessage>,
         validator_id: String,
         target_tps: u64,
         block_time_ms: u64,
+        block_gas_limit: u64,
+        coinbase: Address,
+        validators: Vec<ValidatorConfig>,
+        validator_key: Option<Arc<ValidatorKey>>,
     ) -> Self {
         Self {
             store,
             input_rx,
             output_tx,
+            gossip_tx,
             validator_id,
             target_tps,
             block_time_ms,
+            block_gas_limit,
+            coinbase,
+            quorum: quorum_stake(&validators),
+            validators,
+            validator_key,
+            commit_votes: BTreeMap::new(),
             dag: HashMap::new(),
             pending_txs: Vec::new(),
+            pending_hashes: HashSet::new(),
         }
     }
 
@@ -52,14 +90,26 @@ impl NarwhalBullsharkEngine {
             tokio::select! {
                 Some(msg) = self.input_rx.recv() => {
                     match msg {
+                        ConsensusInput::NewTx(tx) if tx.is_system() => {
+                            // Only `bridge_submitInbound` and gossip hand
+                            // these over, both after checking the guardian
+                            // signatures and that the transfer is new.
+                            self.add_pending(tx);
+                        }
                         ConsensusInput::NewTx(tx) => {
-                            // basic PQ check (optional)
-                            let _ = verify_pq_tx(&tx);
-                            self.pending_txs.push(tx);
+                            // Gossiped txs are checked before they get here;
+                            // RPC submissions are not.
+                            match verify_tx_signatures(&tx) {
+                                Ok(()) => self.add_pending(tx),
+                                Err(e) => warn!("Dropping tx {}: {e}", tx.hash),
+                            }
                         }
                         ConsensusInput::NarwhalBatch(batch) => {
                             self.dag.entry(batch.round).or_default().push(batch);
                         }
+                        ConsensusInput::CommitVote(vote) => {
+                            self.add_commit_vote(vote)?;
+                        }
                     }
                 }
                 _ = tokio::time::sleep(
@@ -68,13 +118,17 @@ impl NarwhalBullsharkEngine {
                     // produce a new round/batch from local pending txs
                     current_round += 1;
                     if !self.pending_txs.is_empty() {
-                        let batch = self.build_local_batch(current_round);
+                        let batch = self.build_local_batch(current_round)?;
+                        if self.gossip_tx.try_send(GossipMessage::Batch(batch.clone())).is_err() {
+                            warn!("Gossip queue full; batch {} not published", batch.id);
+                        }
                         self.dag.entry(current_round).or_default().push(batch);
                     }
 
                     if let Some(block) = self.bullshark_commit(current_round).await? {
                         self.store.put_block(&block)?;
                         self.store.put_head(block.header.number, block.header.hash.0)?;
+                        self.vote_for(&block.header)?;
                         self.output_tx.send(ConsensusOutput::CommittedBlock(block)).await?;
                     }
                 }
@@ -82,9 +136,97 @@ impl NarwhalBullsharkEngine {
         }
     }
 
-    fn build_local_batch(&mut self, round: u64) -> NarwhalBatch {
+    /// Sign and publish a commit vote for a block this node committed.
+    fn vote_for(&mut self, header: &BlockHeader) -> Result<()> {
+        if let Some(key) = &self.validator_key {
+            let vote = key.sign_commit(header.number, header.hash)?;
+            if self.gossip_tx.try_send(GossipMessage::CommitVote(vote.clone())).is_err() {
+                warn!("Gossip queue full; commit vote for {} not published", header.number);
+            }
+            self.add_commit_vote(vote)?;
+        }
+        // Votes of faster peers may already be waiting for this block.
+        self.try_certify(header.number)
+    }
+
+    fn add_commit_vote(&mut self, vote: CommitVote) -> Result<()> {
+        let number = vote.number;
+        let head = self.store.get_head_number()?;
+        let in_window = number.saturating_add(VOTE_WINDOW) >= head
+            && number <= head.saturating_add(VOTE_WINDOW);
+        if !in_window || self.store.get_certificate(number)?.is_some() {
+            return Ok(());
+        }
+        self.commit_votes
+            .entry(number)
+            .or_default()
+            .entry(vote.validator.clone())
+            .or_insert(vote);
+        self.try_certify(number)
+    }
+
+    /// Store a certificate for block `number` once votes for the local block
+    /// reach 2f+1 stake.
+    fn try_certify(&mut self, number: u64) -> Result<()> {
+        let Some(block) = self.store.get_block(number)? else {
+            return Ok(());
+        };
+        let Some(votes) = self.commit_votes.get(&number) else {
+            return Ok(());
+        };
+
+        let mut stakes: HashMap<B256, u64> = HashMap::new();
+        for vote in votes.values() {
+            *stakes.entry(vote.block_hash).or_default() += self.stake_of(&vote.validator);
+        }
+        for (hash, stake) in &stakes {
+            if *hash != block.header.hash && *stake >= self.quorum {
+                error!(
+                    "validators certified block {number} as 0x{}, local block is 0x{}",
+                    hex::encode(hash),
+                    hex::encode(block.header.hash)
+                );
+            }
+        }
+        if stakes.get(&block.header.hash).copied().unwrap_or(0) < self.quorum {
+            return Ok(());
+        }
+
+        let cert = CommitCertificate {
+            number,
+            block_hash: block.header.hash,
+            votes: votes
+                .values()
+                .filter(|vote| vote.block_hash == block.header.hash)
+                .map(|vote| (vote.validator.clone(), vote.signature.clone()))
+                .collect(),
+        };
+        self.store.put_certificate(&cert)?;
+        info!(number, votes = cert.votes.len(), "certified block");
+
+        self.commit_votes.remove(&number);
+        let oldest = number.saturating_sub(VOTE_WINDOW);
+        self.commit_votes = self.commit_votes.split_off(&oldest);
+        Ok(())
+    }
+
+    fn stake_of(&self, validator: &str) -> u64 {
+        self.validators
+            .iter()
+            .find(|v| v.id == validator)
+            .map_or(0, |v| v.stake)
+    }
+
+    fn add_pending(&mut self, tx: HybridTx) {
+        if self.pending_hashes.insert(tx.hash) {
+            self.pending_txs.push(tx);
+        }
+    }
+
+    fn build_local_batch(&mut self, round: u64) -> Result<NarwhalBatch> {
         let txs = std::mem::take(&mut self.pending_txs);
-        NarwhalBatch {
+        self.pending_hashes.clear();
+        let mut batch = NarwhalBatch {
             id: uuid::Uuid::new_v4(),
             round,
             author: self.validator_id.clone(),
@@ -94,12 +236,18 @@ impl NarwhalBullsharkEngine {
                 .map(|batches| batches.iter().map(|b| b.id).collect())
                 .unwrap_or_default(),
             txs,
+            timestamp: unix_time()?,
+            signature: Vec::new(),
+        };
+        if let Some(key) = &self.validator_key {
+            batch.signature = key.sign_batch(&batch)?;
         }
+        Ok(batch)
     }
 
     /// Extremely simplified Bullshark: if we have any batches for the last 3
     /// rounds, we “commit” them in topological order to form a block.
-    async fn bullshark_commit(&self, current_round: u64) -> Result<Option<Block>> {
+    async fn bullshark_commit(&mut self, current_round: u64) -> Result<Option<Block>> {
         if current_round < 3 {
             return Ok(None);
         }
@@ -115,9 +263,15 @@ impl NarwhalBullsharkEngine {
         // Deterministic order: sort by UUID bytes.
         batches.sort_by_key(|b| b.id.as_u128());
 
-        let mut all_txs = Vec::new();
-        for b in batches {
-            all_txs.extend(b.txs.clone());
+        let leader = self.leader(commit_round);
+        let coinbase = leader.map(|v| v.fee_recipient).unwrap_or(self.coinbase);
+        let leader_id = leader.map(|v| v.id.clone()).unwrap_or_else(|| self.validator_id.clone());
+        let batch_ids: Vec<Uuid> = batches.iter().map(|b| b.id).collect();
+        let batch_time = median_timestamp(&batches);
+
+        let (all_txs, overflow) = pack_txs(&batches, &self.validator_id, self.block_gas_limit);
+        for tx in overflow {
+            self.add_pending(tx);
         }
 
         // Construct block header
@@ -127,46 +281,349 @@ impl NarwhalBullsharkEngine {
             .as_ref()
             .map(|h| h.hash)
             .unwrap_or(B256::ZERO);
+        let parent_randao = parent_header
+            .as_ref()
+            .map(|h| h.prevrandao)
+            .unwrap_or(B256::ZERO);
+        let prevrandao =
+            self.compute_randao(parent_randao, commit_round, &leader_id, &batch_ids);
 
-        let tx_root = self.compute_fake_root(&all_txs);
-        let state_root = B256::ZERO; // would come from EVM executor after commit
+        let tx_root = tx_root(&all_txs);
 
-        let ts = SystemTime::now()
-            .duration_since(UNIX_EPOCH)?
-            .as_secs();
+        // Never behind the parent, whatever clocks the batch authors keep.
+        let ts = parent_header
+            .as_ref()
+            .map_or(batch_time, |h| h.timestamp.max(batch_time));
 
-        let header = BlockHeader {
+        let mut header = BlockHeader {
             number,
-            hash: self.compute_block_hash(number, parent_hash, &tx_root),
+            hash: B256::ZERO,
             parent_hash,
-            state_root,
+            state_root: B256::ZERO, // filled in by the executor
             tx_root,
             timestamp: ts,
+            coinbase,
+            prevrandao,
+            gas_limit: self.block_gas_limit,
+            gas_used: 0,                  // filled in by the executor
+            base_fee_per_gas: U256::ZERO, // filled in by the executor
         };
+        header.hash = block_hash(&header);
 
         Ok(Some(Block { header, txs: all_txs }))
     }
 
-    fn compute_fake_root(&self, txs: &[HybridTx]) -> B256 {
-        use sha2::{Digest, Sha256};
-
-        let mut hasher = Sha256::new();
-        for tx in txs {
-            hasher.update(&tx.hash.0);
+    /// Bullshark leader of a round: round-robin over the configured validators.
+    fn leader(&self, round: u64) -> Option<&ValidatorConfig> {
+        if self.validators.is_empty() {
+            return None;
         }
-        let out = hasher.finalize();
-        B256::from_slice(&out)
+        self.validators.get((round % self.validators.len() as u64) as usize)
     }
 
-    fn compute_block_hash(&self, number: u64, parent_hash: B256, tx_root: &B256) -> B256 {
+    /// Randomness beacon for `prevrandao`: chains the parent value with the
+    /// commit round, its leader and the committed batch ids, so every node
+    /// that committed the same sub-DAG derives the same value.
+    fn compute_randao(
+        &self,
+        parent_randao: B256,
+        commit_round: u64,
+        leader_id: &str,
+        batch_ids: &[Uuid],
+    ) -> B256 {
         use sha2::{Digest, Sha256};
 
         let mut hasher = Sha256::new();
-        hasher.update(number.to_be_bytes());
-        hasher.update(parent_hash.0);
-        hasher.update(tx_root.0);
-        let out = hasher.finalize();
-        B256::from_slice(&out)
+        hasher.update(parent_randao.0);
+        hasher.update(commit_round.to_be_bytes());
+        hasher.update(leader_id.as_bytes());
+        for id in batch_ids {
+            hasher.update(id.as_bytes());
+        }
+        B256::from_slice(&hasher.finalize())
+    }
+}
+
+/// Fill a block up to `gas_limit` with the txs of `batches`, each once and
+/// at most `MAX_SYSTEM_TXS_PER_BLOCK` system txs, which apply inbound bridge
+/// transfers and open the block. Returns the block's txs and those of
+/// `local`'s batches left out, for a later block; other validators do the
+/// same with theirs, so their leftovers are dropped here rather than batched
+/// twice. Only the committed sub-DAG decides what goes in: the base fee is
+/// not known until the parent is executed, so txs paying less than it are
+/// packed and then skipped by the executor with a failed receipt.
+fn pack_txs(
+    batches: &[&NarwhalBatch],
+    local: &str,
+    gas_limit: u64,
+) -> (Vec<HybridTx>, Vec<HybridTx>) {
+    let mut txs = Vec::new();
+    let mut overflow = Vec::new();
+    let mut seen = HashSet::new();
+    let mut system_txs = 0;
+    let mut gas_budget = gas_limit;
+    for batch in batches {
+        for tx in &batch.txs {
+            if !seen.insert(tx.hash) {
+                continue;
+            }
+            let fits = !tx.is_system() || system_txs < MAX_SYSTEM_TXS_PER_BLOCK;
+            if fits && tx.gas_limit <= gas_budget {
+                gas_budget -= tx.gas_limit;
+                system_txs += tx.is_system() as usize;
+                txs.push(tx.clone());
+            } else if batch.author == local {
+                overflow.push(tx.clone());
+            }
+        }
     }
+    txs.sort_by_key(|tx| !tx.is_system());
+    (txs, overflow)
+}
+
+/// Binary Merkle root over the bodies of a block's transactions, in block
+/// order.
+pub fn tx_root(txs: &[HybridTx]) -> B256 {
+    merkle::root(&tx_leaves(txs))
+}
+
+/// Merkle leaves of a block's transactions: their whole bodies, signatures
+/// included, bincode-encoded (the `hash` field alone is only a claim).
+pub fn tx_leaves(txs: &[HybridTx]) -> Vec<B256> {
+    txs.iter()
+        .map(|tx| merkle::leaf_hash(&bincode::serialize(tx).expect("tx serializes")))
+        .collect()
+}
+
+/// Median of the authors' timestamps of committed batches: a single
+/// validator with a skewed clock cannot move it far, and every node that
+/// committed the same sub-DAG derives the same value.
+fn median_timestamp(batches: &[&NarwhalBatch]) -> u64 {
+    let mut times: Vec<u64> = batches.iter().map(|b| b.timestamp).collect();
+    times.sort_unstable();
+    times.get(times.len().saturating_sub(1) / 2).copied().unwrap_or(0)
+}
+
+/// Seconds since the Unix epoch on the local clock.
+pub fn unix_time() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+/// Hash of the consensus fields of a header. Execution-derived fields (gas
+/// used, base fee, state root) are left out: each node fills them in when it
+/// gets to execute the block, and every validator that committed the same
+/// sub-DAG must derive the same hash.
+pub fn block_hash(header: &BlockHeader) -> B256 {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    hasher.update(header.number.to_be_bytes());
+    hasher.update(header.parent_hash.0);
+    hasher.update(header.tx_root.0);
+    hasher.update(header.timestamp.to_be_bytes());
+    hasher.update(header.gas_limit.to_be_bytes());
+    hasher.update(header.coinbase.as_slice());
+    hasher.update(header.prevrandao.0);
+    let out = hasher.finalize();
+    B256::from_slice(&out)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{open_store, TempPath};
+    use crate::types::{INITIAL_BASE_FEE, SYSTEM_ADDRESS};
+    use revm::primitives::Bytes;
+
+    fn header() -> BlockHeader {
+        BlockHeader {
+            number: 7,
+            hash: B256::ZERO,
+            parent_hash: B256::with_last_byte(6),
+            state_root: B256::ZERO,
+            tx_root: B256::ZERO,
+            timestamp: 1_700_000_000,
+            coinbase: Address::with_last_byte(1),
+            prevrandao: B256::ZERO,
+            gas_limit: 30_000_000,
+            gas_used: 0,
+            base_fee_per_gas: U256::from(INITIAL_BASE_FEE),
+        }
+    }
+
+    fn tx() -> HybridTx {
+        HybridTx {
+            hash: B256::with_last_byte(1),
+            from: Address::with_last_byte(1),
+            to: Some(Address::with_last_byte(2)),
+            nonce: U256::ZERO,
+            gas_limit: 21_000,
+            max_fee_per_gas: U256::from(2 * INITIAL_BASE_FEE),
+            max_priority_fee_per_gas: U256::from(2),
+            value: U256::from(1_000),
+            data: Bytes::new(),
+            chain_id: 1337,
+            sig: None,
+            pq_sig: None,
+            pq_pubkey: None,
+        }
+    }
+
+    #[test]
+    fn block_hash_covers_the_block_env() {
+        let base = block_hash(&header());
+        let mut other = header();
+        other.coinbase = Address::with_last_byte(2);
+        assert_ne!(block_hash(&other), base);
+        let mut other = header();
+        other.timestamp += 1;
+        assert_ne!(block_hash(&other), base);
+        let mut other = header();
+        other.gas_limit += 1;
+        assert_ne!(block_hash(&other), base);
+    }
+
+    #[test]
+    fn tx_root_commits_to_bodies_not_claimed_hashes() {
+        let root = tx_root(&[tx()]);
+        let mut rewritten = tx();
+        rewritten.to = Some(Address::with_last_byte(3));
+        assert_eq!(rewritten.hash, tx().hash);
+        assert_ne!(tx_root(&[rewritten]), root);
+    }
+
+    fn priced(id: u8, max_fee: u64) -> HybridTx {
+        let mut tx = tx();
+        tx.hash = B256::with_last_byte(id);
+        tx.max_fee_per_gas = U256::from(max_fee);
+        tx
+    }
+
+    fn system(id: u8) -> HybridTx {
+        let mut tx = priced(id, 0);
+        tx.from = SYSTEM_ADDRESS;
+        tx.max_priority_fee_per_gas = U256::ZERO;
+        tx
+    }
+
+    fn batch(author: &str, txs: Vec<HybridTx>) -> NarwhalBatch {
+        NarwhalBatch {
+            id: Uuid::new_v4(),
+            round: 1,
+            author: author.to_string(),
+            parents: vec![],
+            txs,
+            timestamp: 0,
+            signature: vec![],
+        }
+    }
+
+    fn hashes(txs: &[HybridTx]) -> Vec<u8> {
+        txs.iter().map(|tx| tx.hash[31]).collect()
+    }
+
+    #[test]
+    fn packs_each_tx_once_with_system_txs_first() {
+        let fee = INITIAL_BASE_FEE;
+        let local = batch("a", vec![priced(1, fee), system(2), priced(3, fee - 1)]);
+        let foreign = batch("b", vec![system(2), priced(1, fee), priced(4, fee - 1)]);
+        let (txs, overflow) = pack_txs(&[&local, &foreign], "a", 30_000_000);
+        // Underpriced txs are packed all the same; the executor skips them.
+        assert_eq!(hashes(&txs), [2, 1, 3, 4]);
+        assert!(overflow.is_empty());
+    }
+
+    #[test]
+    fn returns_only_local_overflow() {
+        let local = batch("a", vec![priced(1, 1), priced(2, 1)]);
+        let foreign = batch("b", vec![priced(3, 1), priced(4, 1)]);
+        // Room for one tx: the rest of "a" goes back to the pool, and "b"
+        // batches its own leftovers again.
+        let (txs, overflow) = pack_txs(&[&local, &foreign], "a", 21_000);
+        assert_eq!(hashes(&txs), [1]);
+        assert_eq!(hashes(&overflow), [2]);
+    }
+
+    #[test]
+    fn caps_system_txs_per_block() {
+        let count = MAX_SYSTEM_TXS_PER_BLOCK as u8;
+        let local = batch("a", (0..count + 2).map(system).collect());
+        let foreign = batch("b", vec![system(count + 2), priced(200, INITIAL_BASE_FEE)]);
+        let (txs, overflow) = pack_txs(&[&local, &foreign], "a", 30_000_000);
+        assert_eq!(
+            txs.iter().filter(|tx| tx.is_system()).count(),
+            MAX_SYSTEM_TXS_PER_BLOCK
+        );
+        assert_eq!(hashes(&txs).last(), Some(&200));
+        assert_eq!(hashes(&overflow), [count, count + 1]);
+    }
+
+    fn engine(id: &str, parent: &Block) -> (TempPath, NarwhalBullsharkEngine) {
+        let (dir, store) = open_store("consensus");
+        store.put_block(parent).unwrap();
+        store
+            .put_head(parent.header.number, parent.header.hash.0)
+            .unwrap();
+        let validators = ["a", "b"].map(|id| ValidatorConfig {
+            id: id.to_string(),
+            stake: 1,
+            pq_pubkey_hex: String::new(),
+            fee_recipient: Address::with_last_byte(id.as_bytes()[0]),
+        });
+        let (_, input_rx) = tokio::sync::mpsc::channel(1);
+        let (output_tx, _) = tokio::sync::mpsc::channel(1);
+        let (gossip_tx, _) = tokio::sync::mpsc::channel(1);
+        let engine = NarwhalBullsharkEngine::new(
+            store,
+            input_rx,
+            output_tx,
+            gossip_tx,
+            id.to_string(),
+            1_000,
+            100,
+            30_000_000,
+            Address::ZERO,
+            validators.to_vec(),
+            None,
+        );
+        (dir, engine)
+    }
+
+    #[tokio::test]
+    async fn commits_the_same_block_whatever_was_executed_locally() {
+        let mut committed = Block {
+            header: header(),
+            txs: vec![],
+        };
+        committed.header.hash = block_hash(&committed.header);
+        // Execution fills in the parent's remaining fields on one node only.
+        let mut executed = committed.clone();
+        executed.header.state_root = B256::repeat_byte(0xaa);
+        executed.header.gas_used = 21_000;
+        executed.header.base_fee_per_gas = U256::from(2 * INITIAL_BASE_FEE);
+
+        let (_a, mut ahead) = engine("a", &executed);
+        let (_b, mut behind) = engine("b", &committed);
+        let mut batches = vec![
+            batch("a", vec![priced(1, INITIAL_BASE_FEE)]),
+            batch("b", vec![priced(2, INITIAL_BASE_FEE - 1), system(3)]),
+        ];
+        // Committed batches are ordered by id.
+        for (id, batch) in (1..).zip(&mut batches) {
+            batch.id = Uuid::from_u128(id);
+            batch.round = 3;
+        }
+        for engine in [&mut ahead, &mut behind] {
+            engine.dag.insert(3, batches.clone());
+            // Local pools differ, and must not matter either.
+            engine.add_pending(priced(9, INITIAL_BASE_FEE));
+        }
+
+        let ahead = ahead.bullshark_commit(5).await.unwrap().unwrap();
+        let behind = behind.bullshark_commit(5).await.unwrap().unwrap();
+        assert_eq!(ahead.header.number, 8);
+        assert_eq!(hashes(&ahead.txs), [3, 1, 2]);
+        assert_eq!(ahead.header.hash, behind.header.hash);
+        assert_eq!(ahead.header.hash, block_hash(&behind.header));
+    }
+}
@@ -1,6 +1,10 @@
-use pqcrypto_mldsa::mldsa44;
-use pqcrypto_traits::sign::{PublicKey as PkTrait, SecretKey as SkTrait, DetachedSignature as DsTrait};
+use pqcrypto_mldsa::{mldsa44, mldsa65, mldsa87};
+use pqcrypto_traits::sign::{
+    PublicKey as PkTrait, SecretKey as SkTrait, DetachedSignature as DsTrait, VerificationError,
+};
 use crate::types::HybridTx;
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use revm::primitives::{keccak256, Address, B256};
 
 #[derive(Debug, thiserror::Error)]
 pub enum CryptoError {
@@ -8,6 +12,43 @@ pub enum CryptoError {
     PqVerifyFailed,
     #[error("Malformed key or signature")]
     Malformed,
+    #[error("Transaction carries no signature")]
+    Unsigned,
+    #[error("Signature was not made by the sender {0}")]
+    WrongSigner(Address),
+}
+
+/// ML-DSA parameter sets (FIPS 204).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MlDsaLevel {
+    MlDsa44,
+    MlDsa65,
+    MlDsa87,
+}
+
+impl MlDsaLevel {
+    /// Parameter set of a public key, told apart by its size.
+    pub fn from_public_key_len(len: usize) -> Option<Self> {
+        [MlDsaLevel::MlDsa44, MlDsaLevel::MlDsa65, MlDsaLevel::MlDsa87]
+            .into_iter()
+            .find(|level| level.public_key_len() == len)
+    }
+
+    pub fn public_key_len(self) -> usize {
+        match self {
+            MlDsaLevel::MlDsa44 => mldsa44::public_key_bytes(),
+            MlDsaLevel::MlDsa65 => mldsa65::public_key_bytes(),
+            MlDsaLevel::MlDsa87 => mldsa87::public_key_bytes(),
+        }
+    }
+
+    pub fn signature_len(self) -> usize {
+        match self {
+            MlDsaLevel::MlDsa44 => mldsa44::signature_bytes(),
+            MlDsaLevel::MlDsa65 => mldsa65::signature_bytes(),
+            MlDsaLevel::MlDsa87 => mldsa87::signature_bytes(),
+        }
+    }
 }
 
 /// Post-Quantum Keypair
@@ -36,22 +77,101 @@ impl PqKeypair {
 
 /// Sign a message with the PQ secret key
 pub fn sign_pq_message(secret: &mldsa44::SecretKey, msg: &[u8]) -> Vec<u8> {
-    let sig = mldsa44::sign_detached(msg, secret);
+    let sig = mldsa44::detached_sign(msg, secret);
     sig.as_bytes().to_vec()
 }
 
-/// Verify a PQ signature on a HybridTx
-pub fn verify_pq_tx(tx: &HybridTx) -> Result<(), CryptoError> {
-    let pq_sig = tx.pq_signature.as_ref().ok_or(CryptoError::Malformed)?;
-    let pq_pk = tx.pq_pubkey.as_ref().ok_or(CryptoError::Malformed)?;
+/// Sender address of a 65-byte `r || s || v` secp256k1 signature over `hash`.
+/// `v` may be 0/1 or 27/28.
+pub fn ecrecover(sig: &[u8], hash: &B256) -> Result<Address, CryptoError> {
+    if sig.len() != 65 {
+        return Err(CryptoError::Malformed);
+    }
+    let v = match sig[64] {
+        v @ (0 | 1) => v,
+        v @ (27 | 28) => v - 27,
+        _ => return Err(CryptoError::Malformed),
+    };
+    let signature = Signature::from_slice(&sig[..64]).map_err(|_| CryptoError::Malformed)?;
+    let recid = RecoveryId::from_byte(v).ok_or(CryptoError::Malformed)?;
+    let key = VerifyingKey::recover_from_prehash(hash.as_slice(), &signature, recid)
+        .map_err(|_| CryptoError::Malformed)?;
+    let point = key.to_encoded_point(false);
+    Ok(Address::from_slice(&keccak256(&point.as_bytes()[1..])[12..]))
+}
 
-    let pk = mldsa44::PublicKey::from_bytes(pq_pk).map_err(|_| CryptoError::Malformed)?;
-    let sig = mldsa44::DetachedSignature::from_bytes(pq_sig).map_err(|_| CryptoError::Malformed)?;
+/// Address controlled by an ML-DSA public key, for PQ-only senders.
+pub fn pq_address(pubkey: &[u8]) -> Address {
+    Address::from_slice(&keccak256(pubkey)[12..])
+}
 
-    let msg = bincode::serialize(&tx.body).map_err(|_| CryptoError::Malformed)?;
+/// Check the signatures of a HybridTx against its sender. The ECDSA signature
+/// must recover to `from`; an ML-DSA signature must verify under `pq_pubkey`,
+/// which has to own `from` when there is no ECDSA signature.
+pub fn verify_tx_signatures(tx: &HybridTx) -> Result<(), CryptoError> {
+    let hash = tx.signing_hash();
 
-    mldsa44::verify_detached_signature(&sig, &msg, &pk)
-        .map_err(|_| CryptoError::PqVerifyFailed)?;
+    if let Some(sig) = &tx.sig {
+        if ecrecover(sig, &hash)? != tx.from {
+            return Err(CryptoError::WrongSigner(tx.from));
+        }
+    }
 
+    match (&tx.pq_sig, &tx.pq_pubkey) {
+        (Some(sig), Some(pubkey)) => {
+            let level =
+                MlDsaLevel::from_public_key_len(pubkey.len()).ok_or(CryptoError::Malformed)?;
+            verify_mldsa(level, pubkey, hash.as_slice(), sig)?;
+            if tx.sig.is_none() && pq_address(pubkey) != tx.from {
+                return Err(CryptoError::WrongSigner(tx.from));
+            }
+        }
+        (None, None) if tx.sig.is_none() => return Err(CryptoError::Unsigned),
+        (None, None) => {}
+        _ => return Err(CryptoError::Malformed),
+    }
     Ok(())
 }
+
+/// Verify a detached ML-DSA signature over `msg` at the given security level.
+pub fn verify_mldsa(
+    level: MlDsaLevel,
+    pubkey: &[u8],
+    msg: &[u8],
+    sig: &[u8],
+) -> Result<(), CryptoError> {
+    match level {
+        MlDsaLevel::MlDsa44 => verify_detached(pubkey, msg, sig, mldsa44::verify_detached_signature),
+        MlDsaLevel::MlDsa65 => verify_detached(pubkey, msg, sig, mldsa65::verify_detached_signature),
+        MlDsaLevel::MlDsa87 => verify_detached(pubkey, msg, sig, mldsa87::verify_detached_signature),
+    }
+}
+
+/// Sign `msg` with a detached ML-DSA signature at the given security level.
+pub fn sign_mldsa(level: MlDsaLevel, secret: &[u8], msg: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    fn sign<K: SkTrait, S: DsTrait>(
+        secret: &[u8],
+        msg: &[u8],
+        sign: fn(&[u8], &K) -> S,
+    ) -> Result<Vec<u8>, CryptoError> {
+        let sk = K::from_bytes(secret).map_err(|_| CryptoError::Malformed)?;
+        Ok(sign(msg, &sk).as_bytes().to_vec())
+    }
+
+    match level {
+        MlDsaLevel::MlDsa44 => sign(secret, msg, mldsa44::detached_sign),
+        MlDsaLevel::MlDsa65 => sign(secret, msg, mldsa65::detached_sign),
+        MlDsaLevel::MlDsa87 => sign(secret, msg, mldsa87::detached_sign),
+    }
+}
+
+fn verify_detached<P: PkTrait, S: DsTrait>(
+    pubkey: &[u8],
+    msg: &[u8],
+    sig: &[u8],
+    verify: fn(&S, &[u8], &P) -> Result<(), VerificationError>,
+) -> Result<(), CryptoError> {
+    let pk = P::from_bytes(pubkey).map_err(|_| CryptoError::Malformed)?;
+    let sig = S::from_bytes(sig).map_err(|_| CryptoError::Malformed)?;
+    verify(&sig, msg, &pk).map_err(|_| CryptoError::PqVerifyFailed)
+}
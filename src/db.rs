@@ -1,8 +1,25 @@
-use rocksdb::{Options, DB, ColumnFamilyDescriptor, BoundColumnFamily, WriteBatch};
+use crate::types::{
+    AccountState, Block, BlockHeader, CommitCertificate, Receipt, SignedAttestation,
+};
+use revm::primitives::{Address, B256};
+use rocksdb::{Direction, Options, DB, ColumnFamilyDescriptor, IteratorMode, WriteBatch};
 use serde::{Serialize, de::DeserializeOwned};
 use std::sync::Arc;
 use bincode;
 
+const CF_BLOCKS: &str = "blocks";
+const CF_TXS: &str = "txs";
+const CF_RECEIPTS: &str = "receipts";
+const CF_CERTS: &str = "certs";
+const CF_ATTESTATIONS: &str = "attestations";
+const CF_META: &str = "meta";
+const CF_STATE: &str = "state";
+const CF_OUTBOX: &str = "outbox";
+const HEAD_KEY: &[u8] = b"head";
+const CERTIFIED_HEAD_KEY: &[u8] = b"certified_head";
+const GENESIS_HASH_KEY: &[u8] = b"genesis_hash";
+const GENESIS_SPEC_KEY: &[u8] = b"genesis_spec";
+
 /// Simple chain state storage
 pub struct ChainStore {
     db: Arc<DB>,
@@ -10,18 +27,24 @@ pub struct ChainStore {
 
 impl ChainStore {
     /// Open or create the RocksDB database at the given path
-    pub fn open(path: &str) -> Self {
+    pub fn open(path: &str) -> anyhow::Result<Self> {
         let mut opts = Options::default();
         opts.create_if_missing(true);
         opts.create_missing_column_families(true);
 
         let cfs = vec![
-            ColumnFamilyDescriptor::new("blocks", Options::default()),
-            ColumnFamilyDescriptor::new("txs", Options::default()),
+            ColumnFamilyDescriptor::new(CF_BLOCKS, Options::default()),
+            ColumnFamilyDescriptor::new(CF_TXS, Options::default()),
+            ColumnFamilyDescriptor::new(CF_RECEIPTS, Options::default()),
+            ColumnFamilyDescriptor::new(CF_CERTS, Options::default()),
+            ColumnFamilyDescriptor::new(CF_ATTESTATIONS, Options::default()),
+            ColumnFamilyDescriptor::new(CF_META, Options::default()),
+            ColumnFamilyDescriptor::new(CF_STATE, Options::default()),
+            ColumnFamilyDescriptor::new(CF_OUTBOX, Options::default()),
         ];
 
-        let db = DB::open_cf_descriptors(&opts, path, cfs).expect("failed to open RocksDB");
-        Self { db: Arc::new(db) }
+        let db = DB::open_cf_descriptors(&opts, path, cfs)?;
+        Ok(Self { db: Arc::new(db) })
     }
 
     /// Store a serializable object under a key in a column family
@@ -59,4 +82,189 @@ impl ChainStore {
         self.db.write(batch)?;
         Ok(())
     }
+
+    /// Store a block under its number and index its transactions by hash
+    /// (overwrites, e.g. once execution fields are known)
+    pub fn put_block(&self, block: &Block) -> anyhow::Result<()> {
+        let blocks = self.db.cf_handle(CF_BLOCKS).expect("missing CF");
+        let txs = self.db.cf_handle(CF_TXS).expect("missing CF");
+        let number = block.header.number;
+
+        let mut batch = WriteBatch::default();
+        batch.put_cf(&blocks, number.to_be_bytes(), bincode::serialize(block)?);
+        for (index, tx) in block.txs.iter().enumerate() {
+            batch.put_cf(&txs, tx.hash.as_slice(), bincode::serialize(&(number, index as u64))?);
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Block number and index within the block of a transaction
+    pub fn get_tx_location(&self, hash: B256) -> anyhow::Result<Option<(u64, usize)>> {
+        Ok(self
+            .get::<(u64, u64)>(CF_TXS, hash.as_slice())?
+            .map(|(number, index)| (number, index as usize)))
+    }
+
+    /// Retrieve a block by number
+    pub fn get_block(&self, number: u64) -> anyhow::Result<Option<Block>> {
+        self.get(CF_BLOCKS, &number.to_be_bytes())
+    }
+
+    /// Record the latest committed block
+    pub fn put_head(&self, number: u64, hash: [u8; 32]) -> anyhow::Result<()> {
+        self.put(CF_META, HEAD_KEY, &(number, hash))
+    }
+
+    /// Number of the latest committed block (0 for an empty chain)
+    pub fn get_head_number(&self) -> anyhow::Result<u64> {
+        Ok(self
+            .get::<(u64, [u8; 32])>(CF_META, HEAD_KEY)?
+            .map(|(number, _)| number)
+            .unwrap_or(0))
+    }
+
+    /// Header of the latest committed block, if any
+    pub fn get_head_header(&self) -> anyhow::Result<Option<BlockHeader>> {
+        match self.get::<(u64, [u8; 32])>(CF_META, HEAD_KEY)? {
+            Some((number, _)) => Ok(self.get_block(number)?.map(|b| b.header)),
+            None => Ok(None),
+        }
+    }
+
+    /// Store the commit certificate of a block
+    pub fn put_certificate(&self, cert: &CommitCertificate) -> anyhow::Result<()> {
+        self.put(CF_CERTS, &cert.number.to_be_bytes(), cert)?;
+        if cert.number > self.get_certified_head()? {
+            self.put(CF_META, CERTIFIED_HEAD_KEY, &cert.number)?;
+        }
+        Ok(())
+    }
+
+    /// Commit certificate of a block, if one was collected
+    pub fn get_certificate(&self, number: u64) -> anyhow::Result<Option<CommitCertificate>> {
+        self.get(CF_CERTS, &number.to_be_bytes())
+    }
+
+    /// Number of the highest block with a commit certificate (0 for none)
+    pub fn get_certified_head(&self) -> anyhow::Result<u64> {
+        Ok(self.get(CF_META, CERTIFIED_HEAD_KEY)?.unwrap_or(0))
+    }
+
+    /// Store the receipts of an executed block
+    pub fn put_receipts(&self, number: u64, receipts: &[Receipt]) -> anyhow::Result<()> {
+        self.put(CF_RECEIPTS, &number.to_be_bytes(), &receipts)
+    }
+
+    /// Receipts of the block with the given number, once it was executed
+    pub fn get_receipts(&self, number: u64) -> anyhow::Result<Option<Vec<Receipt>>> {
+        self.get(CF_RECEIPTS, &number.to_be_bytes())
+    }
+
+    /// Store the validator attestation of a block
+    pub fn put_attestation(&self, signed: &SignedAttestation) -> anyhow::Result<()> {
+        self.put(CF_ATTESTATIONS, &signed.attestation.number.to_be_bytes(), signed)
+    }
+
+    /// Validator attestation of the block with the given number, if one formed
+    pub fn get_attestation(&self, number: u64) -> anyhow::Result<Option<SignedAttestation>> {
+        self.get(CF_ATTESTATIONS, &number.to_be_bytes())
+    }
+
+    /// Load every stored account
+    pub fn accounts(&self) -> anyhow::Result<Vec<(Address, AccountState)>> {
+        let cf_handle = self.db.cf_handle(CF_STATE).expect("missing CF");
+        let mut accounts = Vec::new();
+        for item in self.db.iterator_cf(&cf_handle, IteratorMode::Start) {
+            let (key, value) = item?;
+            accounts.push((Address::from_slice(&key), bincode::deserialize(&value)?));
+        }
+        Ok(accounts)
+    }
+
+    /// Store a bridge outbox entry, deleting `old_key` in the same write
+    /// when the entry moves (e.g. from the queue to the dead letters)
+    pub fn put_outbox(
+        &self,
+        key: &[u8],
+        old_key: Option<&[u8]>,
+        entry: &[u8],
+    ) -> anyhow::Result<()> {
+        let cf_handle = self.db.cf_handle(CF_OUTBOX).expect("missing CF");
+        let mut batch = WriteBatch::default();
+        if let Some(old_key) = old_key.filter(|old| *old != key) {
+            batch.delete_cf(&cf_handle, old_key);
+        }
+        batch.put_cf(&cf_handle, key, entry);
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Load a bridge outbox entry
+    pub fn get_outbox(&self, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let cf_handle = self.db.cf_handle(CF_OUTBOX).expect("missing CF");
+        Ok(self.db.get_cf(&cf_handle, key)?)
+    }
+
+    /// Load every bridge outbox entry whose key starts with `prefix`, with
+    /// its key
+    pub fn outbox_with_prefix(&self, prefix: &[u8]) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let cf_handle = self.db.cf_handle(CF_OUTBOX).expect("missing CF");
+        let mut entries = Vec::new();
+        let mode = IteratorMode::From(prefix, Direction::Forward);
+        for item in self.db.iterator_cf(&cf_handle, mode) {
+            let (key, value) = item?;
+            if !key.starts_with(prefix) {
+                break;
+            }
+            entries.push((key.to_vec(), value.to_vec()));
+        }
+        Ok(entries)
+    }
+
+    /// Delete bridge outbox entries in one atomic operation
+    pub fn delete_outbox(&self, keys: &[Vec<u8>]) -> anyhow::Result<()> {
+        let cf_handle = self.db.cf_handle(CF_OUTBOX).expect("missing CF");
+        let mut batch = WriteBatch::default();
+        for key in keys {
+            batch.delete_cf(&cf_handle, key);
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Write the genesis allocations, block 0 and its hash in one atomic
+    /// operation, so an interrupted init leaves nothing behind
+    pub fn init_genesis(
+        &self,
+        accounts: impl IntoIterator<Item = (Address, AccountState)>,
+        block: &Block,
+        spec_json: &str,
+    ) -> anyhow::Result<()> {
+        let state = self.db.cf_handle(CF_STATE).expect("missing CF");
+        let blocks = self.db.cf_handle(CF_BLOCKS).expect("missing CF");
+        let meta = self.db.cf_handle(CF_META).expect("missing CF");
+        let hash = block.header.hash;
+
+        let mut batch = WriteBatch::default();
+        for (address, account) in accounts {
+            batch.put_cf(&state, address.as_slice(), bincode::serialize(&account)?);
+        }
+        batch.put_cf(&blocks, 0u64.to_be_bytes(), bincode::serialize(block)?);
+        batch.put_cf(&meta, HEAD_KEY, bincode::serialize(&(0u64, hash.0))?);
+        batch.put_cf(&meta, GENESIS_SPEC_KEY, bincode::serialize(&spec_json.to_string())?);
+        batch.put_cf(&meta, GENESIS_HASH_KEY, bincode::serialize(&hash.0)?);
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Hash of the genesis block this database was initialised with
+    pub fn get_genesis_hash(&self) -> anyhow::Result<Option<B256>> {
+        Ok(self.get::<[u8; 32]>(CF_META, GENESIS_HASH_KEY)?.map(B256::from))
+    }
+
+    /// Genesis spec (JSON) this database was initialised with
+    pub fn get_genesis_spec(&self) -> anyhow::Result<Option<String>> {
+        self.get(CF_META, GENESIS_SPEC_KEY)
+    }
 }
@@ -0,0 +1,81 @@
+use anyhow::{anyhow, Result};
+use libp2p::{Multiaddr, PeerId};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Parse a `/ip4/.../tcp/.../p2p/<peer id>` address.
+pub fn parse_peer_addr(addr: &str) -> Result<(PeerId, Multiaddr)> {
+    let addr: Multiaddr = addr.parse()?;
+    let peer = PeerId::try_from_multiaddr(&addr)
+        .ok_or_else(|| anyhow!("{addr} does not end in /p2p/<peer id>"))?;
+    Ok((peer, addr))
+}
+
+struct Redial {
+    addr: Multiaddr,
+    /// Static peers are kept connected; bootnodes are only dialled while
+    /// the node has no peers at all.
+    persistent: bool,
+    backoff: Duration,
+    /// `None` while connected or while a dial is in flight.
+    next_attempt: Option<Instant>,
+}
+
+/// Configured peers the node dials, and re-dials with exponential backoff
+/// after failures and disconnects.
+pub struct Reconnector {
+    peers: HashMap<PeerId, Redial>,
+}
+
+impl Reconnector {
+    pub fn new(static_peers: &[(PeerId, Multiaddr)], bootnodes: &[(PeerId, Multiaddr)]) -> Self {
+        let now = Instant::now();
+        let mut peers = HashMap::new();
+        for (list, persistent) in [(bootnodes, false), (static_peers, true)] {
+            for (peer, addr) in list {
+                peers.insert(
+                    *peer,
+                    Redial {
+                        addr: addr.clone(),
+                        persistent,
+                        backoff: INITIAL_BACKOFF,
+                        next_attempt: Some(now),
+                    },
+                );
+            }
+        }
+        Self { peers }
+    }
+
+    pub fn on_connected(&mut self, peer: &PeerId) {
+        if let Some(redial) = self.peers.get_mut(peer) {
+            redial.backoff = INITIAL_BACKOFF;
+            redial.next_attempt = None;
+        }
+    }
+
+    /// The last connection to `peer` closed or a dial to it failed.
+    pub fn on_disconnected(&mut self, peer: &PeerId) {
+        if let Some(redial) = self.peers.get_mut(peer) {
+            redial.next_attempt = Some(Instant::now() + redial.backoff);
+            redial.backoff = (redial.backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// Peers to dial now, given how many peers are connected.
+    pub fn due(&mut self, connected: usize) -> Vec<(PeerId, Multiaddr)> {
+        let now = Instant::now();
+        self.peers
+            .iter_mut()
+            .filter(|(_, redial)| redial.persistent || connected == 0)
+            .filter(|(_, redial)| redial.next_attempt.map_or(false, |at| at <= now))
+            .map(|(peer, redial)| {
+                redial.next_attempt = None;
+                (*peer, redial.addr.clone())
+            })
+            .collect()
+    }
+}
@@ -1,58 +1,1061 @@
-use crate::types::{Block, HybridTx};
-use anyhow::Result;
+use crate::genesis::ChainConfig;
+use crate::inbound::{self, InboundBridge};
+use crate::parallel::{self, CanonicalState};
+use crate::precompile::PqPrecompiles;
+use crate::trace::{self, TraceConfig};
+use crate::merkle::{self, LeafValue, SparseMerkleTree, SparseProof};
+use crate::types::{AccountState, Block, BlockHeader, HybridTx, Receipt, INITIAL_BASE_FEE};
+use anyhow::{anyhow, Result};
 use revm::{
-    db::EmptyDB,
-    primitives::{Address, TransactTo, TxEnv, U256},
-    EVM,
+    db::{AccountState as DbAccountState, CacheDB, DatabaseRef, EmptyDB},
+    primitives::{
+        keccak256, AccountInfo, Address, BlockEnv, Bytecode, EVMError, Env, ExecutionResult,
+        HashMap, InvalidTransaction, ResultAndState, State, TransactTo,
+        TxEnv, B256, KECCAK_EMPTY, U256,
+    },
+    DatabaseCommit, EVM,
 };
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
 
-use std::sync::Mutex;
+/// Outcome of a single transaction, enough to build a receipt.
+#[derive(Debug, Clone)]
+pub struct TxReceipt {
+    pub tx_hash: B256,
+    pub effective_gas_price: U256,
+    pub gas_used: u64,
+    pub cumulative_gas_used: u64,
+    pub result: TxResult,
+}
+
+/// Result of a transaction in a block. `Err` means it failed validation (bad
+/// nonce, insufficient balance, ...): it was skipped without changing state
+/// or using gas, and the rest of the block executed normally.
+pub type TxResult = Result<ExecutionResult, InvalidTransaction>;
+
+impl TxReceipt {
+    /// The part of the outcome that is committed to and stored.
+    pub fn receipt(&self) -> Receipt {
+        let (success, logs) = match &self.result {
+            Ok(result) => (result.is_success(), result.logs()),
+            Err(_) => (false, Vec::new()),
+        };
+        Receipt {
+            tx_hash: self.tx_hash,
+            success,
+            cumulative_gas_used: self.cumulative_gas_used,
+            logs,
+        }
+    }
+}
+
+/// Execution-derived header fields and receipts of a block.
+#[derive(Debug, Clone)]
+pub struct BlockExecution {
+    pub base_fee_per_gas: U256,
+    pub gas_used: u64,
+    /// Base fee portion of the fees, burned (credited to nobody).
+    pub burnt_fees: U256,
+    /// Priority fees credited to the block coinbase.
+    pub priority_fees: U256,
+    pub receipts: Vec<TxReceipt>,
+    /// Merkle root of `receipts`.
+    pub receipts_root: B256,
+    /// Root of the state after the block.
+    pub state_root: B256,
+}
+
+/// State of the executor: accounts/storage live in memory.
+pub type StateDb = CacheDB<EmptyDB>;
+
+struct ExecState {
+    db: StateDb,
+    /// Commitment to `db`, updated from the accounts and slots each block
+    /// writes.
+    tree: StateTree,
+    /// Last executed header; its gas usage drives the next base fee.
+    parent: Option<BlockHeader>,
+    /// The most recent blocks, oldest first, for the tracers and proofs.
+    history: VecDeque<PastBlock>,
+}
+
+/// What an executed block needs to be traced again or proven against.
+struct PastBlock {
+    number: u64,
+    env: Env,
+    /// Undone newest first on a copy of the state, gives back the state
+    /// the block started from.
+    pre: PreImages,
+    /// Snapshot of the state tree after the block.
+    tree: StateTree,
+}
+
+/// State a block overwrote, as it was before the block: applied to the
+/// post-state of the block it gives back the pre-state.
+#[derive(Debug, Clone, Default)]
+pub struct PreImages {
+    /// Info and cache state of each account written; `None` if it was not
+    /// in the cache.
+    accounts: HashMap<Address, Option<(AccountInfo, DbAccountState)>>,
+    /// Prior value of each slot written.
+    storage: HashMap<Address, HashMap<U256, U256>>,
+    /// Whole prior storage of accounts created or destroyed by the block;
+    /// their later slot writes are not recorded one by one.
+    wiped: HashMap<Address, HashMap<U256, U256>>,
+}
+
+impl ExecState {
+    /// Copy of the state block `number` started from, with its environment,
+    /// if the block is within the kept history.
+    fn state_before(&self, number: u64) -> Option<(StateDb, Env)> {
+        let from = self.history.iter().position(|past| past.number == number)?;
+        let mut db = self.db.clone();
+        for past in self.history.iter().skip(from).rev() {
+            past.pre.undo(&mut db);
+        }
+        Some((db, self.history[from].env.clone()))
+    }
+}
+
+impl PreImages {
+    /// Record what committing `state` into `db` is about to overwrite. Only
+    /// the first write of the block to an account or slot is kept.
+    pub fn record(&mut self, db: &StateDb, state: &State) {
+        for (address, account) in state {
+            if !account.is_touched() {
+                continue;
+            }
+            self.record_account(db, *address);
+
+            let cached = db.accounts.get(address);
+            if account.is_created() || account.is_selfdestructed() {
+                self.wiped
+                    .entry(*address)
+                    .or_insert_with(|| cached.map(|a| a.storage.clone()).unwrap_or_default());
+            }
+            if self.wiped.contains_key(address) {
+                continue;
+            }
+            let slots = self.storage.entry(*address).or_default();
+            for (slot, value) in &account.storage {
+                if value.is_changed() {
+                    slots.entry(*slot).or_insert_with(|| {
+                        cached
+                            .and_then(|a| a.storage.get(slot).copied())
+                            .unwrap_or_default()
+                    });
+                }
+            }
+        }
+    }
+
+    /// Record the info of `address` before it is written outside a commit.
+    pub fn record_account(&mut self, db: &StateDb, address: Address) {
+        self.accounts.entry(address).or_insert_with(|| {
+            db.accounts
+                .get(&address)
+                .map(|a| (a.info.clone(), a.account_state.clone()))
+        });
+    }
+
+    /// Roll `db` back from the state after the recorded writes to the one
+    /// before them.
+    pub fn undo(&self, db: &mut StateDb) {
+        for (address, storage) in &self.wiped {
+            db.accounts.entry(*address).or_default().storage = storage.clone();
+        }
+        for (address, slots) in &self.storage {
+            let account = db.accounts.entry(*address).or_default();
+            account.storage.extend(slots.clone());
+        }
+        for (address, prior) in &self.accounts {
+            match prior {
+                Some((info, account_state)) => {
+                    let account = db.accounts.entry(*address).or_default();
+                    account.info = info.clone();
+                    account.account_state = account_state.clone();
+                }
+                None => {
+                    db.accounts.remove(address);
+                }
+            }
+        }
+    }
+}
 
 pub struct EvmExecutor {
-    inner: Mutex<Evm<'static, (), EmptyDB>>,
-    chain_id: u64,
+    inner: Mutex<ExecState>,
+    /// Chain id and the fork schedule selecting the spec of each block.
+    chain: ChainConfig,
+    /// Worker threads for parallel execution; 1 runs transactions in order.
+    workers: usize,
+    /// Number of recent blocks that can be traced.
+    trace_history: usize,
+    /// Applies the system transactions opening a block; without it they
+    /// are rejected.
+    inbound: Option<Arc<InboundBridge>>,
 }
 
 impl EvmExecutor {
-    pub fn new(chain_id: u64) -> Self {
+    pub fn new(
+        chain: ChainConfig,
+        workers: usize,
+        trace_history: usize,
+        inbound: Option<Arc<InboundBridge>>,
+    ) -> Self {
+        Self {
+            inner: Mutex::new(ExecState {
+                db: CacheDB::new(EmptyDB::default()),
+                tree: StateTree::default(),
+                parent: None,
+                history: VecDeque::new(),
+            }),
+            chain,
+            workers: workers.max(1),
+            trace_history,
+            inbound,
+        }
+    }
+
+    /// Seed the state with the genesis allocations; `genesis` becomes the
+    /// parent of the first executed block.
+    pub fn load_genesis(
+        &self,
+        accounts: Vec<(Address, AccountState)>,
+        genesis: &BlockHeader,
+    ) -> Result<()> {
+        let mut state = self.inner.lock().unwrap();
+
+        for (address, account) in accounts {
+            let code = Bytecode::new_raw(account.code);
+            let info = AccountInfo::new(account.balance, account.nonce, code.hash_slow(), code);
+            state.db.insert_account_info(address, info);
+            for (slot, value) in account.storage {
+                state.db.insert_account_storage(address, slot, value)?;
+            }
+        }
+
+        state.tree = state_tree(&parallel::canonical_state(&state.db));
+        state.parent = Some(genesis.clone());
+        Ok(())
+    }
+
+    /// Execute a committed block. Base fee is derived from the previously
+    /// executed block, so every node computes the same value.
+    pub fn execute_block(&self, block: &Block) -> Result<BlockExecution> {
+        let mut state = self.inner.lock().unwrap();
+
+        let base_fee = state
+            .parent
+            .as_ref()
+            .map(BlockHeader::next_base_fee)
+            .unwrap_or_else(|| U256::from(INITIAL_BASE_FEE));
+
+        let mut env = Env::default();
+        env.cfg.chain_id = self.chain.chain_id;
+        let spec = self.chain.spec_id(block.header.number, block.header.timestamp);
+        let parent_spec = state
+            .parent
+            .as_ref()
+            .map(|parent| self.chain.spec_id(parent.number, parent.timestamp));
+        if parent_spec.map_or(false, |parent_spec| parent_spec != spec) {
+            info!("Activated {spec:?} at block {}", block.header.number);
+        }
+        env.cfg.spec_id = spec;
+        env.block = Self::block_env(&block.header, base_fee);
+        let system = system_prefix(&block.txs);
+        let tx_envs: Vec<TxEnv> = block.txs[system..].iter().map(|tx| self.tx_env(tx)).collect();
+        let mut pre = PreImages::default();
+
+        let system_outputs =
+            self.apply_system(&mut state.db, &env, &block.txs[..system], &mut pre)?;
+        let outputs = if self.workers > 1 && tx_envs.len() > 1 {
+            parallel::execute_parallel(&mut state.db, &env, &tx_envs, self.workers, &mut pre)
+        } else {
+            execute_sequential(&mut state.db, &env, &tx_envs, &mut pre)
+        }?;
+
+        let mut gas_used = 0u64;
+        let mut burnt_fees = U256::ZERO;
+        let mut priority_fees = U256::ZERO;
+        let mut receipts = Vec::with_capacity(block.txs.len());
 
-        let db = EmptyDB::default();
-        let mut evm = EVM::new();
-        evm.database(db);
+        for (tx, out) in block.txs.iter().zip(system_outputs.into_iter().chain(outputs)) {
+            let tx_gas = out.as_ref().map_or(0, ExecutionResult::gas_used);
+            gas_used += tx_gas;
+            let effective_gas_price = if tx.is_system() {
+                U256::ZERO
+            } else {
+                let price = tx.effective_gas_price(base_fee);
+                burnt_fees += base_fee * U256::from(tx_gas);
+                priority_fees += (price - base_fee) * U256::from(tx_gas);
+                price
+            };
+
+            receipts.push(TxReceipt {
+                tx_hash: tx.hash,
+                effective_gas_price,
+                gas_used: tx_gas,
+                cumulative_gas_used: gas_used,
+                result: out,
+            });
+        }
+
+        let mut header = block.header.clone();
+        header.base_fee_per_gas = base_fee;
+        header.gas_used = gas_used;
+        state.parent = Some(header);
+
+        let ExecState { db, tree, .. } = &mut *state;
+        update_state_tree(tree, db, &pre);
+        let state_root = state.tree.root();
+
+        if self.trace_history > 0 {
+            let tree = state.tree.clone();
+            state.history.push_back(PastBlock {
+                number: block.header.number,
+                env,
+                pre,
+                tree,
+            });
+            while state.history.len() > self.trace_history {
+                state.history.pop_front();
+            }
+        }
+
+        Ok(BlockExecution {
+            base_fee_per_gas: base_fee,
+            gas_used,
+            burnt_fees,
+            priority_fees,
+            receipts_root: receipts_root(
+                &receipts.iter().map(TxReceipt::receipt).collect::<Vec<_>>(),
+            ),
+            receipts,
+            state_root,
+        })
+    }
 
+    /// Number of the last executed block (0 right after genesis).
+    pub fn head_number(&self) -> u64 {
+        let state = self.inner.lock().unwrap();
+        state.parent.as_ref().map_or(0, |parent| parent.number)
+    }
+
+    /// Root of the current state.
+    pub fn state_root(&self) -> B256 {
+        self.inner.lock().unwrap().tree.root()
+    }
 
+    /// Value of a storage slot in the current state.
+    pub fn storage(&self, address: Address, slot: U256) -> U256 {
+        let state = self.inner.lock().unwrap();
+        DatabaseRef::storage(&state.db, address, slot).unwrap_or_default()
+    }
+
+    /// Proof of `address` and its `slots` in the state after block `number`,
+    /// or after the last executed block. Past states are kept for the last
+    /// `trace_history` blocks.
+    pub fn get_proof(
+        &self,
+        address: Address,
+        slots: &[U256],
+        number: Option<u64>,
+    ) -> Result<AccountProof> {
+        // Only the snapshot is taken under the lock; execution goes on while
+        // the proof is built.
+        let tree = {
+            let state = self.inner.lock().unwrap();
+            let head = state.parent.as_ref().map_or(0, |parent| parent.number);
+            match number.filter(|n| *n != head) {
+                None => state.tree.clone(),
+                Some(n) => state
+                    .history
+                    .iter()
+                    .find(|past| past.number == n)
+                    .map(|past| past.tree.clone())
+                    .ok_or_else(|| anyhow!("state after block {n} is not available"))?,
+            }
+        };
+        Ok(account_proof(&tree, address, slots))
+    }
+
+    /// Re-execute `block` under the tracer selected by `config` and return
+    /// one trace per transaction, or only for the transaction at `only`.
+    /// System transactions are applied untraced, with `null` as their trace.
+    /// Works for the last `trace_history` executed blocks.
+    pub fn trace_block(
+        &self,
+        block: &Block,
+        only: Option<usize>,
+        config: &TraceConfig,
+    ) -> Result<Vec<Value>> {
+        let number = block.header.number;
+        let (mut db, env) = {
+            let state = self.inner.lock().unwrap();
+            state.state_before(number).ok_or_else(|| {
+                anyhow!(
+                    "state of block {number} is not available for tracing (last {} blocks are kept)",
+                    self.trace_history
+                )
+            })?
+        };
+
+        let system = system_prefix(&block.txs);
+        let tx_envs: Vec<TxEnv> = block.txs.iter().map(|tx| self.tx_env(tx)).collect();
+        let (start, end) = match only {
+            Some(index) if index < system => {
+                anyhow::bail!("transaction {index} of block {number} is a system transaction")
+            }
+            Some(index) if index < tx_envs.len() => (index, index + 1),
+            Some(index) => anyhow::bail!("block {number} has no transaction {index}"),
+            None => (system, tx_envs.len()),
+        };
+
+        // Transactions before the traced ones only need their state effects.
+        let mut scratch = PreImages::default();
+        self.apply_system(&mut db, &env, &block.txs[..system], &mut scratch)?;
+        execute_sequential(&mut db, &env, &tx_envs[system..start], &mut scratch)?;
+
+        let mut traces = vec![Value::Null; if only.is_some() { 0 } else { system }];
+        for tx in &tx_envs[start..end] {
+            let mut env = env.clone();
+            env.tx = tx.clone();
+            traces.push(trace::trace_tx(&mut db, &env, config)?);
+        }
+        Ok(traces)
+    }
+
+    /// Apply the system transactions opening a block, in order.
+    fn apply_system(
+        &self,
+        db: &mut StateDb,
+        env: &Env,
+        txs: &[HybridTx],
+        pre: &mut PreImages,
+    ) -> Result<Vec<TxResult>, EVMError<Infallible>> {
+        match &self.inbound {
+            Some(bridge) => bridge.apply_block(db, env, txs, pre),
+            None => Ok(txs
+                .iter()
+                .map(|tx| {
+                    warn!("Rejecting system tx {}: inbound transfers are disabled", tx.hash);
+                    inbound::rejected()
+                })
+                .collect()),
+        }
+    }
+
+    /// Block context seen by contracts (`NUMBER`, `TIMESTAMP`, `COINBASE`, ...).
+    fn block_env(header: &BlockHeader, base_fee: U256) -> BlockEnv {
+        let mut env = BlockEnv {
+            number: U256::from(header.number),
+            // revm burns the base fee and pays only the tip to the coinbase.
+            coinbase: header.coinbase,
+            timestamp: U256::from(header.timestamp),
+            gas_limit: U256::from(header.gas_limit),
+            basefee: base_fee,
+            difficulty: U256::ZERO,
+            prevrandao: Some(header.prevrandao),
+            blob_excess_gas_and_price: None,
+        };
+        // No blob transactions on this chain, but Cancun requires the field.
+        env.set_blob_excess_gas_and_price(0);
+        env
+    }
+
+    fn tx_env(&self, tx: &HybridTx) -> TxEnv {
+        let mut tx_env = TxEnv::default();
+        tx_env.caller = tx.from;
+        tx_env.transact_to = match tx.to {
+            Some(to) => TransactTo::Call(to),
+            None => TransactTo::create(),
+        };
+        tx_env.data = tx.data.clone();
+        tx_env.value = tx.value;
+        tx_env.nonce = Some(tx.nonce.saturating_to());
+        tx_env.gas_limit = tx.gas_limit;
+        // EIP-1559: revm charges min(max_fee, base_fee + priority_fee).
+        tx_env.gas_price = tx.max_fee_per_gas;
+        tx_env.gas_priority_fee = Some(tx.max_priority_fee_per_gas);
+        tx_env.chain_id = Some(self.chain.chain_id);
+        tx_env
+    }
+}
+
+/// Number of system transactions at the start of `txs`. Consensus orders
+/// them first; any further down run like user transactions and are skipped
+/// as invalid, since they pay no fee.
+fn system_prefix(txs: &[HybridTx]) -> usize {
+    txs.iter().take_while(|tx| tx.is_system()).count()
+}
+
+/// Sparse Merkle tree committing to a state: accounts keyed by the hash of
+/// their address (see `AccountLeaf`).
+pub type StateTree = SparseMerkleTree<AccountLeaf>;
+
+/// An account in the state tree, with the tree of its non-zero slots keyed
+/// by the hash of the slot and holding the value as 32 bytes.
+#[derive(Debug, Clone)]
+pub struct AccountLeaf {
+    pub balance: U256,
+    pub nonce: u64,
+    pub code_hash: B256,
+    pub storage: SparseMerkleTree<B256>,
+    hash: B256,
+}
+
+impl AccountLeaf {
+    fn new(balance: U256, nonce: u64, code_hash: B256, storage: SparseMerkleTree<B256>) -> Self {
+        let hash = account_hash(balance, nonce, code_hash, storage.root());
         Self {
-            inner: Mutex::new(evm),
-            chain_id,
+            balance,
+            nonce,
+            code_hash,
+            storage,
+            hash,
         }
     }
+}
 
-    pub fn execute_block(&self, block: &Block) -> Result<Vec<ExecutionResult>> {
-        let mut evm = self.inner.lock().unwrap();
+impl LeafValue for AccountLeaf {
+    fn value_hash(&self) -> B256 {
+        self.hash
+    }
+}
 
-        let mut results = Vec::with_capacity(block.txs.len());
+/// Hash of an account: balance (32 bytes), nonce (big-endian u64), code
+/// hash and storage root.
+pub fn account_hash(balance: U256, nonce: u64, code_hash: B256, storage_root: B256) -> B256 {
+    let mut data = Vec::with_capacity(32 + 8 + 32 + 32);
+    data.extend_from_slice(&balance.to_be_bytes::<32>());
+    data.extend_from_slice(&nonce.to_be_bytes());
+    data.extend_from_slice(code_hash.as_slice());
+    data.extend_from_slice(storage_root.as_slice());
+    keccak256(data)
+}
 
-        for tx in &block.txs {
-            let mut tx_env = TxEnv::default();
-            tx_env.caller = tx.from;
-            tx_env.transact_to = match tx.to {
-                Some(to) => TransactTo::Call(to),
-                None => TransactTo::Create,
-            };
-            tx_env.data = tx.data.clone();
-            tx_env.value = tx.value;
-            tx_env.nonce = tx.nonce;
-            tx_env.gas_limit = tx.gas_limit;
-            tx_env.gas_price = tx.max_fee_per_gas; // simplification
-            tx_env.chain_id = Some(U256::from(self.chain_id));
+/// Key of an account in the state tree.
+pub fn account_key(address: &Address) -> B256 {
+    keccak256(address)
+}
 
-            evm.context.evm.env.tx = tx_env;
+/// Key of a slot in an account's storage tree.
+pub fn slot_key(slot: &U256) -> B256 {
+    keccak256(slot.to_be_bytes::<32>())
+}
 
-            let out = evm.transact_commit()?; // commit state into DB
-            results.push(out);
+/// State tree of `state`, built from scratch.
+pub fn state_tree(state: &CanonicalState) -> StateTree {
+    let mut tree = StateTree::default();
+    for (address, (balance, nonce, code_hash, storage)) in state {
+        let mut slots = SparseMerkleTree::default();
+        for (slot, value) in storage {
+            slots.insert(slot_key(slot), B256::from(*value));
         }
+        tree.insert(
+            account_key(address),
+            AccountLeaf::new(*balance, *nonce, *code_hash, slots),
+        );
+    }
+    tree
+}
 
-        Ok(results)
+/// Root of the state tree of `state`.
+pub fn state_root(state: &CanonicalState) -> B256 {
+    state_tree(state).root()
+}
+
+/// Bring `tree` in line with `db` after a block that wrote what `pre`
+/// recorded, touching only those accounts and slots.
+fn update_state_tree(tree: &mut StateTree, db: &StateDb, pre: &PreImages) {
+    // Every account written is in `pre.accounts`, whatever else changed.
+    for address in pre.accounts.keys() {
+        let key = account_key(address);
+        let account = db.accounts.get(address);
+        let current = |slot: &U256| {
+            account
+                .and_then(|a| a.storage.get(slot).copied())
+                .unwrap_or_default()
+        };
+
+        let mut storage = SparseMerkleTree::default();
+        if pre.wiped.contains_key(address) {
+            for (slot, value) in account.iter().flat_map(|a| &a.storage) {
+                if *value != U256::ZERO {
+                    storage.insert(slot_key(slot), B256::from(*value));
+                }
+            }
+        } else {
+            if let Some(leaf) = tree.get(&key) {
+                storage = leaf.storage.clone();
+            }
+            for slot in pre.storage.get(address).into_iter().flat_map(|s| s.keys()) {
+                match current(slot) {
+                    U256::ZERO => storage.remove(&slot_key(slot)),
+                    value => storage.insert(slot_key(slot), B256::from(value)),
+                }
+            }
+        }
+
+        match account.and_then(|a| a.info()) {
+            Some(info) if !(info.is_empty() && storage.is_empty()) => tree.insert(
+                key,
+                AccountLeaf::new(info.balance, info.nonce, info.code_hash, storage),
+            ),
+            _ => tree.remove(&key),
+        }
     }
 }
 
+/// An account and some of its slots with their sparse Merkle proofs against
+/// the state root, as returned by `bridge_getStateProof`. Absent accounts
+/// and slots come with proofs of their absence.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountProof {
+    pub address: Address,
+    pub balance: U256,
+    pub nonce: u64,
+    pub code_hash: B256,
+    pub storage_hash: B256,
+    /// Path of `account_key(address)` through the state tree.
+    pub account_proof: SparseProof,
+    pub storage_proof: Vec<StorageProof>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageProof {
+    pub key: U256,
+    pub value: U256,
+    /// Path of `slot_key(key)` through the account's storage tree.
+    pub proof: SparseProof,
+}
+
+impl AccountProof {
+    /// Whether the account and each slot are as stated in the state with
+    /// root `state_root`, absent ones included.
+    pub fn verify(&self, state_root: B256) -> bool {
+        let key = account_key(&self.address);
+        let exists = self
+            .account_proof
+            .leaf
+            .as_ref()
+            .is_some_and(|leaf| leaf.key == key);
+        let account = exists
+            .then(|| account_hash(self.balance, self.nonce, self.code_hash, self.storage_hash));
+        let absent_is_empty = self.balance == U256::ZERO
+            && self.nonce == 0
+            && self.code_hash == KECCAK_EMPTY
+            && self.storage_hash == B256::ZERO;
+        if !self.account_proof.verify(state_root, &key, account) || !(exists || absent_is_empty) {
+            return false;
+        }
+        self.storage_proof.iter().all(|slot| {
+            let value = (slot.value != U256::ZERO).then(|| B256::from(slot.value));
+            let key = slot_key(&slot.key);
+            slot.proof.verify(self.storage_hash, &key, value)
+        })
+    }
+}
+
+/// Proofs of `address` and its `slots` in `tree`.
+pub fn account_proof(tree: &StateTree, address: Address, slots: &[U256]) -> AccountProof {
+    let key = account_key(&address);
+    let leaf = tree.get(&key);
+    AccountProof {
+        address,
+        balance: leaf.map_or(U256::ZERO, |leaf| leaf.balance),
+        nonce: leaf.map_or(0, |leaf| leaf.nonce),
+        code_hash: leaf.map_or(KECCAK_EMPTY, |leaf| leaf.code_hash),
+        storage_hash: leaf.map_or(B256::ZERO, |leaf| leaf.storage.root()),
+        account_proof: tree.prove(&key),
+        storage_proof: slots
+            .iter()
+            .map(|slot| {
+                let key = slot_key(slot);
+                StorageProof {
+                    key: *slot,
+                    value: leaf
+                        .and_then(|leaf| leaf.storage.get(&key))
+                        .map_or(U256::ZERO, |value| U256::from_be_bytes(value.0)),
+                    // An absent account has an empty storage tree.
+                    proof: leaf
+                        .map(|leaf| leaf.storage.prove(&key))
+                        .unwrap_or_default(),
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Merkle leaves of a block's receipts, in block order.
+pub fn receipt_leaves(receipts: &[Receipt]) -> Vec<B256> {
+    receipts
+        .iter()
+        .map(|receipt| merkle::leaf_hash(&receipt.encode()))
+        .collect()
+}
+
+/// Binary Merkle root over the encoded receipts of a block, in block order.
+pub fn receipts_root(receipts: &[Receipt]) -> B256 {
+    merkle::root(&receipt_leaves(receipts))
+}
+
+/// Run transactions one after another, committing each into `db` and
+/// recording what it overwrites in `pre`. Invalid transactions are skipped;
+/// only errors affecting the whole block (a bad block environment) abort.
+pub(crate) fn execute_sequential(
+    db: &mut StateDb,
+    env: &Env,
+    txs: &[TxEnv],
+    pre: &mut PreImages,
+) -> Result<Vec<TxResult>, EVMError<Infallible>> {
+    let mut evm = EVM::with_env(env.clone());
+    evm.database(db);
+
+    let mut outputs = Vec::with_capacity(txs.len());
+    for tx in txs {
+        evm.env.tx = tx.clone();
+        // Inspector hook serves the PQ precompiles.
+        outputs.push(match evm.inspect(PqPrecompiles) {
+            Ok(ResultAndState { result, state }) => {
+                let db = evm.db.as_mut().expect("database is set");
+                pre.record(db, &state);
+                db.commit(state);
+                Ok(result)
+            }
+            Err(EVMError::Transaction(invalid)) => Err(invalid),
+            Err(e) => return Err(e),
+        });
+    }
+    Ok(outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use revm::db::DatabaseRef;
+    use revm::primitives::Bytes;
+
+    const ETH: u64 = 1_000_000_000_000_000_000;
+    /// `storage[0] += 1`
+    const COUNTER: [u8; 8] = [0x5f, 0x54, 0x60, 0x01, 0x01, 0x5f, 0x55, 0x00];
+
+    fn account(n: u8) -> Address {
+        Address::repeat_byte(n)
+    }
+
+    fn counter() -> Address {
+        account(0xc0)
+    }
+
+    fn coinbase() -> Address {
+        account(0xfe)
+    }
+
+    fn executor(workers: usize) -> EvmExecutor {
+        executor_keeping(workers, 0)
+    }
+
+    /// Executor keeping the state of the last `trace_history` blocks.
+    fn executor_keeping(workers: usize, trace_history: usize) -> EvmExecutor {
+        let chain: ChainConfig = serde_json::from_value(serde_json::json!({
+            "chainId": 1337,
+            "shanghaiTime": 0,
+            "cancunTime": 0,
+        }))
+        .unwrap();
+        let executor = EvmExecutor::new(chain, workers, trace_history, None);
+
+        let funded = |balance: u64| AccountState {
+            balance: U256::from(balance),
+            ..AccountState::default()
+        };
+        let accounts = vec![
+            (account(1), funded(ETH)),
+            (account(2), funded(ETH)),
+            (account(3), funded(ETH)),
+            // Cannot cover its gas.
+            (account(4), funded(1_000)),
+            (account(5), funded(ETH)),
+            (
+                counter(),
+                AccountState {
+                    code: COUNTER.to_vec().into(),
+                    ..AccountState::default()
+                },
+            ),
+        ];
+        executor.load_genesis(accounts, &header(0)).unwrap();
+        executor
+    }
+
+    fn header(number: u64) -> BlockHeader {
+        BlockHeader {
+            number,
+            hash: B256::ZERO,
+            parent_hash: B256::ZERO,
+            state_root: B256::ZERO,
+            tx_root: B256::ZERO,
+            timestamp: number,
+            coinbase: coinbase(),
+            prevrandao: B256::ZERO,
+            gas_limit: 30_000_000,
+            // At the target, so the next block keeps the same base fee.
+            gas_used: 15_000_000,
+            base_fee_per_gas: U256::from(INITIAL_BASE_FEE),
+        }
+    }
+
+    fn tx(from: u8, nonce: u64, to: Address, value: u64, max_fee: u64) -> HybridTx {
+        HybridTx {
+            hash: B256::with_last_byte(from),
+            from: account(from),
+            to: Some(to),
+            nonce: U256::from(nonce),
+            gas_limit: 100_000,
+            max_fee_per_gas: U256::from(max_fee),
+            max_priority_fee_per_gas: U256::from(2),
+            value: U256::from(value),
+            data: Bytes::new(),
+            chain_id: 1337,
+            sig: None,
+            pq_sig: None,
+            pq_pubkey: None,
+        }
+    }
+
+    fn basic(executor: &EvmExecutor, address: Address) -> AccountInfo {
+        let state = executor.inner.lock().unwrap();
+        state.db.basic(address).unwrap().unwrap_or_default()
+    }
+
+    fn valid_txs() -> Vec<HybridTx> {
+        let fee = 2 * INITIAL_BASE_FEE;
+        vec![
+            tx(1, 0, account(9), 1_000, fee),
+            tx(2, 0, counter(), 0, fee),
+            tx(1, 1, counter(), 0, fee),
+        ]
+    }
+
+    /// Valid transactions interleaved with ones failing validation: a nonce
+    /// gap, a sender that cannot pay for gas, a fee cap under the base fee.
+    fn mixed_txs() -> Vec<HybridTx> {
+        let fee = 2 * INITIAL_BASE_FEE;
+        let valid = valid_txs();
+        vec![
+            valid[0].clone(),
+            tx(3, 7, account(9), 1_000, fee),
+            valid[1].clone(),
+            tx(4, 0, account(9), 0, fee),
+            tx(5, 0, counter(), 0, INITIAL_BASE_FEE - 1),
+            valid[2].clone(),
+        ]
+    }
+
+    fn assert_invalid_txs_are_skipped(workers: usize) {
+        let mixed = executor(workers);
+        let block = Block {
+            header: header(1),
+            txs: mixed_txs(),
+        };
+        let execution = mixed.execute_block(&block).unwrap();
+
+        let expected = executor(workers);
+        let expected_execution = expected
+            .execute_block(&Block {
+                header: header(1),
+                txs: valid_txs(),
+            })
+            .unwrap();
+
+        let receipts = &execution.receipts;
+        assert!(matches!(
+            receipts[1].result,
+            Err(InvalidTransaction::NonceTooHigh { .. })
+        ));
+        assert!(matches!(
+            receipts[3].result,
+            Err(InvalidTransaction::LackOfFundForMaxFee { .. })
+        ));
+        assert!(matches!(
+            receipts[4].result,
+            Err(InvalidTransaction::GasPriceLessThanBasefee)
+        ));
+        for invalid in [1, 3, 4] {
+            assert_eq!(receipts[invalid].gas_used, 0);
+            assert_eq!(
+                receipts[invalid].cumulative_gas_used,
+                receipts[invalid - 1].cumulative_gas_used
+            );
+        }
+
+        // The valid transactions ran exactly as they would on their own.
+        let valid: Vec<_> = [0, 2, 5].iter().map(|&i| &receipts[i]).collect();
+        for (receipt, expected) in valid.iter().zip(&expected_execution.receipts) {
+            assert_eq!(receipt.result, expected.result);
+            assert!(receipt.result.as_ref().unwrap().is_success());
+        }
+        assert_eq!(execution.gas_used, expected_execution.gas_used);
+        assert_eq!(execution.burnt_fees, expected_execution.burnt_fees);
+        assert_eq!(execution.priority_fees, expected_execution.priority_fees);
+        assert_eq!(execution.state_root, expected_execution.state_root);
+
+        // Skipped senders paid nothing and kept their nonce.
+        for (sender, balance) in [(3, ETH), (4, 1_000), (5, ETH)] {
+            let info = basic(&mixed, account(sender));
+            assert_eq!(info.balance, U256::from(balance));
+            assert_eq!(info.nonce, 0);
+        }
+        assert_eq!(basic(&mixed, coinbase()).balance, execution.priority_fees);
+    }
+
+    #[test]
+    fn invalid_txs_are_skipped_sequentially() {
+        assert_invalid_txs_are_skipped(1);
+    }
+
+    #[test]
+    fn invalid_txs_are_skipped_in_parallel() {
+        assert_invalid_txs_are_skipped(4);
+    }
+
+    /// Blocks with transfers, storage writes and a contract creation.
+    fn varied_blocks() -> [Vec<HybridTx>; 4] {
+        let fee = 2 * INITIAL_BASE_FEE;
+        // Init code running `storage[0] = 1` and deploying no code.
+        let mut create = tx(5, 0, counter(), 0, fee);
+        create.to = None;
+        create.data = Bytes::from(vec![0x60, 0x01, 0x5f, 0x55, 0x5f, 0x5f, 0xf3]);
+        // Funded beforehand, so the creation wipes an existing account.
+        let created = account(5).create(0);
+        let mut first = valid_txs();
+        first.push(tx(3, 0, created, 5, fee));
+        [
+            first,
+            vec![create, tx(2, 1, counter(), 0, fee)],
+            vec![tx(1, 2, account(7), 5, fee), tx(3, 1, account(1), 5, fee)],
+            vec![tx(3, 2, counter(), 0, fee)],
+        ]
+    }
+
+    fn assert_history_restores_pre_state(workers: usize) {
+        let executor = executor_keeping(workers, 3);
+        let mut before = Vec::new();
+        for (i, txs) in varied_blocks().into_iter().enumerate() {
+            before.push(parallel::canonical_state(
+                &executor.inner.lock().unwrap().db,
+            ));
+            let block = Block {
+                header: header(i as u64 + 1),
+                txs,
+            };
+            executor.execute_block(&block).unwrap();
+        }
+
+        let state = executor.inner.lock().unwrap();
+        assert!(state.state_before(1).is_none());
+        for number in 2..=4 {
+            let (db, env) = state.state_before(number).unwrap();
+            assert_eq!(env.block.number, U256::from(number));
+            assert_eq!(parallel::canonical_state(&db), before[number as usize - 1]);
+        }
+    }
+
+    #[test]
+    fn history_restores_pre_state_sequentially() {
+        assert_history_restores_pre_state(1);
+    }
+
+    #[test]
+    fn history_restores_pre_state_in_parallel() {
+        assert_history_restores_pre_state(4);
+    }
+
+    #[test]
+    fn proofs_verify_against_state_roots() {
+        let executor = executor_keeping(4, 3);
+        let mut roots = Vec::new();
+        for (i, txs) in varied_blocks().into_iter().enumerate() {
+            let block = Block {
+                header: header(i as u64 + 1),
+                txs,
+            };
+            roots.push(executor.execute_block(&block).unwrap().state_root);
+        }
+
+        let slots = [U256::ZERO, U256::from(1)];
+        // Block 1 has left the history.
+        assert!(executor.get_proof(account(1), &slots, Some(1)).is_err());
+        for (number, root) in (1..).zip(&roots).skip(1) {
+            let latest = (number == roots.len() as u64).then_some(None);
+            for at in latest.into_iter().chain([Some(number)]) {
+                for address in [account(1), counter(), account(5).create(0), account(0xaa)] {
+                    let proof = executor.get_proof(address, &slots, at).unwrap();
+                    assert!(proof.verify(*root), "{address} at block {number}");
+                    assert!(!proof.verify(B256::ZERO));
+                }
+            }
+        }
+
+        let proof = executor.get_proof(counter(), &slots, None).unwrap();
+        assert_eq!(proof.storage_proof[0].value, U256::from(4));
+        assert_eq!(proof.storage_proof[1].value, U256::ZERO);
+        let mut forged = proof.clone();
+        forged.storage_proof[0].value = U256::from(5);
+        assert!(!forged.verify(roots[3]));
+        let mut forged = proof.clone();
+        forged.storage_proof[1].value = U256::from(1);
+        assert!(!forged.verify(roots[3]));
+        let mut forged = proof;
+        forged.balance = U256::from(1);
+        assert!(!forged.verify(roots[3]));
+
+        let absent = executor.get_proof(account(0xaa), &slots, None).unwrap();
+        let mut forged = absent;
+        forged.storage_proof[0].value = U256::from(1);
+        assert!(!forged.verify(roots[3]));
+    }
+
+    #[test]
+    fn receipt_proofs_verify_against_receipts_root() {
+        let execution = executor(1)
+            .execute_block(&Block {
+                header: header(1),
+                txs: mixed_txs(),
+            })
+            .unwrap();
+        let receipts: Vec<Receipt> = execution.receipts.iter().map(TxReceipt::receipt).collect();
+        let leaves = receipt_leaves(&receipts);
+        let root = execution.receipts_root;
+        for (index, leaf) in leaves.iter().enumerate() {
+            let proof = merkle::proof(&leaves, index).unwrap();
+            assert!(merkle::verify(root, *leaf, index, &proof));
+            let other = leaves[(index + 1) % leaves.len()];
+            assert!(!merkle::verify(root, other, index, &proof));
+        }
+    }
+
+    #[test]
+    fn state_root_is_maintained_incrementally() {
+        let executor = executor(4);
+        for (i, txs) in varied_blocks().into_iter().enumerate() {
+            let block = Block {
+                header: header(i as u64 + 1),
+                txs,
+            };
+            let execution = executor.execute_block(&block).unwrap();
+            let rebuilt = state_root(&parallel::canonical_state(
+                &executor.inner.lock().unwrap().db,
+            ));
+            assert_eq!(execution.state_root, rebuilt);
+        }
+    }
+}
@@ -0,0 +1,395 @@
+use crate::bridge::contract::{self, BRIDGE_ADDRESS};
+use crate::config::{ForkOverrides, GuardianSetConfig, NodeConfig, ValidatorConfig};
+use crate::db::ChainStore;
+use crate::evm;
+use crate::parallel::CanonicalState;
+use crate::types::{AccountState, Block, BlockHeader, INITIAL_BASE_FEE};
+use anyhow::{bail, ensure, Context, Result};
+use revm::primitives::{Address, Bytecode, Bytes, SpecId, B256, U256};
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use tracing::info;
+
+/// Genesis spec. Follows Geth's `genesis.json` layout where practical and adds
+/// the initial validator set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Genesis {
+    pub config: ChainConfig,
+    #[serde(default, deserialize_with = "quantity")]
+    pub timestamp: u64,
+    #[serde(deserialize_with = "quantity")]
+    pub gas_limit: u64,
+    #[serde(default)]
+    pub base_fee_per_gas: Option<U256>,
+    #[serde(default)]
+    pub coinbase: Address,
+    #[serde(default)]
+    pub mix_hash: B256,
+    #[serde(default)]
+    pub extra_data: Bytes,
+    #[serde(default)]
+    pub alloc: BTreeMap<Address, GenesisAccount>,
+    /// Not part of Geth's format.
+    #[serde(default)]
+    pub validators: Vec<ValidatorConfig>,
+    /// Where the token bridge contract is allocated, unless `alloc` already
+    /// has an account there. Not part of Geth's format.
+    #[serde(default)]
+    pub bridge_address: Option<Address>,
+    /// Signers of inbound transfers; none disables them. Not part of Geth's
+    /// format.
+    #[serde(default)]
+    pub guardians: Option<GuardianSetConfig>,
+}
+
+/// Chain id and fork activations. Shanghai and Cancun activate by block
+/// timestamp as in Geth (`shanghaiTime`, `cancunTime`), or by number.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainConfig {
+    pub chain_id: u64,
+    #[serde(default)]
+    pub london_block: Option<u64>,
+    #[serde(default)]
+    pub shanghai_time: Option<u64>,
+    #[serde(default)]
+    pub cancun_time: Option<u64>,
+    #[serde(default)]
+    pub shanghai_block: Option<u64>,
+    #[serde(default)]
+    pub cancun_block: Option<u64>,
+    /// Remaining Geth fields, checked by `validate` so that a fork this
+    /// chain does not know is refused rather than silently left inactive.
+    #[serde(flatten)]
+    pub other: BTreeMap<String, serde_json::Value>,
+}
+
+/// Geth's pre-merge forks; all of them are part of the genesis spec here,
+/// so they may only be set to block 0.
+const LEGACY_FORKS: &[&str] = &[
+    "homesteadBlock",
+    "daoForkBlock",
+    "eip150Block",
+    "eip155Block",
+    "eip158Block",
+    "byzantiumBlock",
+    "constantinopleBlock",
+    "petersburgBlock",
+    "istanbulBlock",
+    "muirGlacierBlock",
+    "berlinBlock",
+    "arrowGlacierBlock",
+    "grayGlacierBlock",
+    "mergeNetsplitBlock",
+];
+
+impl ChainConfig {
+    /// revm spec in force for the block with the given number and timestamp.
+    /// The base fee market and consensus randomness exist from genesis, so
+    /// Paris is the oldest spec.
+    pub fn spec_id(&self, number: u64, timestamp: u64) -> SpecId {
+        let active = |block: Option<u64>, time: Option<u64>| {
+            block.map_or(false, |block| number >= block)
+                || time.map_or(false, |time| timestamp >= time)
+        };
+        if active(self.cancun_block, self.cancun_time) {
+            SpecId::CANCUN
+        } else if active(self.shanghai_block, self.shanghai_time) {
+            SpecId::SHANGHAI
+        } else {
+            SpecId::MERGE
+        }
+    }
+
+    /// Reject schedules this chain cannot run.
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            self.london_block.unwrap_or(0) == 0,
+            "londonBlock must be 0: EIP-1559 is active from genesis"
+        );
+        for (name, value) in &self.other {
+            if LEGACY_FORKS.contains(&name.as_str()) {
+                ensure!(
+                    value.is_null() || value.as_u64() == Some(0),
+                    "{name} must be 0: pre-merge forks are active from genesis"
+                );
+            } else if name.ends_with("Block") || name.ends_with("Time") {
+                bail!("unsupported fork {name}");
+            }
+        }
+        ensure!(
+            self.shanghai_block.is_none() || self.shanghai_time.is_none(),
+            "shanghaiBlock and shanghaiTime are both set"
+        );
+        ensure!(
+            self.cancun_block.is_none() || self.cancun_time.is_none(),
+            "cancunBlock and cancunTime are both set"
+        );
+        match (self.shanghai_block, self.cancun_block) {
+            (Some(shanghai), Some(cancun)) => ensure!(
+                shanghai <= cancun,
+                "cancunBlock ({cancun}) is before shanghaiBlock ({shanghai})"
+            ),
+            (None, Some(_)) => bail!("cancunBlock is set without shanghaiBlock"),
+            _ => {}
+        }
+        match (self.shanghai_time, self.cancun_time) {
+            (Some(shanghai), Some(cancun)) => ensure!(
+                shanghai <= cancun,
+                "cancunTime ({cancun}) is before shanghaiTime ({shanghai})"
+            ),
+            (None, Some(_)) => bail!("cancunTime is set without shanghaiTime"),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Apply operator-supplied activations over the genesis schedule. An
+    /// override replaces the fork's genesis activation of either kind.
+    pub fn apply_overrides(&mut self, overrides: &ForkOverrides) {
+        if overrides.shanghai_block.is_some() || overrides.shanghai_time.is_some() {
+            self.shanghai_block = overrides.shanghai_block;
+            self.shanghai_time = overrides.shanghai_time;
+        }
+        if overrides.cancun_block.is_some() || overrides.cancun_time.is_some() {
+            self.cancun_block = overrides.cancun_block;
+            self.cancun_time = overrides.cancun_time;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenesisAccount {
+    #[serde(default)]
+    pub balance: U256,
+    #[serde(default, deserialize_with = "quantity")]
+    pub nonce: u64,
+    #[serde(default)]
+    pub code: Bytes,
+    #[serde(default)]
+    pub storage: BTreeMap<B256, B256>,
+}
+
+impl Genesis {
+    pub fn load(path: &str) -> Result<Self> {
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("reading genesis file {path}"))?;
+        serde_json::from_str(&raw).with_context(|| format!("parsing genesis file {path}"))
+    }
+
+    /// Empty devnet genesis built from the node config.
+    pub fn dev(cfg: &NodeConfig) -> Self {
+        Self {
+            config: ChainConfig {
+                chain_id: cfg.chain_id,
+                london_block: Some(0),
+                shanghai_time: Some(0),
+                cancun_time: Some(0),
+                shanghai_block: None,
+                cancun_block: None,
+                other: BTreeMap::new(),
+            },
+            timestamp: 0,
+            gas_limit: cfg.block_gas_limit,
+            base_fee_per_gas: None,
+            coinbase: Address::ZERO,
+            mix_hash: B256::ZERO,
+            extra_data: Bytes::new(),
+            alloc: BTreeMap::new(),
+            validators: cfg.validators.clone(),
+            bridge_address: Some(BRIDGE_ADDRESS),
+            guardians: cfg.bridges.guardians.clone(),
+        }
+    }
+
+    /// Allocations in the form stored in `ChainStore`, the bridge contract
+    /// included.
+    pub fn accounts(&self) -> impl Iterator<Item = (Address, AccountState)> + '_ {
+        let bridge = self
+            .bridge_address
+            .filter(|address| !self.alloc.contains_key(address))
+            .map(|address| {
+                let account = AccountState {
+                    code: contract::runtime_code(),
+                    ..AccountState::default()
+                };
+                (address, account)
+            });
+        self.alloc.iter().map(|(address, account)| {
+            let state = AccountState {
+                balance: account.balance,
+                nonce: account.nonce,
+                code: account.code.clone(),
+                storage: account
+                    .storage
+                    .iter()
+                    .map(|(k, v)| (U256::from_be_bytes(k.0), U256::from_be_bytes(v.0)))
+                    .collect(),
+            };
+            (*address, state)
+        })
+        .chain(bridge)
+    }
+
+    /// Root of the genesis allocations, as the executor computes it once
+    /// they are loaded.
+    fn state_root(&self) -> B256 {
+        let state: CanonicalState = self
+            .accounts()
+            .filter_map(|(address, account)| {
+                let code_hash = Bytecode::new_raw(account.code.clone()).hash_slow();
+                let storage: BTreeMap<U256, U256> = account
+                    .storage
+                    .into_iter()
+                    .filter(|(_, value)| *value != U256::ZERO)
+                    .collect();
+                let empty = account.balance == U256::ZERO
+                    && account.nonce == 0
+                    && account.code.is_empty();
+                if empty && storage.is_empty() {
+                    return None;
+                }
+                Some((address, (account.balance, account.nonce, code_hash, storage)))
+            })
+            .collect();
+        evm::state_root(&state)
+    }
+
+    /// Block 0 described by this spec.
+    pub fn to_block(&self) -> Block {
+        let mut header = BlockHeader {
+            number: 0,
+            hash: B256::ZERO,
+            parent_hash: B256::ZERO,
+            state_root: self.state_root(),
+            tx_root: B256::ZERO,
+            timestamp: self.timestamp,
+            coinbase: self.coinbase,
+            prevrandao: self.mix_hash,
+            gas_limit: self.gas_limit,
+            gas_used: 0,
+            base_fee_per_gas: self
+                .base_fee_per_gas
+                .unwrap_or_else(|| U256::from(INITIAL_BASE_FEE)),
+        };
+
+        let mut hasher = Sha256::new();
+        hasher.update(self.config.chain_id.to_be_bytes());
+        hasher.update(bincode::serialize(&header).expect("header serializes"));
+        hasher.update(&self.extra_data);
+        header.hash = B256::from_slice(&hasher.finalize());
+
+        Block { header, txs: vec![] }
+    }
+}
+
+/// Write block 0 and the genesis allocations into `store`. Idempotent for the
+/// same spec; refuses to touch a database initialised with a different one.
+pub fn init(store: &ChainStore, genesis: &Genesis) -> Result<Block> {
+    genesis.config.validate()?;
+    let block = genesis.to_block();
+    let hash = block.header.hash;
+
+    match store.get_genesis_hash()? {
+        Some(stored) if stored == hash => return Ok(block),
+        Some(stored) => bail!(
+            "genesis mismatch: database was initialised with 0x{}, spec hashes to 0x{}",
+            hex::encode(stored),
+            hex::encode(hash)
+        ),
+        None => {}
+    }
+
+    // One write: a crash mid-init leaves the database uninitialised.
+    store.init_genesis(genesis.accounts(), &block, &serde_json::to_string(genesis)?)?;
+
+    info!(
+        "Initialised chain {} with genesis 0x{} ({} accounts)",
+        genesis.config.chain_id,
+        hex::encode(hash),
+        genesis.alloc.len()
+    );
+    Ok(block)
+}
+
+/// Resolve the genesis for this start: the configured file, else the one the
+/// database was initialised with, else an empty devnet genesis. The result is
+/// checked against the stored genesis hash; fork overrides from `cfg` are
+/// applied afterwards, as they are not part of block 0.
+pub fn load_or_init(store: &ChainStore, cfg: &NodeConfig) -> Result<(Genesis, Block)> {
+    let mut genesis = match &cfg.genesis_path {
+        Some(path) => Genesis::load(path)?,
+        None => match store.get_genesis_spec()? {
+            Some(spec) => serde_json::from_str(&spec)?,
+            None => Genesis::dev(cfg),
+        },
+    };
+    let block = init(store, &genesis)?;
+    genesis.config.apply_overrides(&cfg.fork_overrides);
+    genesis.config.validate()?;
+    Ok((genesis, block))
+}
+
+/// Accepts JSON numbers as well as Geth-style hex quantities ("0x1c9c380").
+fn quantity<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Quantity {
+        Num(u64),
+        Str(String),
+    }
+
+    match Quantity::deserialize(deserializer)? {
+        Quantity::Num(n) => Ok(n),
+        Quantity::Str(s) => match s.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => s.parse(),
+        }
+        .map_err(serde::de::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain_config(json: &str) -> ChainConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn geth_time_based_forks() {
+        let config = chain_config(
+            r#"{"chainId": 1337, "homesteadBlock": 0, "londonBlock": 0,
+                "shanghaiTime": 100, "cancunTime": 200}"#,
+        );
+        config.validate().unwrap();
+        assert_eq!(config.spec_id(5, 99), SpecId::MERGE);
+        assert_eq!(config.spec_id(5, 100), SpecId::SHANGHAI);
+        assert_eq!(config.spec_id(5, 200), SpecId::CANCUN);
+    }
+
+    #[test]
+    fn unknown_forks_are_rejected() {
+        let config = chain_config(r#"{"chainId": 1337, "pragueTime": 0}"#);
+        assert!(config.validate().is_err());
+        let config = chain_config(r#"{"chainId": 1337, "berlinBlock": 5}"#);
+        assert!(config.validate().is_err());
+        let config = chain_config(r#"{"chainId": 1337, "cancunTime": 0}"#);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn overrides_replace_either_kind() {
+        let mut config = chain_config(r#"{"chainId": 1337, "shanghaiTime": 100}"#);
+        config.apply_overrides(&ForkOverrides {
+            shanghai_block: Some(10),
+            ..ForkOverrides::default()
+        });
+        config.validate().unwrap();
+        assert_eq!(config.shanghai_time, None);
+        assert_eq!(config.spec_id(10, 0), SpecId::SHANGHAI);
+    }
+}
@@ -0,0 +1,552 @@
+use crate::config::{GuardianSetConfig, NodeConfig, ValidatorConfig};
+use crate::crypto::{sign_mldsa, verify_mldsa, MlDsaLevel};
+use crate::types::{
+    AttestationVote, BlockAttestation, CommitCertificate, CommitVote, InboundMessage,
+    InboundTransfer, NarwhalBatch,
+};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use libp2p::{identity, PeerId};
+use revm::primitives::B256;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use tracing::info;
+
+/// Domain separator of the handshake signature.
+const HANDSHAKE_DOMAIN: &[u8] = b"narwhal-evm/pq-handshake/v1";
+/// Domain separator of batch signatures.
+const BATCH_DOMAIN: &[u8] = b"narwhal-evm/batch/v1";
+/// Domain separator of commit votes.
+const COMMIT_DOMAIN: &[u8] = b"narwhal-evm/commit/v1";
+/// Domain separator of block attestations.
+const ATTESTATION_DOMAIN: &[u8] = b"narwhal-evm/attestation/v1";
+/// Domain separator of guardian-signed inbound transfers.
+const INBOUND_DOMAIN: &[u8] = b"narwhal-evm/inbound/v1";
+
+/// libp2p identity of this node: derived from `node_key_seed` if set,
+/// otherwise read from `node_key_path`, which is created on first start.
+pub fn load_node_key(cfg: &NodeConfig) -> Result<identity::Keypair> {
+    if let Some(seed) = &cfg.node_key_seed {
+        let secret = identity::ed25519::SecretKey::from_bytes(Sha256::digest(seed.as_bytes()))
+            .map_err(|e| anyhow!("deriving node key from seed: {e}"))?;
+        return Ok(identity::Keypair::Ed25519(secret.into()));
+    }
+
+    let Some(path) = &cfg.node_key_path else {
+        return Ok(identity::Keypair::generate_ed25519());
+    };
+    if Path::new(path).exists() {
+        let bytes = std::fs::read(path).with_context(|| format!("reading node key {path}"))?;
+        return identity::Keypair::from_protobuf_encoding(&bytes)
+            .with_context(|| format!("decoding node key {path}"));
+    }
+
+    let key = identity::Keypair::generate_ed25519();
+    if let Some(dir) = Path::new(path).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, key.to_protobuf_encoding()?)
+        .with_context(|| format!("writing node key {path}"))?;
+    info!("Generated node key {path}");
+    Ok(key)
+}
+
+fn mldsa_level(pubkey: &[u8]) -> Result<MlDsaLevel> {
+    MlDsaLevel::from_public_key_len(pubkey.len())
+        .ok_or_else(|| anyhow!("{} bytes is not an ML-DSA public key", pubkey.len()))
+}
+
+/// ML-DSA identity of the validator this node runs as.
+pub struct ValidatorKey {
+    pub id: String,
+    chain_id: u64,
+    level: MlDsaLevel,
+    secret: Vec<u8>,
+}
+
+impl ValidatorKey {
+    /// Load the secret key from `pq_secret_key_path` and check it against the
+    /// public key configured for `validator_id`. `None` if this node is not in
+    /// the validator set.
+    pub fn load(cfg: &NodeConfig) -> Result<Option<Self>> {
+        let Some(validator) = cfg.validators.iter().find(|v| v.id == cfg.validator_id) else {
+            return Ok(None);
+        };
+        let Some(path) = &cfg.pq_secret_key_path else {
+            bail!("validator {} has no pq_secret_key_path", validator.id);
+        };
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("reading ML-DSA secret key {path}"))?;
+        let secret = hex::decode(raw.trim()).with_context(|| format!("decoding {path}"))?;
+
+        let pubkey = hex::decode(&validator.pq_pubkey_hex)?;
+        let key = Self {
+            id: validator.id.clone(),
+            chain_id: cfg.chain_id,
+            level: mldsa_level(&pubkey)?,
+            secret,
+        };
+
+        let probe = key.sign(HANDSHAKE_DOMAIN)?;
+        verify_mldsa(key.level, &pubkey, HANDSHAKE_DOMAIN, &probe).map_err(|_| {
+            anyhow!("ML-DSA secret key {path} does not match the public key of {}", key.id)
+        })?;
+        Ok(Some(key))
+    }
+
+    pub fn sign(&self, msg: &[u8]) -> Result<Vec<u8>> {
+        Ok(sign_mldsa(self.level, &self.secret, msg)?)
+    }
+
+    /// Sign a batch this validator authored.
+    pub fn sign_batch(&self, batch: &NarwhalBatch) -> Result<Vec<u8>> {
+        self.sign(&batch_message(self.chain_id, batch))
+    }
+
+    /// Vote for a block this validator committed.
+    pub fn sign_commit(&self, number: u64, block_hash: B256) -> Result<CommitVote> {
+        Ok(CommitVote {
+            number,
+            block_hash,
+            validator: self.id.clone(),
+            signature: self.sign(&commit_message(self.chain_id, number, block_hash))?,
+        })
+    }
+
+    /// Attest to the outcome of a block this validator executed.
+    pub fn sign_attestation(&self, attestation: BlockAttestation) -> Result<AttestationVote> {
+        Ok(AttestationVote {
+            signature: self.sign(&attestation_message(&attestation))?,
+            attestation,
+            validator: self.id.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+impl ValidatorKey {
+    /// A fresh ML-DSA-44 key for validator `id`, with its config entry.
+    pub fn generate(chain_id: u64, id: &str, stake: u64) -> (Self, ValidatorConfig) {
+        use pqcrypto_traits::sign::{PublicKey, SecretKey};
+
+        let (pk, sk) = pqcrypto_mldsa::mldsa44::keypair();
+        let key = Self {
+            id: id.to_string(),
+            chain_id,
+            level: MlDsaLevel::MlDsa44,
+            secret: sk.as_bytes().to_vec(),
+        };
+        let config = ValidatorConfig {
+            id: id.to_string(),
+            stake,
+            pq_pubkey_hex: hex::encode(pk.as_bytes()),
+            fee_recipient: Default::default(),
+        };
+        (key, config)
+    }
+}
+
+/// Signed bytes of an attestation, laid out for destination chains to
+/// rebuild: the domain, then chain id and number as big-endian u64s, then the
+/// block hash, state root and receipts root.
+fn attestation_message(attestation: &BlockAttestation) -> Vec<u8> {
+    let mut msg = ATTESTATION_DOMAIN.to_vec();
+    msg.extend_from_slice(&attestation.chain_id.to_be_bytes());
+    msg.extend_from_slice(&attestation.number.to_be_bytes());
+    msg.extend_from_slice(attestation.block_hash.as_slice());
+    msg.extend_from_slice(attestation.state_root.as_slice());
+    msg.extend_from_slice(attestation.receipts_root.as_slice());
+    msg
+}
+
+/// Signed bytes of an inbound transfer, for guardians on the source chain to
+/// rebuild: the domain, then this chain's id, the source chain and nonce as
+/// big-endian u64s, token, recipient, the amount as 32 big-endian bytes and
+/// a mint flag byte.
+pub fn inbound_message(chain_id: u64, transfer: &InboundTransfer) -> Vec<u8> {
+    let mut msg = INBOUND_DOMAIN.to_vec();
+    msg.extend_from_slice(&chain_id.to_be_bytes());
+    msg.extend_from_slice(&transfer.source_chain.to_be_bytes());
+    msg.extend_from_slice(&transfer.nonce.to_be_bytes());
+    msg.extend_from_slice(transfer.token.as_slice());
+    msg.extend_from_slice(transfer.recipient.as_slice());
+    msg.extend_from_slice(&transfer.amount.to_be_bytes::<32>());
+    msg.push(transfer.mint as u8);
+    msg
+}
+
+fn commit_message(chain_id: u64, number: u64, block_hash: B256) -> Vec<u8> {
+    let mut msg = COMMIT_DOMAIN.to_vec();
+    msg.extend_from_slice(&chain_id.to_be_bytes());
+    msg.extend_from_slice(&number.to_be_bytes());
+    msg.extend_from_slice(block_hash.as_slice());
+    msg
+}
+
+fn batch_message(chain_id: u64, batch: &NarwhalBatch) -> Vec<u8> {
+    let mut msg = BATCH_DOMAIN.to_vec();
+    msg.extend_from_slice(&chain_id.to_be_bytes());
+    msg.extend_from_slice(batch.digest().as_slice());
+    msg
+}
+
+/// First message on a connection. A validator proves control of its ML-DSA
+/// key by signing the libp2p peer id it is connecting from, which Noise has
+/// already authenticated; full nodes send no claim.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PqHello {
+    pub validator: Option<(String, Vec<u8>)>,
+}
+
+fn handshake_message(chain_id: u64, validator_id: &str, peer: &PeerId) -> Vec<u8> {
+    let mut msg = HANDSHAKE_DOMAIN.to_vec();
+    msg.extend_from_slice(&chain_id.to_be_bytes());
+    msg.extend_from_slice(&(validator_id.len() as u64).to_be_bytes());
+    msg.extend_from_slice(validator_id.as_bytes());
+    msg.extend_from_slice(&peer.to_bytes());
+    msg
+}
+
+impl PqHello {
+    pub fn new(chain_id: u64, key: Option<&ValidatorKey>, local_peer: &PeerId) -> Result<Self> {
+        let validator = match key {
+            Some(key) => {
+                let sig = key.sign(&handshake_message(chain_id, &key.id, local_peer))?;
+                Some((key.id.clone(), sig))
+            }
+            None => None,
+        };
+        Ok(Self { validator })
+    }
+}
+
+/// Smallest stake that is more than two thirds of the total (2f+1).
+pub fn quorum_stake(validators: &[ValidatorConfig]) -> u64 {
+    let total: u64 = validators.iter().map(|v| v.stake).sum();
+    total * 2 / 3 + 1
+}
+
+/// Public keys and stakes of the configured validators.
+pub struct ValidatorSet {
+    chain_id: u64,
+    validators: HashMap<String, (Vec<u8>, u64)>,
+    quorum: u64,
+}
+
+impl ValidatorSet {
+    pub fn new(chain_id: u64, validators: &[ValidatorConfig]) -> Result<Self> {
+        let keys = validators
+            .iter()
+            .map(|v| Ok((v.id.clone(), (hex::decode(&v.pq_pubkey_hex)?, v.stake))))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            chain_id,
+            validators: keys,
+            quorum: quorum_stake(validators),
+        })
+    }
+
+    /// Verify `sig` by validator `id`; returns its stake.
+    fn verify(&self, id: &str, msg: &[u8], sig: &[u8]) -> Result<u64> {
+        let (pubkey, stake) = self
+            .validators
+            .get(id)
+            .ok_or_else(|| anyhow!("unknown validator {id}"))?;
+        verify_mldsa(mldsa_level(pubkey)?, pubkey, msg, sig)?;
+        Ok(*stake)
+    }
+
+    /// Check that `batch` is signed by the validator it names as author.
+    pub fn verify_batch(&self, batch: &NarwhalBatch) -> Result<()> {
+        self.verify(
+            &batch.author,
+            &batch_message(self.chain_id, batch),
+            &batch.signature,
+        )
+        .map(drop)
+        .map_err(|e| anyhow!("batch {} is not signed by {}: {e}", batch.id, batch.author))
+    }
+
+    pub fn verify_commit_vote(&self, vote: &CommitVote) -> Result<()> {
+        let msg = commit_message(self.chain_id, vote.number, vote.block_hash);
+        self.verify(&vote.validator, &msg, &vote.signature)
+            .map(drop)
+            .map_err(|e| {
+                anyhow!(
+                    "bad commit vote of {} for block {}: {e}",
+                    vote.validator,
+                    vote.number
+                )
+            })
+    }
+
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    /// Stake of validator `id`; 0 if it is not in the set.
+    pub fn stake(&self, id: &str) -> u64 {
+        self.validators.get(id).map_or(0, |(_, stake)| *stake)
+    }
+
+    /// Smallest stake that makes a quorum (2f+1).
+    pub fn quorum(&self) -> u64 {
+        self.quorum
+    }
+
+    /// Check that distinct validators with at least 2f+1 stake signed `msg`.
+    fn verify_quorum(&self, what: &str, msg: &[u8], sigs: &[(String, Vec<u8>)]) -> Result<()> {
+        if self.validators.is_empty() {
+            bail!("no validator set to check {what} against");
+        }
+        let mut signers = HashSet::new();
+        let mut stake = 0;
+        for (id, sig) in sigs {
+            if !signers.insert(id) {
+                bail!("{what} counts {id} twice");
+            }
+            stake += self
+                .verify(id, msg, sig)
+                .with_context(|| what.to_string())?;
+        }
+        if stake < self.quorum {
+            bail!("{what} has {stake} of the {} stake needed", self.quorum);
+        }
+        Ok(())
+    }
+
+    pub fn verify_certificate(&self, cert: &CommitCertificate) -> Result<()> {
+        let msg = commit_message(self.chain_id, cert.number, cert.block_hash);
+        self.verify_quorum(
+            &format!("certificate for block {}", cert.number),
+            &msg,
+            &cert.votes,
+        )
+    }
+
+    pub fn verify_attestation_vote(&self, vote: &AttestationVote) -> Result<()> {
+        let number = vote.attestation.number;
+        if vote.attestation.chain_id != self.chain_id {
+            bail!(
+                "attestation of {} for block {number} is for chain {}",
+                vote.validator,
+                vote.attestation.chain_id
+            );
+        }
+        let msg = attestation_message(&vote.attestation);
+        self.verify(&vote.validator, &msg, &vote.signature)
+            .map(drop)
+            .map_err(|e| {
+                anyhow!(
+                    "bad attestation of {} for block {number}: {e}",
+                    vote.validator
+                )
+            })
+    }
+}
+
+/// Public keys of the guardians vouching for transfers from other chains.
+pub struct GuardianSet {
+    chain_id: u64,
+    guardians: HashMap<String, Vec<u8>>,
+    threshold: usize,
+}
+
+impl GuardianSet {
+    pub fn new(chain_id: u64, cfg: &GuardianSetConfig) -> Result<Self> {
+        ensure!(
+            cfg.threshold > 0 && cfg.threshold <= cfg.guardians.len(),
+            "guardian threshold {} is not between 1 and the {} guardians",
+            cfg.threshold,
+            cfg.guardians.len()
+        );
+        let mut guardians = HashMap::new();
+        for guardian in &cfg.guardians {
+            let pubkey = hex::decode(&guardian.pq_pubkey_hex)
+                .with_context(|| format!("decoding key of guardian {}", guardian.id))?;
+            mldsa_level(&pubkey)?;
+            if guardians.insert(guardian.id.clone(), pubkey).is_some() {
+                bail!("guardian {} is listed twice", guardian.id);
+            }
+        }
+        Ok(Self {
+            chain_id,
+            guardians,
+            threshold: cfg.threshold,
+        })
+    }
+
+    /// Check that at least `threshold` distinct guardians signed the
+    /// transfer for this chain.
+    pub fn verify(&self, msg: &InboundMessage) -> Result<()> {
+        let transfer = &msg.transfer;
+        let what = format!(
+            "transfer {} from chain {}",
+            transfer.nonce, transfer.source_chain
+        );
+        let signed = inbound_message(self.chain_id, transfer);
+        let mut signers = HashSet::new();
+        for (id, sig) in &msg.signatures {
+            if !signers.insert(id) {
+                bail!("{what} counts guardian {id} twice");
+            }
+            let pubkey = self
+                .guardians
+                .get(id)
+                .ok_or_else(|| anyhow!("{what} is signed by unknown guardian {id}"))?;
+            verify_mldsa(mldsa_level(pubkey)?, pubkey, &signed, sig)
+                .map_err(|e| anyhow!("{what}: bad signature of guardian {id}: {e}"))?;
+        }
+        ensure!(
+            signers.len() >= self.threshold,
+            "{what} has {} of the {} guardian signatures needed",
+            signers.len(),
+            self.threshold
+        );
+        Ok(())
+    }
+}
+
+/// Validators proven to be behind connected peers.
+pub struct ValidatorRegistry {
+    set: Arc<ValidatorSet>,
+    peers: HashMap<PeerId, String>,
+}
+
+impl ValidatorRegistry {
+    pub fn new(set: Arc<ValidatorSet>) -> Self {
+        Self {
+            set,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Check the hello `peer` sent. Returns the validator it proved to be, if
+    /// any; an error means the claim was false and the peer must be dropped.
+    pub fn verify(&mut self, peer: PeerId, hello: &PqHello) -> Result<Option<String>> {
+        let Some((id, sig)) = &hello.validator else {
+            return Ok(None);
+        };
+        let msg = handshake_message(self.set.chain_id, id, &peer);
+        self.set
+            .verify(id, &msg, sig)
+            .map_err(|e| anyhow!("{peer} failed to prove validator {id}: {e}"))?;
+
+        self.peers.insert(peer, id.clone());
+        Ok(Some(id.clone()))
+    }
+
+    /// Validator authenticated on `peer`.
+    pub fn validator_of(&self, peer: &PeerId) -> Option<&str> {
+        self.peers.get(peer).map(String::as_str)
+    }
+
+    pub fn remove(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAIN_ID: u64 = 1337;
+
+    /// Validators a..d of equal stake, so a quorum is three of them.
+    fn validators() -> (Arc<ValidatorSet>, Vec<ValidatorKey>) {
+        let (keys, configs): (Vec<_>, Vec<_>) = ["a", "b", "c", "d"]
+            .into_iter()
+            .map(|id| ValidatorKey::generate(CHAIN_ID, id, 1))
+            .unzip();
+        (
+            Arc::new(ValidatorSet::new(CHAIN_ID, &configs).unwrap()),
+            keys,
+        )
+    }
+
+    fn certificate(keys: &[&ValidatorKey]) -> CommitCertificate {
+        let block_hash = B256::repeat_byte(1);
+        let votes = keys
+            .iter()
+            .map(|key| {
+                let vote = key.sign_commit(9, block_hash).unwrap();
+                (vote.validator, vote.signature)
+            })
+            .collect();
+        CommitCertificate {
+            number: 9,
+            block_hash,
+            votes,
+        }
+    }
+
+    #[test]
+    fn handshake_proves_the_validator() {
+        let (set, keys) = validators();
+        let mut registry = ValidatorRegistry::new(set);
+        let peer = PeerId::random();
+        let hello = PqHello::new(CHAIN_ID, Some(&keys[0]), &peer).unwrap();
+        assert_eq!(
+            registry.verify(peer, &hello).unwrap(),
+            Some("a".to_string())
+        );
+        assert_eq!(registry.validator_of(&peer), Some("a"));
+
+        let full_node = PqHello::new(CHAIN_ID, None, &peer).unwrap();
+        assert_eq!(registry.verify(PeerId::random(), &full_node).unwrap(), None);
+    }
+
+    #[test]
+    fn handshake_with_an_unregistered_key_is_refused() {
+        let (set, _) = validators();
+        let mut registry = ValidatorRegistry::new(set);
+        let peer = PeerId::random();
+        // A key claiming a registered id, and one outside the set.
+        let (impostor, _) = ValidatorKey::generate(CHAIN_ID, "a", 1);
+        let (stranger, _) = ValidatorKey::generate(CHAIN_ID, "e", 1);
+        for key in [&impostor, &stranger] {
+            let hello = PqHello::new(CHAIN_ID, Some(key), &peer).unwrap();
+            assert!(registry.verify(peer, &hello).is_err());
+        }
+        assert_eq!(registry.validator_of(&peer), None);
+    }
+
+    #[test]
+    fn handshake_is_bound_to_the_peer_and_chain() {
+        let (set, keys) = validators();
+        let mut registry = ValidatorRegistry::new(set);
+        let (peer, other) = (PeerId::random(), PeerId::random());
+
+        // Replayed by another peer than the one that signed it.
+        let hello = PqHello::new(CHAIN_ID, Some(&keys[0]), &peer).unwrap();
+        assert!(registry.verify(other, &hello).is_err());
+        assert_eq!(registry.validator_of(&other), None);
+
+        let foreign = PqHello::new(CHAIN_ID + 1, Some(&keys[0]), &peer).unwrap();
+        assert!(registry.verify(peer, &foreign).is_err());
+    }
+
+    #[test]
+    fn certificate_needs_a_quorum_of_distinct_signers() {
+        let (set, keys) = validators();
+        let (a, b, c) = (&keys[0], &keys[1], &keys[2]);
+        assert!(set.verify_certificate(&certificate(&[a, b, c])).is_ok());
+
+        let err = set.verify_certificate(&certificate(&[a, b])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "certificate for block 9 has 2 of the 3 stake needed"
+        );
+        // Three votes, but only two validators.
+        let err = set
+            .verify_certificate(&certificate(&[a, b, a]))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "certificate for block 9 counts a twice");
+
+        let mut forged = certificate(&[a, b, c]);
+        forged.block_hash = B256::repeat_byte(2);
+        assert!(set.verify_certificate(&forged).is_err());
+    }
+}
@@ -0,0 +1,365 @@
+use crate::bridge::contract;
+use crate::evm::{EvmExecutor, PreImages, StateDb, TxResult};
+use crate::genesis::Genesis;
+use crate::identity::GuardianSet;
+use crate::precompile::PqPrecompiles;
+use crate::types::{HybridTx, InboundMessage, InboundTransfer, SYSTEM_ADDRESS};
+use anyhow::{ensure, Context, Result};
+use revm::{
+    primitives::{
+        Address, Bytes, EVMError, Env, ExecutionResult, ResultAndState, TransactTo, TxEnv, B256,
+        U256,
+    },
+    DatabaseCommit, EVM,
+};
+use std::convert::Infallible;
+use tracing::warn;
+
+/// Gas available to the system call applying an inbound transfer.
+pub const INBOUND_GAS_LIMIT: u64 = 200_000;
+/// System txs pay no gas, so a block carries at most this many of them.
+pub const MAX_SYSTEM_TXS_PER_BLOCK: usize = 64;
+
+/// Transfers from other chains, signed by the genesis guardians and applied
+/// by system transactions calling the bridge contract's `release`, which
+/// keeps the used nonces of every source chain in its storage.
+pub struct InboundBridge {
+    chain_id: u64,
+    contract: Address,
+    guardians: GuardianSet,
+}
+
+impl InboundBridge {
+    /// `None` unless the genesis has both a bridge contract and guardians.
+    pub fn from_genesis(genesis: &Genesis) -> Result<Option<Self>> {
+        let (Some(contract), Some(guardians)) = (genesis.bridge_address, &genesis.guardians) else {
+            return Ok(None);
+        };
+        let chain_id = genesis.config.chain_id;
+        Ok(Some(Self {
+            chain_id,
+            contract,
+            guardians: GuardianSet::new(chain_id, guardians)?,
+        }))
+    }
+
+    /// The system transaction applying `msg`, once its signatures check out.
+    pub fn system_tx(&self, msg: &InboundMessage) -> Result<HybridTx> {
+        self.guardians.verify(msg)?;
+        Ok(self.build(msg))
+    }
+
+    fn build(&self, msg: &InboundMessage) -> HybridTx {
+        let mut tx = HybridTx {
+            hash: B256::ZERO,
+            from: SYSTEM_ADDRESS,
+            to: Some(self.contract),
+            nonce: U256::ZERO,
+            gas_limit: INBOUND_GAS_LIMIT,
+            max_fee_per_gas: U256::ZERO,
+            max_priority_fee_per_gas: U256::ZERO,
+            value: U256::ZERO,
+            data: bincode::serialize(msg)
+                .expect("inbound message serializes")
+                .into(),
+            chain_id: self.chain_id,
+            sig: None,
+            pq_sig: None,
+            pq_pubkey: None,
+        };
+        tx.hash = tx.tx_hash();
+        tx
+    }
+
+    /// Check that `tx` is exactly what `system_tx` builds for a message the
+    /// guardians signed, and return that message.
+    pub fn verify_system_tx(&self, tx: &HybridTx) -> Result<InboundMessage> {
+        let msg: InboundMessage = bincode::deserialize(&tx.data)
+            .with_context(|| format!("system tx {} carries no inbound transfer", tx.hash))?;
+        let expected = self.build(&msg);
+        ensure!(
+            tx.hash == expected.hash
+                && tx.tx_hash() == expected.hash
+                && tx.sig.is_none()
+                && tx.pq_sig.is_none()
+                && tx.pq_pubkey.is_none(),
+            "system tx {} is not the one its transfer calls for",
+            tx.hash
+        );
+        self.guardians.verify(&msg)?;
+        Ok(msg)
+    }
+
+    /// Whether the bridge contract already marked `transfer`'s nonce as
+    /// used in the executed state. Blocks committed but not yet executed
+    /// may apply it too, so `false` does not make a replay impossible.
+    pub fn is_applied(&self, executor: &EvmExecutor, transfer: &InboundTransfer) -> bool {
+        let slot = contract::used_slot(transfer.source_chain, transfer.nonce);
+        executor.storage(self.contract, slot) != U256::ZERO
+    }
+
+    /// Apply the system transactions opening a block, in order. Those past
+    /// `MAX_SYSTEM_TXS_PER_BLOCK`, which an honest leader never packs, are
+    /// rejected.
+    pub fn apply_block(
+        &self,
+        db: &mut StateDb,
+        env: &Env,
+        txs: &[HybridTx],
+        pre: &mut PreImages,
+    ) -> Result<Vec<TxResult>, EVMError<Infallible>> {
+        let mut results = Vec::with_capacity(txs.len());
+        for (index, tx) in txs.iter().enumerate() {
+            if index >= MAX_SYSTEM_TXS_PER_BLOCK {
+                warn!("Rejecting system tx {}: over the per-block cap", tx.hash);
+                results.push(rejected());
+                continue;
+            }
+            results.push(self.apply(db, env, tx, pre)?);
+        }
+        Ok(results)
+    }
+
+    /// Apply a system transaction: a fee-free call from `SYSTEM_ADDRESS` to
+    /// `release`, which reverts if the nonce was used before. What it
+    /// overwrites is recorded in `pre`. Transactions
+    /// that fail `verify_system_tx` revert without touching the state.
+    pub fn apply(
+        &self,
+        db: &mut StateDb,
+        env: &Env,
+        tx: &HybridTx,
+        pre: &mut PreImages,
+    ) -> Result<TxResult, EVMError<Infallible>> {
+        let msg = match self.verify_system_tx(tx) {
+            Ok(msg) => msg,
+            Err(e) => {
+                warn!("Rejecting system tx: {e:#}");
+                return Ok(rejected());
+            }
+        };
+
+        let mut env = env.clone();
+        env.block.basefee = U256::ZERO;
+        env.tx = TxEnv::default();
+        env.tx.caller = SYSTEM_ADDRESS;
+        env.tx.transact_to = TransactTo::Call(self.contract);
+        env.tx.data = contract::release_call(&msg.transfer);
+        env.tx.gas_limit = tx.gas_limit;
+        env.tx.gas_price = U256::ZERO;
+        env.tx.chain_id = Some(self.chain_id);
+
+        let mut evm = EVM::with_env(env);
+        evm.database(db);
+        match evm.inspect(PqPrecompiles) {
+            Ok(ResultAndState { result, state }) => {
+                let db = evm.db.as_mut().expect("database is set");
+                pre.record(db, &state);
+                db.commit(state);
+                Ok(Ok(result))
+            }
+            Err(EVMError::Transaction(invalid)) => Ok(Err(invalid)),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Outcome of a system transaction that could not be applied at all.
+pub fn rejected() -> TxResult {
+    Ok(ExecutionResult::Revert {
+        gas_used: 0,
+        output: Bytes::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bridge::contract::runtime_code;
+    use crate::config::{GuardianConfig, GuardianSetConfig, NodeConfig};
+    use crate::crypto::{sign_mldsa, MlDsaLevel};
+    use crate::identity::inbound_message;
+    use pqcrypto_mldsa::mldsa44;
+    use pqcrypto_traits::sign::{PublicKey, SecretKey};
+    use revm::db::DatabaseRef;
+    use revm::primitives::{AccountInfo, Bytecode, SpecId};
+
+    const RECIPIENT: Address = Address::repeat_byte(0x7e);
+
+    /// Guardian ids with their secret keys.
+    type Guardians = Vec<(String, Vec<u8>)>;
+
+    /// A bridge with three guardians, two of which must sign.
+    fn bridge() -> (InboundBridge, Guardians) {
+        let keys: Vec<_> = (0..3)
+            .map(|i| (format!("g{i}"), mldsa44::keypair()))
+            .collect();
+        let mut cfg = NodeConfig::default();
+        cfg.bridges.guardians = Some(GuardianSetConfig {
+            guardians: keys
+                .iter()
+                .map(|(id, (pk, _))| GuardianConfig {
+                    id: id.clone(),
+                    pq_pubkey_hex: hex::encode(pk.as_bytes()),
+                })
+                .collect(),
+            threshold: 2,
+        });
+        let bridge = InboundBridge::from_genesis(&Genesis::dev(&cfg))
+            .unwrap()
+            .unwrap();
+        let secrets = keys
+            .into_iter()
+            .map(|(id, (_, sk))| (id, sk.as_bytes().to_vec()))
+            .collect();
+        (bridge, secrets)
+    }
+
+    fn transfer(nonce: u64) -> InboundTransfer {
+        InboundTransfer {
+            source_chain: 1,
+            nonce,
+            token: Address::ZERO,
+            recipient: RECIPIENT,
+            amount: U256::from(1_000),
+            mint: false,
+        }
+    }
+
+    fn signed(
+        bridge: &InboundBridge,
+        guardians: &[(String, Vec<u8>)],
+        nonce: u64,
+    ) -> InboundMessage {
+        let transfer = transfer(nonce);
+        let msg = inbound_message(bridge.chain_id, &transfer);
+        let signatures = guardians
+            .iter()
+            .map(|(id, secret)| {
+                let sig = sign_mldsa(MlDsaLevel::MlDsa44, secret, &msg).unwrap();
+                (id.clone(), sig)
+            })
+            .collect();
+        InboundMessage {
+            transfer,
+            signatures,
+        }
+    }
+
+    /// The bridge contract, holding ETH to release.
+    fn state(bridge: &InboundBridge) -> (StateDb, Env) {
+        let mut db = StateDb::new(Default::default());
+        let code = Bytecode::new_raw(runtime_code());
+        let info = AccountInfo::new(U256::from(1_000_000), 1, code.hash_slow(), code);
+        db.insert_account_info(bridge.contract, info);
+        let mut env = Env::default();
+        env.cfg.chain_id = bridge.chain_id;
+        env.cfg.spec_id = SpecId::CANCUN;
+        (db, env)
+    }
+
+    fn apply(
+        bridge: &InboundBridge,
+        db: &mut StateDb,
+        env: &Env,
+        tx: &HybridTx,
+    ) -> ExecutionResult {
+        let result = bridge.apply(db, env, tx, &mut PreImages::default());
+        result.unwrap().unwrap()
+    }
+
+    fn is_used(bridge: &InboundBridge, db: &StateDb, nonce: u64) -> bool {
+        let slot = contract::used_slot(1, nonce);
+        DatabaseRef::storage(db, bridge.contract, slot).unwrap() != U256::ZERO
+    }
+
+    fn balance(db: &StateDb, address: Address) -> U256 {
+        DatabaseRef::basic(db, address)
+            .unwrap()
+            .map_or(U256::ZERO, |info| info.balance)
+    }
+
+    #[test]
+    fn needs_the_guardian_threshold() {
+        let (bridge, guardians) = bridge();
+        let (mut db, env) = state(&bridge);
+
+        let valid = signed(&bridge, &guardians[..2], 1);
+        let tx = bridge.system_tx(&valid).unwrap();
+        assert_eq!(
+            bridge.verify_system_tx(&tx).unwrap().transfer,
+            valid.transfer
+        );
+
+        let short = signed(&bridge, &guardians[..1], 2);
+        let twice = signed(&bridge, &[guardians[0].clone(), guardians[0].clone()], 3);
+        // Registered guardian ids, other keys; and an unknown guardian.
+        let impostors: Guardians = ["g0", "g1", "g9"]
+            .into_iter()
+            .map(|id| (id.to_string(), mldsa44::keypair().1.as_bytes().to_vec()))
+            .collect();
+        let forged = signed(&bridge, &impostors[..2], 4);
+        let unknown = signed(&bridge, &[guardians[0].clone(), impostors[2].clone()], 5);
+        for msg in [short, twice, forged, unknown] {
+            assert!(bridge.system_tx(&msg).is_err());
+            let tx = bridge.build(&msg);
+            assert!(bridge.verify_system_tx(&tx).is_err());
+            let result = apply(&bridge, &mut db, &env, &tx);
+            assert_eq!(result, rejected().unwrap());
+            assert!(!is_used(&bridge, &db, msg.transfer.nonce));
+        }
+        assert_eq!(balance(&db, RECIPIENT), U256::ZERO);
+
+        // The tx must be exactly the one the transfer calls for.
+        let mut tampered = tx.clone();
+        tampered.gas_limit += 1;
+        tampered.hash = tampered.tx_hash();
+        assert!(bridge.verify_system_tx(&tampered).is_err());
+    }
+
+    #[test]
+    fn applies_a_transfer_once() {
+        let (bridge, guardians) = bridge();
+        let (mut db, env) = state(&bridge);
+        let tx = bridge.system_tx(&signed(&bridge, &guardians, 1)).unwrap();
+
+        assert!(apply(&bridge, &mut db, &env, &tx).is_success());
+        assert!(is_used(&bridge, &db, 1));
+        assert_eq!(balance(&db, RECIPIENT), U256::from(1_000));
+
+        // Replays pass the signature checks but revert in the contract.
+        assert!(bridge.verify_system_tx(&tx).is_ok());
+        let replay = apply(&bridge, &mut db, &env, &tx);
+        assert!(matches!(replay, ExecutionResult::Revert { gas_used, .. } if gas_used > 0));
+        assert_eq!(balance(&db, RECIPIENT), U256::from(1_000));
+    }
+
+    #[test]
+    fn caps_system_txs_per_block() {
+        let (bridge, guardians) = bridge();
+        let (mut db, env) = state(&bridge);
+        let count = MAX_SYSTEM_TXS_PER_BLOCK as u64 + 1;
+        let txs: Vec<HybridTx> = (0..count)
+            .map(|nonce| {
+                bridge
+                    .system_tx(&signed(&bridge, &guardians[1..], nonce))
+                    .unwrap()
+            })
+            .collect();
+
+        let results = bridge
+            .apply_block(&mut db, &env, &txs, &mut PreImages::default())
+            .unwrap();
+        let (last, applied) = results.split_last().unwrap();
+        assert!(applied
+            .iter()
+            .all(|result| result.as_ref().unwrap().is_success()));
+        assert_eq!(*last, rejected());
+        assert!(is_used(&bridge, &db, count - 2));
+        assert!(!is_used(&bridge, &db, count - 1));
+        assert_eq!(
+            balance(&db, RECIPIENT),
+            U256::from(1_000 * MAX_SYSTEM_TXS_PER_BLOCK as u64)
+        );
+    }
+}
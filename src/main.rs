@@ -1,13 +1,27 @@
+mod attestation;
 mod bridge;
 mod config;
 mod consensus;
 mod crypto;
 mod db;
+mod discovery;
 mod evm;
+mod genesis;
+mod identity;
+mod inbound;
+mod merkle;
 mod node;
 mod p2p;
+mod parallel;
+mod precompile;
 mod rpc;
+mod sync;
+#[cfg(test)]
+mod testing;
+mod trace;
 mod types;
+mod validation;
+mod wire;
 
 use crate::{
     bridge::BridgeManager,
@@ -15,8 +29,10 @@ use crate::{
     consensus::NarwhalBullsharkEngine,
     db::ChainStore,
     evm::EvmExecutor,
+    identity::{ValidatorKey, ValidatorSet},
+    inbound::InboundBridge,
     node::NodeRuntime,
-    rpc::{spawn_rpc, EthApiImpl},
+    rpc::{spawn_admin_rpc, spawn_rpc, AdminApiImpl, BridgeApiImpl, DebugApiImpl, EthApiImpl},
 };
 use std::sync::Arc;
 use tokio::sync::mpsc;
@@ -31,34 +47,130 @@ async fn main() -> Result<()> {
         .init();
 
     // In real life load from a config file / CLI
-    let cfg = NodeConfig::default();
+    let mut cfg = NodeConfig::default();
 
     // Chain store
     let store = Arc::new(ChainStore::open(&cfg.rocksdb_path)?);
 
+    // `init <genesis.json>`: write block 0 and its state, then exit.
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("init") {
+        let path = args.get(2).ok_or_else(|| anyhow::anyhow!("usage: init <genesis.json>"))?;
+        genesis::init(&store, &genesis::Genesis::load(path)?)?;
+        return Ok(());
+    }
+
+    // Genesis (checked against the stored hash on every start)
+    let (genesis, genesis_block) = genesis::load_or_init(&store, &cfg)?;
+    cfg.chain_id = genesis.config.chain_id;
+    cfg.block_gas_limit = genesis.gas_limit;
+    if !genesis.validators.is_empty() {
+        cfg.validators = genesis.validators.clone();
+    }
+
+    // Guardian-signed transfers from other chains, applied by system
+    // transactions at the start of a block
+    let inbound = InboundBridge::from_genesis(&genesis)?.map(Arc::new);
+
     // EVM executor
-    let executor = Arc::new(EvmExecutor::new(cfg.chain_id));
+    let executor = Arc::new(EvmExecutor::new(
+        genesis.config.clone(),
+        cfg.execution_workers,
+        cfg.trace_history_blocks,
+        inbound.clone(),
+    ));
+    executor.load_genesis(store.accounts()?, &genesis_block.header)?;
 
-    // Bridges
-    let bridge = Arc::new(BridgeManager::new(cfg.bridges.clone()));
+    // Bridges: committed blocks are queued in the store's outbox and
+    // delivered by a background worker
+    let bridge = Arc::new(BridgeManager::new(
+        cfg.bridges.clone(),
+        store.clone(),
+        genesis.bridge_address,
+    )?);
+    tokio::spawn({
+        let bridge = bridge.clone();
+        async move { bridge.check_health().await }
+    });
+    tokio::spawn(bridge.clone().run());
 
     // Channels:
     // 1. P2P/RPC → Consensus
     let (consensus_tx, consensus_rx) = mpsc::channel(1024);
     // 2. Consensus → NodeRuntime (executors + bridges)
     let (cons_out_tx, cons_out_rx) = mpsc::channel(1024);
+    // 3. Consensus + NodeRuntime → P2P (gossip to publish)
+    let (gossip_tx, gossip_rx) = mpsc::channel(1024);
+
+    // 4. NodeRuntime → P2P (block sync requests)
+    let (sync_tx, sync_rx) = mpsc::channel(16);
+    // 5. P2P → NodeRuntime (block attestations of other validators)
+    let (attestation_tx, attestation_rx) = mpsc::channel(1024);
+
+    // Validator identity (ML-DSA), shared by the handshake, batch signing,
+    // commit votes and block attestations
+    let validator_key = ValidatorKey::load(&cfg)?.map(Arc::new);
+    let validator_set = Arc::new(ValidatorSet::new(cfg.chain_id, &cfg.validators)?);
 
     // Spawn P2P
-    p2p::spawn_p2p(&cfg.libp2p_listen, consensus_tx.clone()).await?;
+    p2p::spawn_p2p(
+        &cfg,
+        store.clone(),
+        validator_key.clone(),
+        consensus_tx.clone(),
+        attestation_tx,
+        inbound.clone(),
+        executor.clone(),
+        gossip_rx,
+        sync_rx,
+    )
+    .await?;
+
+    // Spawn JSON-RPC
+    let api_impl = EthApiImpl::new(store.clone(), consensus_tx.clone());
+    let debug_impl = DebugApiImpl::new(store.clone(), executor.clone());
+    let bridge_impl = BridgeApiImpl::new(
+        store.clone(),
+        executor.clone(),
+        bridge.clone(),
+        consensus_tx.clone(),
+        inbound,
+    );
+    let _rpc_handle = spawn_rpc(cfg.rpc_listen, api_impl, debug_impl, bridge_impl).await?;
+    let _admin_handle = match cfg.admin_rpc_listen {
+        Some(addr) => Some(spawn_admin_rpc(addr, AdminApiImpl::new(bridge.clone())).await?),
+        None => None,
+    };
+
+    // Node runtime (execute committed blocks + bridge). It catches up with
+    // the network before consensus starts, and returns an error when local
+    // execution diverges from the attested state, which halts the node.
+    let mut runtime = NodeRuntime::new(
+        store.clone(),
+        executor.clone(),
+        cons_out_rx,
+        sync_tx,
+        gossip_tx.clone(),
+        attestation_rx,
+        validator_key.clone(),
+        validator_set,
+        bridge.clone(),
+    )?;
+    runtime.sync().await?;
 
     // Spawn consensus
     let engine = NarwhalBullsharkEngine::new(
         store.clone(),
         consensus_rx,
         cons_out_tx,
-        "validator-0".to_string(),
+        gossip_tx,
+        cfg.validator_id.clone(),
         cfg.target_tps,
         cfg.block_time_ms,
+        cfg.block_gas_limit,
+        cfg.fee_recipient(&cfg.validator_id),
+        cfg.validators.clone(),
+        validator_key,
     );
     tokio::spawn(async move {
         if let Err(e) = engine.run().await {
@@ -66,20 +178,5 @@ async fn main() -> Result<()> {
         }
     });
 
-    // Spawn JSON-RPC
-    let api_impl = EthApiImpl::new(store.clone(), consensus_tx);
-    let _rpc_handle = spawn_rpc(cfg.rpc_listen, api_impl).await?;
-
-    // Spawn node runtime (execute committed blocks + bridge)
-    let runtime = NodeRuntime::new(executor.clone(), cons_out_rx, bridge.clone());
-    tokio::spawn(async move {
-        if let Err(e) = runtime.run().await {
-            eprintln!("Node runtime failed: {e:?}");
-        }
-    });
-
-    // Keep running
-    futures::future::pending::<()>().await;
-    Ok(())
+    runtime.run().await
 }
-
@@ -0,0 +1,467 @@
+use revm::primitives::{keccak256, B256};
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Domain tags keeping leaf and inner-node preimages apart, so an inner
+/// node can never be passed off as a leaf.
+const LEAF_TAG: u8 = 0;
+const NODE_TAG: u8 = 1;
+
+/// Hash of a leaf's encoded data.
+pub fn leaf_hash(data: &[u8]) -> B256 {
+    let mut preimage = Vec::with_capacity(data.len() + 1);
+    preimage.push(LEAF_TAG);
+    preimage.extend_from_slice(data);
+    keccak256(preimage)
+}
+
+fn node_hash(left: &B256, right: &B256) -> B256 {
+    let mut preimage = [0u8; 65];
+    preimage[0] = NODE_TAG;
+    preimage[1..33].copy_from_slice(left.as_slice());
+    preimage[33..].copy_from_slice(right.as_slice());
+    keccak256(preimage)
+}
+
+/// Leaves padded with zero hashes to a power of two, so every proof has the
+/// same length and a verifier only needs the leaf index to order siblings.
+fn padded(leaves: &[B256]) -> Vec<B256> {
+    let mut level = leaves.to_vec();
+    level.resize(leaves.len().next_power_of_two(), B256::ZERO);
+    level
+}
+
+fn next_level(level: &[B256]) -> Vec<B256> {
+    level
+        .chunks(2)
+        .map(|pair| node_hash(&pair[0], &pair[1]))
+        .collect()
+}
+
+/// Root of a binary Merkle tree over `leaves` (see `leaf_hash`); zero for
+/// no leaves.
+pub fn root(leaves: &[B256]) -> B256 {
+    if leaves.is_empty() {
+        return B256::ZERO;
+    }
+    let mut level = padded(leaves);
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+/// Sibling hashes from the leaf at `index` up to the root. At each level
+/// bit `i` of `index` tells whether the sibling is on the left (1) or the
+/// right (0).
+pub fn proof(leaves: &[B256], index: usize) -> Option<Vec<B256>> {
+    if index >= leaves.len() {
+        return None;
+    }
+    let mut level = padded(leaves);
+    let mut index = index;
+    let mut siblings = Vec::new();
+    while level.len() > 1 {
+        siblings.push(level[index ^ 1]);
+        level = next_level(&level);
+        index /= 2;
+    }
+    Some(siblings)
+}
+
+/// Whether `proof`, as `proof` returns it, puts `leaf` at `index` in the
+/// tree with root `root`.
+pub fn verify(root: B256, leaf: B256, index: usize, proof: &[B256]) -> bool {
+    let mut hash = leaf;
+    let mut index = index;
+    for sibling in proof {
+        hash = if index & 1 == 1 {
+            node_hash(sibling, &hash)
+        } else {
+            node_hash(&hash, sibling)
+        };
+        index /= 2;
+    }
+    index == 0 && hash == root
+}
+
+/// Value held in a [`SparseMerkleTree`] leaf, committed to through its hash.
+pub trait LeafValue: Clone {
+    fn value_hash(&self) -> B256;
+}
+
+impl LeafValue for B256 {
+    fn value_hash(&self) -> B256 {
+        *self
+    }
+}
+
+/// Persistent sparse Merkle tree over 256-bit keys, walked from the most
+/// significant bit. A subtree holding a single leaf is replaced by that
+/// leaf, so paths are as long as needed to tell the keys apart, and the
+/// shape depends only on the keys held, not on the order they were written.
+/// Updates copy the path they touch: clones are cheap snapshots.
+#[derive(Debug, Clone)]
+pub struct SparseMerkleTree<V> {
+    root: Option<Arc<Node<V>>>,
+}
+
+#[derive(Debug)]
+enum Node<V> {
+    Leaf {
+        key: B256,
+        value: V,
+        hash: B256,
+    },
+    Branch {
+        children: [Option<Arc<Node<V>>>; 2],
+        hash: B256,
+    },
+}
+
+impl<V> Node<V> {
+    fn hash(&self) -> B256 {
+        match self {
+            Node::Leaf { hash, .. } | Node::Branch { hash, .. } => *hash,
+        }
+    }
+}
+
+impl<V> Default for SparseMerkleTree<V> {
+    fn default() -> Self {
+        Self { root: None }
+    }
+}
+
+impl<V: LeafValue> SparseMerkleTree<V> {
+    /// Root hash; zero for an empty tree.
+    pub fn root(&self) -> B256 {
+        subtree_hash(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn get(&self, key: &B256) -> Option<&V> {
+        let mut node = self.root.as_ref();
+        let mut depth = 0;
+        while let Some(current) = node {
+            match &**current {
+                Node::Leaf {
+                    key: found, value, ..
+                } => return (found == key).then_some(value),
+                Node::Branch { children, .. } => {
+                    node = children[bit(key, depth)].as_ref();
+                    depth += 1;
+                }
+            }
+        }
+        None
+    }
+
+    pub fn insert(&mut self, key: B256, value: V) {
+        let hash = leaf_hash(&[key.as_slice(), value.value_hash().as_slice()].concat());
+        let leaf = Arc::new(Node::Leaf { key, value, hash });
+        self.root = Some(insert(self.root.as_ref(), 0, key, leaf));
+    }
+
+    pub fn remove(&mut self, key: &B256) {
+        self.root = remove(self.root.as_ref(), 0, key);
+    }
+
+    /// Proof that `key` is in the tree with its current value, or absent.
+    pub fn prove(&self, key: &B256) -> SparseProof {
+        let mut siblings = Vec::new();
+        let mut node = self.root.as_ref();
+        while let Some(current) = node {
+            match &**current {
+                Node::Leaf { key, value, .. } => {
+                    let leaf = ProofLeaf {
+                        key: *key,
+                        value_hash: value.value_hash(),
+                    };
+                    return SparseProof {
+                        siblings,
+                        leaf: Some(leaf),
+                    };
+                }
+                Node::Branch { children, .. } => {
+                    let side = bit(key, siblings.len());
+                    siblings.push(subtree_hash(&children[1 - side]));
+                    node = children[side].as_ref();
+                }
+            }
+        }
+        SparseProof {
+            siblings,
+            leaf: None,
+        }
+    }
+}
+
+/// Bit `depth` of `key`, counting from the most significant.
+fn bit(key: &B256, depth: usize) -> usize {
+    usize::from((key[depth / 8] >> (7 - depth % 8)) & 1)
+}
+
+fn subtree_hash<V>(node: &Option<Arc<Node<V>>>) -> B256 {
+    node.as_ref().map_or(B256::ZERO, |node| node.hash())
+}
+
+/// Node over `children`, collapsed to the leaf or emptiness below it when
+/// that is all it holds.
+fn branch<V>(children: [Option<Arc<Node<V>>>; 2]) -> Option<Arc<Node<V>>> {
+    match &children {
+        [None, None] => None,
+        [Some(only), None] | [None, Some(only)] if matches!(**only, Node::Leaf { .. }) => {
+            Some(only.clone())
+        }
+        _ => {
+            let hash = node_hash(&subtree_hash(&children[0]), &subtree_hash(&children[1]));
+            Some(Arc::new(Node::Branch { children, hash }))
+        }
+    }
+}
+
+fn insert<V>(
+    node: Option<&Arc<Node<V>>>,
+    depth: usize,
+    key: B256,
+    leaf: Arc<Node<V>>,
+) -> Arc<Node<V>> {
+    let Some(node) = node else {
+        return leaf;
+    };
+    match &**node {
+        Node::Leaf { key: existing, .. } if *existing == key => leaf,
+        Node::Leaf { key: existing, .. } => split(node.clone(), *existing, leaf, key, depth),
+        Node::Branch { children, .. } => {
+            let side = bit(&key, depth);
+            let mut children = children.clone();
+            children[side] = Some(insert(children[side].as_ref(), depth + 1, key, leaf));
+            branch(children).expect("branch holds the inserted leaf")
+        }
+    }
+}
+
+/// Subtree at `depth` holding the two leaves `a` and `b`.
+fn split<V>(
+    a: Arc<Node<V>>,
+    a_key: B256,
+    b: Arc<Node<V>>,
+    b_key: B256,
+    depth: usize,
+) -> Arc<Node<V>> {
+    let (a_side, b_side) = (bit(&a_key, depth), bit(&b_key, depth));
+    let mut children = [None, None];
+    if a_side == b_side {
+        children[a_side] = Some(split(a, a_key, b, b_key, depth + 1));
+    } else {
+        children[a_side] = Some(a);
+        children[b_side] = Some(b);
+    }
+    branch(children).expect("branch holds both leaves")
+}
+
+fn remove<V>(node: Option<&Arc<Node<V>>>, depth: usize, key: &B256) -> Option<Arc<Node<V>>> {
+    let node = node?;
+    match &**node {
+        Node::Leaf { key: existing, .. } if existing == key => None,
+        Node::Leaf { .. } => Some(node.clone()),
+        Node::Branch { children, .. } => {
+            let side = bit(key, depth);
+            let mut children = children.clone();
+            children[side] = remove(children[side].as_ref(), depth + 1, key);
+            branch(children)
+        }
+    }
+}
+
+/// Path of a key through a [`SparseMerkleTree`], proving either its value
+/// or its absence.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SparseProof {
+    /// Sibling hashes from the root down to where the key's path ends; at
+    /// depth `i` bit `i` of the key (most significant first) tells whether
+    /// the sibling is on the left (1) or the right (0).
+    pub siblings: Vec<B256>,
+    /// Leaf where the path ends: the key's own, another key's sharing the
+    /// path (the key is absent), or none for an empty subtree (absent too).
+    pub leaf: Option<ProofLeaf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProofLeaf {
+    pub key: B256,
+    pub value_hash: B256,
+}
+
+impl SparseProof {
+    /// Whether this proves that, in the tree with root `root`, `key` holds a
+    /// value with hash `value_hash`, or is absent if that is `None`.
+    pub fn verify(&self, root: B256, key: &B256, value_hash: Option<B256>) -> bool {
+        if self.siblings.len() > 256 {
+            return false;
+        }
+        let terminal = match (&self.leaf, value_hash) {
+            (Some(leaf), Some(value_hash)) => {
+                if leaf.key != *key || leaf.value_hash != value_hash {
+                    return false;
+                }
+                leaf.hash()
+            }
+            (Some(leaf), None) => {
+                let shares_path =
+                    (0..self.siblings.len()).all(|d| bit(&leaf.key, d) == bit(key, d));
+                if leaf.key == *key || !shares_path {
+                    return false;
+                }
+                leaf.hash()
+            }
+            (None, Some(_)) => return false,
+            (None, None) => B256::ZERO,
+        };
+        let computed =
+            self.siblings
+                .iter()
+                .enumerate()
+                .rev()
+                .fold(terminal, |hash, (depth, sibling)| {
+                    if bit(key, depth) == 1 {
+                        node_hash(sibling, &hash)
+                    } else {
+                        node_hash(&hash, sibling)
+                    }
+                });
+        computed == root
+    }
+}
+
+impl ProofLeaf {
+    fn hash(&self) -> B256 {
+        leaf_hash(&[self.key.as_slice(), self.value_hash.as_slice()].concat())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    fn key(n: u64) -> B256 {
+        keccak256(n.to_be_bytes())
+    }
+
+    fn tree(keys: &[u64]) -> SparseMerkleTree<B256> {
+        let mut tree = SparseMerkleTree::default();
+        for n in keys {
+            tree.insert(key(*n), B256::with_last_byte(*n as u8 + 1));
+        }
+        tree
+    }
+
+    #[test]
+    fn proves_every_leaf() {
+        for len in 1..=9u64 {
+            let leaves: Vec<B256> = (0..len).map(|n| leaf_hash(&n.to_be_bytes())).collect();
+            let root = root(&leaves);
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = proof(&leaves, index).unwrap();
+                assert_eq!(
+                    proof.len(),
+                    leaves.len().next_power_of_two().trailing_zeros() as usize
+                );
+                assert!(verify(root, *leaf, index, &proof));
+                assert!(!verify(root, *leaf, index ^ 1, &proof));
+                assert!(!verify(root, leaf_hash(b"other"), index, &proof));
+                assert!(!verify(
+                    root,
+                    *leaf,
+                    index + leaves.len().next_power_of_two(),
+                    &proof
+                ));
+            }
+            assert_eq!(proof(&leaves, leaves.len()), None);
+        }
+        assert_eq!(root(&[]), B256::ZERO);
+    }
+
+    #[test]
+    fn root_depends_only_on_contents() {
+        let mut keys: Vec<u64> = (0..64).collect();
+        let expected = tree(&keys).root();
+        keys.shuffle(&mut StdRng::seed_from_u64(1));
+        assert_eq!(tree(&keys).root(), expected);
+
+        // Removing keys gives the tree that never held them.
+        let mut shrunk = tree(&keys);
+        for n in 32..64 {
+            shrunk.remove(&key(n));
+        }
+        assert_eq!(shrunk.root(), tree(&(0..32).collect::<Vec<_>>()).root());
+        for n in 0..32 {
+            shrunk.remove(&key(n));
+        }
+        assert!(shrunk.is_empty());
+        assert_eq!(shrunk.root(), B256::ZERO);
+    }
+
+    #[test]
+    fn updates_leave_snapshots_untouched() {
+        let mut tree = tree(&[1, 2, 3]);
+        let snapshot = tree.clone();
+        let root = snapshot.root();
+        tree.insert(key(2), B256::ZERO);
+        tree.remove(&key(3));
+        assert_eq!(snapshot.root(), root);
+        assert_eq!(snapshot.get(&key(3)), Some(&B256::with_last_byte(4)));
+        assert_eq!(tree.get(&key(3)), None);
+    }
+
+    #[test]
+    fn proves_inclusion_and_absence() {
+        let tree = tree(&(0..40).collect::<Vec<_>>());
+        let root = tree.root();
+        for n in 0..40 {
+            let value = B256::with_last_byte(n as u8 + 1);
+            let proof = tree.prove(&key(n));
+            assert!(proof.verify(root, &key(n), Some(value)));
+            assert!(!proof.verify(root, &key(n), Some(B256::ZERO)));
+            assert!(!proof.verify(root, &key(n), None));
+        }
+        for n in 40..80 {
+            let proof = tree.prove(&key(n));
+            assert!(proof.verify(root, &key(n), None));
+            assert!(!proof.verify(root, &key(n), Some(B256::ZERO)));
+        }
+
+        let empty = SparseMerkleTree::<B256>::default();
+        assert!(empty.prove(&key(0)).verify(B256::ZERO, &key(0), None));
+    }
+
+    #[test]
+    fn rejects_tampered_proofs() {
+        let tree = tree(&(0..16).collect::<Vec<_>>());
+        let root = tree.root();
+        let value = B256::with_last_byte(4);
+
+        let mut proof = tree.prove(&key(3));
+        proof.siblings[0] = B256::ZERO;
+        assert!(!proof.verify(root, &key(3), Some(value)));
+
+        // Another key's leaf only proves absence if it lies on the path.
+        let mut proof = tree.prove(&key(3));
+        proof.leaf = tree.prove(&key(5)).leaf;
+        assert!(!proof.verify(root, &key(3), None));
+
+        // Dropping the leaf does not turn a member into an absent key.
+        let mut proof = tree.prove(&key(3));
+        proof.leaf = None;
+        assert!(!proof.verify(root, &key(3), None));
+    }
+}
@@ -1,54 +1,339 @@
 use crate::{
+    attestation::AttestationPool,
     bridge::BridgeManager,
-    consensus::{NarwhalBullsharkEngine},
     db::ChainStore,
-    evm::EvmExecutor,
-    types::ConsensusOutput,
+    evm::{EvmExecutor, TxReceipt},
+    identity::{ValidatorKey, ValidatorSet},
+    sync::{
+        verify_bodies, verify_headers, SyncCommand, SyncRequest, SyncResponse,
+        MAX_BLOCKS_PER_REQUEST, MAX_HEADERS_PER_REQUEST,
+    },
+    types::{
+        AttestationVote, Block, BlockAttestation, CommitCertificate, ConsensusOutput, Receipt,
+        SignedAttestation,
+    },
+    wire::GossipMessage,
 };
-use anyhow::Result;
-use std::sync::Arc;
-use tokio::sync::mpsc::{Receiver};
+use anyhow::{anyhow, bail, Result};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{
+    mpsc::{Receiver, Sender},
+    oneshot,
+};
+use tracing::{error, info, warn};
+
+/// How long sync waits for a peer to report its head before giving up and
+/// starting from the local chain.
+const SYNC_PEER_WAIT: Duration = Duration::from_secs(10);
+/// Consecutive failed sync rounds after which the node stops syncing.
+const SYNC_ATTEMPTS: usize = 3;
 
 pub struct NodeRuntime {
+    store: Arc<ChainStore>,
     executor: Arc<EvmExecutor>,
     consensus_output_rx: Receiver<ConsensusOutput>,
+    /// Block sync requests to the p2p task.
+    sync_tx: Sender<SyncCommand>,
+    /// Attestations of executed blocks are published through here.
+    gossip_tx: Sender<GossipMessage>,
+    /// Attestations other validators gossiped.
+    attestation_rx: Receiver<AttestationVote>,
+    /// Signs attestations; `None` on non-validator nodes.
+    validator_key: Option<Arc<ValidatorKey>>,
+    /// Checks the commit certificates of synced blocks.
+    validators: Arc<ValidatorSet>,
+    attestations: AttestationPool,
     bridge: Arc<BridgeManager>,
 }
 
 impl NodeRuntime {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        store: Arc<ChainStore>,
         executor: Arc<EvmExecutor>,
         consensus_output_rx: Receiver<ConsensusOutput>,
+        sync_tx: Sender<SyncCommand>,
+        gossip_tx: Sender<GossipMessage>,
+        attestation_rx: Receiver<AttestationVote>,
+        validator_key: Option<Arc<ValidatorKey>>,
+        validators: Arc<ValidatorSet>,
         bridge: Arc<BridgeManager>,
-    ) -> Self {
-        Self {
+    ) -> Result<Self> {
+        let runtime = Self {
+            attestations: AttestationPool::new(store.clone(), validators.clone()),
+            store,
             executor,
             consensus_output_rx,
+            sync_tx,
+            gossip_tx,
+            attestation_rx,
+            validator_key,
+            validators,
             bridge,
+        };
+        // The executor starts from the loaded genesis allocations, which
+        // must be the ones block 0 commits to.
+        if let Some(genesis) = runtime.store.get_block(0)? {
+            let local = runtime.executor.state_root();
+            if local != genesis.header.state_root {
+                bail!(
+                    "state divergence: genesis commits to root 0x{}, loaded allocations have 0x{}",
+                    hex::encode(genesis.header.state_root),
+                    hex::encode(local)
+                );
+            }
         }
+        Ok(runtime)
     }
 
+    /// Catch up before the node joins consensus: re-execute the blocks
+    /// stored before a restart, then import certified blocks from peers
+    /// until the local chain reaches the highest certified head they report.
+    pub async fn sync(&mut self) -> Result<()> {
+        let head = self.store.get_head_number()?;
+        for number in self.executor.head_number() + 1..=head {
+            let block = self
+                .store
+                .get_block(number)?
+                .ok_or_else(|| anyhow!("block {number} is missing from the store"))?;
+            self.process_block(block, false)?;
+        }
+        if head > 0 {
+            info!("Replayed local blocks up to {head}");
+        }
+
+        let Some(mut target) = self.wait_for_network_head().await? else {
+            info!("No peers to sync from; starting from block {head}");
+            return self.finish_sync().await;
+        };
+
+        let mut failures = 0;
+        loop {
+            let local = self.store.get_head_number()?;
+            if local >= target {
+                // The network may have moved on while we caught up.
+                match self.network_head().await? {
+                    Some(head) if head > local => target = head,
+                    _ => break,
+                }
+            }
+
+            let blocks = match self.fetch_certified(local, target).await {
+                Ok(blocks) => blocks,
+                Err(e) => {
+                    warn!("Sync after block {local} failed: {e:#}");
+                    failures += 1;
+                    if failures >= SYNC_ATTEMPTS {
+                        warn!("Giving up on sync at block {local}");
+                        break;
+                    }
+                    continue;
+                }
+            };
+            failures = 0;
+
+            for (block, cert) in blocks {
+                self.store.put_block(&block)?;
+                self.store.put_certificate(&cert)?;
+                self.store
+                    .put_head(block.header.number, block.header.hash.0)?;
+                self.process_block(block, false)?;
+            }
+            info!(
+                head = self.store.get_head_number()?,
+                target, "synced blocks"
+            );
+        }
+
+        self.finish_sync().await
+    }
+
+    async fn finish_sync(&self) -> Result<()> {
+        self.sync_tx
+            .send(SyncCommand::Synced)
+            .await
+            .map_err(|_| anyhow!("p2p task stopped"))
+    }
+
+    /// Fetch the headers after `local` from a peer, keep the certified ones
+    /// that extend the local chain, then fetch and check their bodies.
+    async fn fetch_certified(
+        &self,
+        local: u64,
+        target: u64,
+    ) -> Result<Vec<(Block, CommitCertificate)>> {
+        let parent_hash = self
+            .store
+            .get_block(local)?
+            .ok_or_else(|| anyhow!("local head {local} is missing from the store"))?
+            .header
+            .hash;
+
+        let count = (target - local).min(MAX_HEADERS_PER_REQUEST);
+        let request = SyncRequest::Headers {
+            from: local + 1,
+            count,
+        };
+        let SyncResponse::Headers(headers) = self.fetch(request, local + 1).await? else {
+            bail!("peer answered a header request with something else");
+        };
+
+        let certified = verify_headers(&self.validators, local, parent_hash, headers)?;
+
+        let mut blocks = Vec::with_capacity(certified.len());
+        for chunk in certified.chunks(MAX_BLOCKS_PER_REQUEST as usize) {
+            let from = chunk[0].0.number;
+            let count = chunk.len() as u64;
+            let request = SyncRequest::Blocks { from, count };
+            let SyncResponse::Blocks(bodies) = self.fetch(request, from + count - 1).await? else {
+                bail!("peer answered a block request with something else");
+            };
+            blocks.extend(verify_bodies(chunk, bodies)?);
+        }
+        Ok(blocks)
+    }
+
+    async fn fetch(&self, request: SyncRequest, min_head: u64) -> Result<SyncResponse> {
+        let (reply, response) = oneshot::channel();
+        let command = SyncCommand::Fetch {
+            request,
+            min_head,
+            reply,
+        };
+        self.sync_tx
+            .send(command)
+            .await
+            .map_err(|_| anyhow!("p2p task stopped"))?;
+        response
+            .await
+            .map_err(|_| anyhow!("p2p task dropped the request"))?
+    }
+
+    async fn network_head(&self) -> Result<Option<u64>> {
+        let (reply, response) = oneshot::channel();
+        self.sync_tx
+            .send(SyncCommand::NetworkHead(reply))
+            .await
+            .map_err(|_| anyhow!("p2p task stopped"))?;
+        response
+            .await
+            .map_err(|_| anyhow!("p2p task dropped the request"))
+    }
+
+    async fn wait_for_network_head(&self) -> Result<Option<u64>> {
+        let deadline = Instant::now() + SYNC_PEER_WAIT;
+        loop {
+            if let Some(head) = self.network_head().await? {
+                return Ok(Some(head));
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    }
+
+    /// Runs until consensus stops or execution diverges from the attested
+    /// state; an error means the node must halt.
     pub async fn run(mut self) -> Result<()> {
-        while let Some(msg) = self.consensus_output_rx.recv().await {
-            match msg {
-                ConsensusOutput::CommittedBlock(block) => {
-                    // Execute block on EVM (state updates).
-                    let _results = self.executor.execute_block(&block)?;
-
-                    // In a full node you’d now compute and persist state_root, receipts, etc.
-
-                    // Notify bridges (fire-and-forget style).
-                    let bridge = self.bridge.clone();
-                    let block_clone = block.clone();
-                    tokio::spawn(async move {
-                        let _ = bridge.notify_solana(&block_clone).await;
-                        let _ = bridge.notify_sui(&block_clone).await;
-                        let _ = bridge.notify_aptos(&block_clone).await;
-                    });
+        loop {
+            let result = tokio::select! {
+                msg = self.consensus_output_rx.recv() => match msg {
+                    Some(ConsensusOutput::CommittedBlock(block)) => self.process_block(block, true),
+                    None => return Ok(()),
+                },
+                Some(vote) = self.attestation_rx.recv() => {
+                    let signed = self.attestations.add(vote);
+                    self.relay_attestation(signed)
                 }
+            };
+            if let Err(e) = result {
+                error!("{e:#}");
+                return Err(e);
             }
         }
-        Ok(())
     }
-}
 
+    /// Execute a block. `live` blocks were just committed by local
+    /// consensus: their results are attested, which checks them against the
+    /// other validators' and feeds the bridges. Blocks replayed or synced at
+    /// startup are not.
+    fn process_block(&mut self, mut block: Block, live: bool) -> Result<()> {
+        // Execute block on EVM (state updates).
+        let execution = self.executor.execute_block(&block)?;
+
+        // Persist the execution-derived header fields.
+        block.header.base_fee_per_gas = execution.base_fee_per_gas;
+        block.header.gas_used = execution.gas_used;
+        block.header.state_root = execution.state_root;
+        self.store.put_block(&block)?;
+        let receipts: Vec<Receipt> = execution.receipts.iter().map(TxReceipt::receipt).collect();
+        self.store.put_receipts(block.header.number, &receipts)?;
+        for receipt in &execution.receipts {
+            if let Err(invalid) = &receipt.result {
+                warn!(
+                    number = block.header.number,
+                    tx = %receipt.tx_hash,
+                    "skipped invalid transaction: {invalid:?}"
+                );
+            }
+        }
+        info!(
+            number = block.header.number,
+            gas_used = execution.gas_used,
+            base_fee = %execution.base_fee_per_gas,
+            burnt = %execution.burnt_fees,
+            tips = %execution.priority_fees,
+            state_root = %execution.state_root,
+            "executed block"
+        );
+
+        if !live {
+            return Ok(());
+        }
+
+        // The attestation is the execution certificate: validators sign
+        // the root, and a quorum signing another one halts this node.
+        self.attest(BlockAttestation {
+            chain_id: self.validators.chain_id(),
+            number: block.header.number,
+            block_hash: block.header.hash,
+            state_root: execution.state_root,
+            receipts_root: execution.receipts_root,
+        })
+    }
+
+    /// Record the local outcome of a block and, on validators, sign and
+    /// gossip it.
+    fn attest(&mut self, attestation: BlockAttestation) -> Result<()> {
+        if let Some(key) = &self.validator_key {
+            let vote = key.sign_attestation(attestation.clone())?;
+            let _ = self
+                .gossip_tx
+                .try_send(GossipMessage::Attestation(vote.clone()));
+            let signed = self.attestations.add(vote);
+            self.relay_attestation(signed)?;
+        }
+        let signed = self.attestations.executed(attestation);
+        self.relay_attestation(signed)
+    }
+
+    /// Queue a block attestation that reached quorum, and the deposits in
+    /// its block, for the bridges, which hold them until the block is final
+    /// for each destination; the outbox is persisted, so a restart or a
+    /// destination outage delays delivery instead of dropping it.
+    fn relay_attestation(&self, signed: Result<Option<SignedAttestation>>) -> Result<()> {
+        let Some(signed) = signed? else {
+            return Ok(());
+        };
+        let number = signed.attestation.number;
+        let receipts = self
+            .store
+            .get_receipts(number)?
+            .ok_or_else(|| anyhow!("receipts of attested block {number} are missing"))?;
+        self.bridge.relay(&signed, &receipts)
+    }
+}
@@ -1,93 +1,674 @@
-use crate::types::{ConsensusInput, HybridTx, NarwhalBatch};
-use anyhow::Result;
+use crate::config::NodeConfig;
+use crate::db::ChainStore;
+use crate::discovery::{parse_peer_addr, Reconnector};
+use crate::evm::EvmExecutor;
+use crate::identity::{load_node_key, PqHello, ValidatorKey, ValidatorRegistry, ValidatorSet};
+use crate::inbound::InboundBridge;
+use crate::sync::{self, SyncCodec, SyncCommand, SyncProtocol, SyncRequest, SyncResponse};
+use crate::types::{AttestationVote, ConsensusInput};
+use crate::validation::{GossipValidator, Verdict};
+use crate::wire::{GossipMessage, WireCodec, WireError};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::{io, AsyncRead, AsyncWrite, AsyncWriteExt, StreamExt};
 use libp2p::{
-    gossipsub::{self, Gossipsub, GossipsubEvent, IdentTopic, MessageAuthenticity},
-    identity,
-    swarm::{NetworkBehaviour, SwarmBuilder, SwarmEvent},
-    tcp, yamux, PeerId, Swarm,
+    core::upgrade::{self, read_length_prefixed, write_length_prefixed, ProtocolName},
+    gossipsub::{
+        self, Gossipsub, GossipsubEvent, IdentTopic, MessageAcceptance, MessageAuthenticity,
+        PeerScoreParams, PeerScoreThresholds, TopicScoreParams,
+    },
+    identify::{Identify, IdentifyConfig, IdentifyEvent},
+    kad::{store::MemoryStore, Kademlia, KademliaConfig, KademliaEvent},
+    mdns::{MdnsConfig, MdnsEvent, TokioMdns},
+    noise,
+    request_response::{
+        OutboundFailure, ProtocolSupport, RequestId, RequestResponse, RequestResponseCodec,
+        RequestResponseConfig, RequestResponseEvent, RequestResponseMessage,
+    },
+    swarm::{behaviour::toggle::Toggle, SwarmBuilder, SwarmEvent},
+    tcp::{GenTcpConfig, TokioTcpTransport},
+    yamux, NetworkBehaviour, PeerId, Transport,
+};
+use revm::primitives::B256;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{
+    mpsc::{Receiver, Sender},
+    oneshot,
 };
-use libp2p::Transport;
-use libp2p::swarm::NetworkBehaviour;
-use serde::{Deserialize, Serialize};
-use std::time::Duration;
-use tokio::sync::mpsc::Sender;
 use tracing::{info, warn};
 
-const TOPIC_TX: &str = "eth-narwhal-tx";
-const TOPIC_BATCH: &str = "eth-narwhal-batch";
+const TOPIC_TX: &str = "tx";
+const TOPIC_BATCH: &str = "batch";
+const TOPIC_COMMIT: &str = "commit";
+const TOPIC_ATTESTATION: &str = "attestation";
+/// A full batch of signed txs; the gossipsub default of 64 KiB fits only a
+/// few dozen ML-DSA signatures.
+const MAX_GOSSIP_SIZE: usize = 4 * 1024 * 1024;
+
+/// Protocol on which peers exchange `PqHello`s right after connecting.
+const HANDSHAKE_PROTOCOL: &str = "pq-handshake/1";
+/// Fits an ML-DSA-87 signature with room to spare.
+const MAX_HELLO_SIZE: usize = 16 * 1024;
+const SYNC_PROTOCOL: &str = "sync/1";
+/// Gossip for consensus held while the node syncs; past this the oldest is
+/// dropped, as it is the most likely to be in blocks sync brings in anyway.
+const MAX_HELD_GOSSIP: usize = 16 * 1024;
+/// How often connected peers are asked for their certified head.
+const STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+const KAD_PROTOCOL: &str = "kad/1.0.0";
+const IDENTIFY_PROTOCOL: &str = "id/1.0.0";
+/// Peers discovered through the DHT are dialled until this many are connected.
+const TARGET_PEERS: usize = 25;
+const REDIAL_INTERVAL: Duration = Duration::from_secs(1);
+const KAD_BOOTSTRAP_INTERVAL: Duration = Duration::from_secs(300);
+/// Peers scoring below this are disconnected and banned for `BAN_DURATION`.
+/// Equal to the gossipsub graylist threshold, below which their traffic is
+/// already ignored.
+const DISCONNECT_SCORE: f64 = -80.0;
+const BAN_DURATION: Duration = Duration::from_secs(600);
+
+/// Topic and protocol names scoped to one network, identified by chain id and
+/// genesis hash, so that nodes of different networks sharing a LAN or DHT
+/// neither mix gossip nor speak each other's protocols.
+struct NetworkNames {
+    prefix: String,
+}
+
+impl NetworkNames {
+    fn new(chain_id: u64, genesis_hash: B256) -> Self {
+        Self {
+            prefix: format!("/narwhal-evm/{chain_id}/{}", hex::encode(&genesis_hash[..8])),
+        }
+    }
+
+    fn name(&self, name: &str) -> String {
+        format!("{}/{name}", self.prefix)
+    }
+
+    fn topic(&self, name: &str) -> IdentTopic {
+        IdentTopic::new(self.name(name))
+    }
+}
 
 #[derive(NetworkBehaviour)]
 pub struct NodeBehaviour {
     gossipsub: Gossipsub,
+    handshake: RequestResponse<HandshakeCodec>,
+    sync: RequestResponse<SyncCodec>,
+    kademlia: Kademlia<MemoryStore>,
+    identify: Identify,
+    /// Local network discovery, for devnets.
+    mdns: Toggle<TokioMdns>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HandshakeProtocol(String);
+
+impl ProtocolName for HandshakeProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+/// Length-prefixed bincode `PqHello` in both directions.
+#[derive(Debug, Clone)]
+pub struct HandshakeCodec;
+
+async fn read_hello<T: AsyncRead + Unpin + Send>(io: &mut T) -> io::Result<PqHello> {
+    let bytes = read_length_prefixed(io, MAX_HELLO_SIZE).await?;
+    bincode::deserialize(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn write_hello<T: AsyncWrite + Unpin + Send>(io: &mut T, hello: PqHello) -> io::Result<()> {
+    let bytes =
+        bincode::serialize(&hello).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    write_length_prefixed(io, bytes).await?;
+    io.close().await
+}
+
+#[async_trait]
+impl RequestResponseCodec for HandshakeCodec {
+    type Protocol = HandshakeProtocol;
+    type Request = PqHello;
+    type Response = PqHello;
+
+    async fn read_request<T>(&mut self, _: &HandshakeProtocol, io: &mut T) -> io::Result<PqHello>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_hello(io).await
+    }
+
+    async fn read_response<T>(&mut self, _: &HandshakeProtocol, io: &mut T) -> io::Result<PqHello>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_hello(io).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &HandshakeProtocol,
+        io: &mut T,
+        hello: PqHello,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_hello(io, hello).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &HandshakeProtocol,
+        io: &mut T,
+        hello: PqHello,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_hello(io, hello).await
+    }
+}
+
+/// Only invalid deliveries are scored: a quiet chain must not penalise peers
+/// for low mesh traffic. Each rejected message costs 10 points squared with
+/// the number of recent rejections, decaying by 1% a second.
+fn peer_score_params(topics: &[&IdentTopic]) -> PeerScoreParams {
+    let mut params = PeerScoreParams::default();
+    for topic in topics {
+        let topic_params = TopicScoreParams {
+            topic_weight: 1.0,
+            mesh_message_deliveries_weight: 0.0,
+            mesh_failure_penalty_weight: 0.0,
+            invalid_message_deliveries_weight: -10.0,
+            invalid_message_deliveries_decay: 0.99,
+            ..TopicScoreParams::default()
+        };
+        params.topics.insert(topic.hash(), topic_params);
+    }
+    params
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-enum GossipMessage {
-    Tx(HybridTx),
-    Batch(NarwhalBatch),
+/// Where accepted gossip is forwarded.
+enum Inbound {
+    Consensus(ConsensusInput),
+    Attestation(AttestationVote),
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn spawn_p2p(
-    listen_addr: &str,
+    cfg: &NodeConfig,
+    store: Arc<ChainStore>,
+    validator_key: Option<Arc<ValidatorKey>>,
     consensus_tx: Sender<ConsensusInput>,
+    attestation_tx: Sender<AttestationVote>,
+    inbound: Option<Arc<InboundBridge>>,
+    executor: Arc<EvmExecutor>,
+    mut outbound_rx: Receiver<GossipMessage>,
+    mut sync_rx: Receiver<SyncCommand>,
 ) -> Result<()> {
-    let local_key = identity::Keypair::generate_ed25519();
+    let genesis_hash = store
+        .get_genesis_hash()?
+        .ok_or_else(|| anyhow!("database has no genesis"))?;
+    let local_key = load_node_key(cfg)?;
     let local_peer_id = PeerId::from(local_key.public());
     info!("Local peer id: {local_peer_id}");
 
-    let transport = tcp::tokio::Transport::new(tcp::Config::default())
-        .upgrade(libp2p::core::upgrade::Version::V1Lazy)
-        .authenticate(libp2p::noise::Config::new(
-            &local_key
-        )?)
-        .multiplex(yamux::Config::default())
+    // Validators prove their ML-DSA key to every peer; full nodes only check.
+    if let Some(key) = &validator_key {
+        info!("Authenticating as validator {}", key.id);
+    }
+    let hello = PqHello::new(cfg.chain_id, validator_key.as_deref(), &local_peer_id)?;
+    let validator_set = Arc::new(ValidatorSet::new(cfg.chain_id, &cfg.validators)?);
+    let mut registry = ValidatorRegistry::new(validator_set.clone());
+    let validator = GossipValidator::new(cfg, validator_set, inbound, executor);
+    let codec = WireCodec::new(cfg.chain_id, cfg.gossip_compression);
+    let names = NetworkNames::new(cfg.chain_id, genesis_hash);
+    let kad_protocol = names.name(KAD_PROTOCOL);
+    let network_version = names.name(IDENTIFY_PROTOCOL);
+    info!("Network {}", names.prefix);
+
+    let transport = TokioTcpTransport::new(GenTcpConfig::default().nodelay(true))
+        .upgrade(upgrade::Version::V1)
+        .authenticate(noise::NoiseAuthenticated::xx(&local_key)?)
+        .multiplex(yamux::YamuxConfig::default())
         .boxed();
 
-    let gossipsub_config = gossipsub::ConfigBuilder::default()
+    let gossipsub_config = gossipsub::GossipsubConfigBuilder::default()
         .heartbeat_interval(Duration::from_secs(1))
-        .validation_mode(gossipsub::ValidationMode::Permissive)
+        .max_transmit_size(MAX_GOSSIP_SIZE)
+        .validation_mode(gossipsub::ValidationMode::Strict)
+        // Messages are forwarded only once `validator` accepts them.
+        .validate_messages()
         .build()
         .expect("valid gossipsub config");
 
     let mut gossipsub = Gossipsub::new(
         MessageAuthenticity::Signed(local_key.clone()),
         gossipsub_config,
-    )?;
+    )
+    .map_err(anyhow::Error::msg)?;
 
-    let tx_topic = IdentTopic::new(TOPIC_TX);
-    let batch_topic = IdentTopic::new(TOPIC_BATCH);
+    let tx_topic = names.topic(TOPIC_TX);
+    let batch_topic = names.topic(TOPIC_BATCH);
+    let commit_topic = names.topic(TOPIC_COMMIT);
+    let attestation_topic = names.topic(TOPIC_ATTESTATION);
 
     gossipsub.subscribe(&tx_topic)?;
     gossipsub.subscribe(&batch_topic)?;
+    gossipsub.subscribe(&commit_topic)?;
+    gossipsub.subscribe(&attestation_topic)?;
+    gossipsub
+        .with_peer_score(
+            peer_score_params(&[&tx_topic, &batch_topic, &commit_topic, &attestation_topic]),
+            PeerScoreThresholds::default(),
+        )
+        .map_err(anyhow::Error::msg)?;
+
+    let handshake = RequestResponse::new(
+        HandshakeCodec,
+        [(HandshakeProtocol(names.name(HANDSHAKE_PROTOCOL)), ProtocolSupport::Full)],
+        RequestResponseConfig::default(),
+    );
+
+    let sync = RequestResponse::new(
+        SyncCodec,
+        [(SyncProtocol(names.name(SYNC_PROTOCOL)), ProtocolSupport::Full)],
+        RequestResponseConfig::default(),
+    );
+
+    let mut kad_config = KademliaConfig::default();
+    kad_config.set_protocol_names(vec![Cow::Owned(kad_protocol.clone().into_bytes())]);
+    let mut kademlia =
+        Kademlia::with_config(local_peer_id, MemoryStore::new(local_peer_id), kad_config);
+
+    let bootnodes = cfg
+        .bootnodes
+        .iter()
+        .map(|addr| parse_peer_addr(addr))
+        .collect::<Result<Vec<_>>>()?;
+    let static_peers = cfg
+        .static_peers
+        .iter()
+        .map(|addr| parse_peer_addr(addr))
+        .collect::<Result<Vec<_>>>()?;
+    for (peer, addr) in bootnodes.iter().chain(&static_peers) {
+        kademlia.add_address(peer, addr.clone());
+    }
+    let mut reconnector = Reconnector::new(&static_peers, &bootnodes);
+
+    // Peers compare `protocol_version` to tell whether they share a network.
+    let identify = Identify::new(IdentifyConfig::new(
+        network_version.clone(),
+        local_key.public(),
+    ));
 
-    let behaviour = NodeBehaviour { gossipsub };
+    let mdns = match cfg.enable_mdns {
+        true => Some(TokioMdns::new(MdnsConfig::default())?),
+        false => None,
+    };
 
-    let mut swarm = SwarmBuilder::with_tokio_executor(transport, behaviour, local_peer_id)
+    let behaviour = NodeBehaviour {
+        gossipsub,
+        handshake,
+        sync,
+        kademlia,
+        identify,
+        mdns: Toggle::from(mdns),
+    };
+
+    let mut swarm = SwarmBuilder::new(transport, behaviour, local_peer_id)
+        .executor(Box::new(|fut| {
+            tokio::spawn(fut);
+        }))
         .build();
 
-    swarm.listen_on(listen_addr.parse()?)?;
+    swarm.listen_on(cfg.libp2p_listen.parse()?)?;
 
     tokio::spawn(async move {
+        let mut redial = tokio::time::interval(REDIAL_INTERVAL);
+        let mut bootstrap = tokio::time::interval(KAD_BOOTSTRAP_INTERVAL);
+        let mut status = tokio::time::interval(STATUS_INTERVAL);
+        let mut banned: HashMap<PeerId, Instant> = HashMap::new();
+        // Certified heads peers reported, and sync requests awaiting replies.
+        let mut peer_heads: HashMap<PeerId, u64> = HashMap::new();
+        let mut pending: HashMap<RequestId, oneshot::Sender<Result<SyncResponse>>> = HashMap::new();
+        let mut fetches = 0usize;
+        // Consensus only starts once the node has synced; until then its
+        // gossip is held here.
+        let mut held: Option<VecDeque<ConsensusInput>> = Some(VecDeque::new());
+
         loop {
-            match swarm.select_next_some().await {
+            let event = tokio::select! {
+                event = swarm.select_next_some() => event,
+                _ = redial.tick() => {
+                    let now = Instant::now();
+                    banned.retain(|peer, until| {
+                        let expired = *until <= now;
+                        if expired {
+                            swarm.unban_peer_id(*peer);
+                        }
+                        !expired
+                    });
+                    let misbehaving: Vec<PeerId> = swarm
+                        .connected_peers()
+                        .filter(|peer| {
+                            let score = swarm.behaviour().gossipsub.peer_score(peer);
+                            score.map_or(false, |score| score < DISCONNECT_SCORE)
+                        })
+                        .copied()
+                        .collect();
+                    for peer in misbehaving {
+                        match registry.validator_of(&peer) {
+                            Some(id) => {
+                                warn!("Banning peer {peer} (validator {id}) for invalid gossip")
+                            }
+                            None => warn!("Banning peer {peer} for invalid gossip"),
+                        }
+                        swarm.ban_peer_id(peer);
+                        banned.insert(peer, now + BAN_DURATION);
+                    }
+
+                    let connected = swarm.connected_peers().count();
+                    for (peer, addr) in reconnector.due(connected) {
+                        if swarm.is_connected(&peer) {
+                            reconnector.on_connected(&peer);
+                        } else if let Err(e) = swarm.dial(addr) {
+                            warn!("Dialing {peer} failed: {e}");
+                            reconnector.on_disconnected(&peer);
+                        }
+                    }
+                    continue;
+                }
+                Some(msg) = outbound_rx.recv() => {
+                    let topic = match &msg {
+                        GossipMessage::Tx(_) => tx_topic.hash(),
+                        GossipMessage::Batch(_) => batch_topic.hash(),
+                        GossipMessage::CommitVote(_) => commit_topic.hash(),
+                        GossipMessage::Attestation(_) => attestation_topic.hash(),
+                    };
+                    match codec.encode(&msg) {
+                        Ok(bytes) => {
+                            // Fails with no subscribed peers, which is normal
+                            // while the node is alone.
+                            if let Err(e) = swarm.behaviour_mut().gossipsub.publish(topic, bytes) {
+                                warn!("Publishing gossip failed: {e:?}");
+                            }
+                        }
+                        Err(e) => warn!("Encoding gossip failed: {e}"),
+                    }
+                    continue;
+                }
+                Some(command) = sync_rx.recv() => {
+                    match command {
+                        SyncCommand::NetworkHead(reply) => {
+                            let _ = reply.send(peer_heads.values().max().copied());
+                        }
+                        SyncCommand::Fetch { request, min_head, reply } => {
+                            // Rotate over the peers that have the blocks, so
+                            // a retry after bad data goes to another peer.
+                            let candidates: Vec<PeerId> = peer_heads
+                                .iter()
+                                .filter(|(_, head)| **head >= min_head)
+                                .map(|(peer, _)| *peer)
+                                .collect();
+                            if candidates.is_empty() {
+                                let _ = reply.send(Err(anyhow!("no peer has block {min_head}")));
+                                continue;
+                            }
+                            fetches += 1;
+                            let peer = candidates[fetches % candidates.len()];
+                            let id = swarm.behaviour_mut().sync.send_request(&peer, request);
+                            pending.insert(id, reply);
+                        }
+                        SyncCommand::Synced => {
+                            for input in held.take().into_iter().flatten() {
+                                if consensus_tx.send(input).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    continue;
+                }
+                _ = status.tick() => {
+                    let peers: Vec<PeerId> = swarm.connected_peers().copied().collect();
+                    for peer in peers {
+                        swarm.behaviour_mut().sync.send_request(&peer, SyncRequest::Status);
+                    }
+                    continue;
+                }
+                _ = bootstrap.tick() => {
+                    // Fails only while the routing table is empty.
+                    let _ = swarm.behaviour_mut().kademlia.bootstrap();
+                    continue;
+                }
+            };
+
+            match event {
                 SwarmEvent::Behaviour(NodeBehaviourEvent::Gossipsub(
-                    GossipsubEvent::Message { message, .. },
+                    GossipsubEvent::Message {
+                        propagation_source,
+                        message_id,
+                        message,
+                    },
                 )) => {
-                    if let Ok(msg) = serde_json::from_slice::<GossipMessage>(&message.data) {
-                        match msg {
-                            GossipMessage::Tx(tx) => {
-                                let _ = consensus_tx
-                                    .send(ConsensusInput::NewTx(tx))
-                                    .await;
+                    let (verdict, input) = match codec.decode(&message.data) {
+                        Ok(GossipMessage::Tx(tx)) if message.topic == tx_topic.hash() => (
+                            validator.validate_tx(&tx),
+                            Some(Inbound::Consensus(ConsensusInput::NewTx(tx))),
+                        ),
+                        Ok(GossipMessage::Batch(batch)) if message.topic == batch_topic.hash() => (
+                            validator.validate_batch(&batch),
+                            Some(Inbound::Consensus(ConsensusInput::NarwhalBatch(batch))),
+                        ),
+                        Ok(GossipMessage::CommitVote(vote))
+                            if message.topic == commit_topic.hash() =>
+                        {
+                            (
+                                validator.validate_commit_vote(&vote),
+                                Some(Inbound::Consensus(ConsensusInput::CommitVote(vote))),
+                            )
+                        }
+                        Ok(GossipMessage::Attestation(vote))
+                            if message.topic == attestation_topic.hash() =>
+                        {
+                            (
+                                validator.validate_attestation(&vote),
+                                Some(Inbound::Attestation(vote)),
+                            )
+                        }
+                        Ok(_) => (Verdict::Reject("message on the wrong topic".into()), None),
+                        // A peer on a newer protocol is not misbehaving.
+                        Err(e @ WireError::UnsupportedVersion(_)) => {
+                            (Verdict::Ignore(e.to_string()), None)
+                        }
+                        Err(e) => (Verdict::Reject(e.to_string()), None),
+                    };
+
+                    let acceptance = match &verdict {
+                        Verdict::Accept => MessageAcceptance::Accept,
+                        Verdict::Ignore(_) => MessageAcceptance::Ignore,
+                        Verdict::Reject(reason) => {
+                            warn!("Rejecting gossip from {propagation_source}: {reason}");
+                            MessageAcceptance::Reject
+                        }
+                    };
+                    let _ = swarm.behaviour_mut().gossipsub.report_message_validation_result(
+                        &message_id,
+                        &propagation_source,
+                        acceptance,
+                    );
+
+                    // Consensus backpressures the swarm once it runs. The
+                    // runtime does not collect attestations while it syncs,
+                    // so those that do not fit are dropped.
+                    match (verdict, input) {
+                        (Verdict::Accept, Some(Inbound::Consensus(input))) => match &mut held {
+                            Some(held) => {
+                                if held.len() == MAX_HELD_GOSSIP {
+                                    held.pop_front();
+                                }
+                                held.push_back(input);
                             }
-                            GossipMessage::Batch(batch) => {
-                                let _ = consensus_tx
-                                    .send(ConsensusInput::NarwhalBatch(batch))
-                                    .await;
+                            None => {
+                                if consensus_tx.send(input).await.is_err() {
+                                    warn!("Consensus stopped; dropping gossip");
+                                }
                             }
+                        },
+                        (Verdict::Accept, Some(Inbound::Attestation(vote))) => {
+                            let _ = attestation_tx.try_send(vote);
                         }
+                        _ => {}
                     }
                 }
+                SwarmEvent::Behaviour(NodeBehaviourEvent::Handshake(
+                    RequestResponseEvent::Message { peer, message },
+                )) => {
+                    let (their_hello, channel) = match message {
+                        RequestResponseMessage::Request { request, channel, .. } => {
+                            (request, Some(channel))
+                        }
+                        RequestResponseMessage::Response { response, .. } => (response, None),
+                    };
+                    match registry.verify(peer, &their_hello) {
+                        Ok(validator) => {
+                            if let Some(id) = validator {
+                                info!("Peer {peer} authenticated as validator {id}");
+                            }
+                            if let Some(channel) = channel {
+                                let _ = swarm
+                                    .behaviour_mut()
+                                    .handshake
+                                    .send_response(channel, hello.clone());
+                            }
+                            swarm
+                                .behaviour_mut()
+                                .sync
+                                .send_request(&peer, SyncRequest::Status);
+                        }
+                        Err(e) => {
+                            warn!("Handshake rejected: {e:#}");
+                            let _ = swarm.disconnect_peer_id(peer);
+                        }
+                    }
+                }
+                SwarmEvent::Behaviour(NodeBehaviourEvent::Handshake(
+                    RequestResponseEvent::OutboundFailure { peer, error, .. },
+                )) => {
+                    warn!("Handshake with {peer} failed: {error:?}");
+                    // The handshake protocol is network-scoped, so a peer
+                    // without it is on another network.
+                    if matches!(error, OutboundFailure::UnsupportedProtocols) {
+                        let _ = swarm.disconnect_peer_id(peer);
+                    }
+                }
+                SwarmEvent::Behaviour(NodeBehaviourEvent::Sync(
+                    RequestResponseEvent::Message { peer, message },
+                )) => match message {
+                    RequestResponseMessage::Request { request, channel, .. } => {
+                        match sync::serve(&store, &request) {
+                            Ok(response) => {
+                                let _ = swarm.behaviour_mut().sync.send_response(channel, response);
+                            }
+                            Err(e) => warn!("Serving {request:?} to {peer} failed: {e:#}"),
+                        }
+                    }
+                    RequestResponseMessage::Response { request_id, response } => match response {
+                        SyncResponse::Status { certified_head } => {
+                            peer_heads.insert(peer, certified_head);
+                        }
+                        response => {
+                            if let Some(reply) = pending.remove(&request_id) {
+                                let _ = reply.send(Ok(response));
+                            }
+                        }
+                    },
+                },
+                SwarmEvent::Behaviour(NodeBehaviourEvent::Sync(
+                    RequestResponseEvent::OutboundFailure {
+                        peer,
+                        request_id,
+                        error,
+                    },
+                )) => {
+                    if let Some(reply) = pending.remove(&request_id) {
+                        let _ =
+                            reply.send(Err(anyhow!("sync request to {peer} failed: {error:?}")));
+                    }
+                }
+                SwarmEvent::Behaviour(NodeBehaviourEvent::Identify(
+                    IdentifyEvent::Received { peer_id, info },
+                )) => {
+                    if info.protocol_version != network_version {
+                        warn!(
+                            "Disconnecting {peer_id}: on network {}, not {network_version}",
+                            info.protocol_version
+                        );
+                        swarm.behaviour_mut().kademlia.remove_peer(&peer_id);
+                        let _ = swarm.disconnect_peer_id(peer_id);
+                        continue;
+                    }
+                    // Listen addresses make the peer reachable through the DHT.
+                    if info.protocols.iter().any(|p| *p == kad_protocol) {
+                        for addr in info.listen_addrs {
+                            swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
+                        }
+                    }
+                }
+                SwarmEvent::Behaviour(NodeBehaviourEvent::Kademlia(
+                    KademliaEvent::RoutingUpdated {
+                        peer,
+                        is_new_peer: true,
+                        ..
+                    },
+                )) => {
+                    let below_target = swarm.connected_peers().count() < TARGET_PEERS;
+                    if below_target && !swarm.is_connected(&peer) {
+                        let _ = swarm.dial(peer);
+                    }
+                }
+                SwarmEvent::Behaviour(NodeBehaviourEvent::Mdns(MdnsEvent::Discovered(found))) => {
+                    for (peer, addr) in found {
+                        swarm.behaviour_mut().kademlia.add_address(&peer, addr);
+                        if !swarm.is_connected(&peer) {
+                            let _ = swarm.dial(peer);
+                        }
+                    }
+                }
+                SwarmEvent::OutgoingConnectionError {
+                    peer_id: Some(peer_id),
+                    error,
+                } => {
+                    warn!("Connecting to {peer_id} failed: {error}");
+                    reconnector.on_disconnected(&peer_id);
+                }
+                SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+                    reconnector.on_connected(&peer_id);
+                    // The dialer opens the handshake; the listener answers
+                    // with its own hello.
+                    if endpoint.is_dialer() {
+                        swarm
+                            .behaviour_mut()
+                            .handshake
+                            .send_request(&peer_id, hello.clone());
+                    }
+                }
+                SwarmEvent::ConnectionClosed {
+                    peer_id,
+                    num_established: 0,
+                    ..
+                } => {
+                    registry.remove(&peer_id);
+                    peer_heads.remove(&peer_id);
+                    reconnector.on_disconnected(&peer_id);
+                }
                 SwarmEvent::NewListenAddr { address, .. } => {
                     info!("Listening on {address}");
                 }
@@ -102,4 +683,3 @@ pub async fn spawn_p2p(
 
     Ok(())
 }
-
@@ -0,0 +1,601 @@
+use crate::evm::{execute_sequential, PreImages, StateDb, TxResult};
+use crate::precompile::PqPrecompiles;
+use revm::{
+    db::{AccountState as DbAccountState, DatabaseRef},
+    interpreter::{opcode, CallInputs, Gas, InstructionResult, Interpreter},
+    primitives::{
+        AccountInfo, Address, Bytecode, Bytes, EVMError, Env, ResultAndState, TxEnv, B256, U256,
+    },
+    Database, DatabaseCommit, EVMData, Inspector, EVM,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+
+/// A unit of state a transaction can read or write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Location {
+    Basic(Address),
+    Storage(Address, U256),
+}
+
+#[derive(Debug, Clone)]
+enum MvValue {
+    Basic(Option<AccountInfo>),
+    Storage(U256),
+}
+
+/// Transaction index and incarnation that wrote a value.
+type Version = (usize, u32);
+
+/// Multi-version memory: every location keeps the value written by each
+/// transaction of the block, so a transaction reads the latest write of any
+/// transaction ordered before it.
+#[derive(Default)]
+struct MvMemory {
+    data: RwLock<HashMap<Location, BTreeMap<usize, (u32, MvValue)>>>,
+    /// Storage wiped by contract creation or SELFDESTRUCT, per account.
+    wipes: RwLock<HashMap<Address, BTreeMap<usize, u32>>>,
+    /// Code deployed in this block; content addressed, so unversioned.
+    codes: RwLock<HashMap<B256, Bytecode>>,
+    /// Write set of each transaction's last incarnation, for cleanup.
+    written: Mutex<HashMap<usize, (Vec<Location>, Vec<Address>)>>,
+}
+
+impl MvMemory {
+    /// Latest value written by a transaction before `tx_idx`, if any.
+    fn read(&self, location: &Location, tx_idx: usize) -> Option<(Version, MvValue)> {
+        let write = self
+            .data
+            .read()
+            .unwrap()
+            .get(location)
+            .and_then(|writes| writes.range(..tx_idx).next_back())
+            .map(|(idx, (incarnation, value))| ((*idx, *incarnation), value.clone()));
+
+        let Location::Storage(address, _) = location else {
+            return write;
+        };
+        let wipe = self
+            .wipes
+            .read()
+            .unwrap()
+            .get(address)
+            .and_then(|wipes| wipes.range(..tx_idx).next_back())
+            .map(|(idx, incarnation)| (*idx, *incarnation));
+
+        // A transaction wipes before it writes, so a wipe only shadows writes
+        // of earlier transactions.
+        match (write, wipe) {
+            (Some(((write_idx, _), _)), Some(wipe)) if wipe.0 > write_idx => {
+                Some((wipe, MvValue::Storage(U256::ZERO)))
+            }
+            (None, Some(wipe)) => Some((wipe, MvValue::Storage(U256::ZERO))),
+            (write, _) => write,
+        }
+    }
+
+    /// Replace the write set of `tx_idx` with that of a new incarnation.
+    fn publish(&self, tx_idx: usize, incarnation: u32, writes: WriteSet) {
+        let mut written = self.written.lock().unwrap();
+        let mut data = self.data.write().unwrap();
+        let mut wipes = self.wipes.write().unwrap();
+
+        if let Some((locations, wiped)) = written.remove(&tx_idx) {
+            for location in locations {
+                if let Some(entries) = data.get_mut(&location) {
+                    entries.remove(&tx_idx);
+                }
+            }
+            for address in wiped {
+                if let Some(entries) = wipes.get_mut(&address) {
+                    entries.remove(&tx_idx);
+                }
+            }
+        }
+
+        let locations = writes.values.iter().map(|(location, _)| *location).collect();
+        for (location, value) in writes.values {
+            data.entry(location).or_default().insert(tx_idx, (incarnation, value));
+        }
+        for address in &writes.wipes {
+            wipes.entry(*address).or_default().insert(tx_idx, incarnation);
+        }
+        written.insert(tx_idx, (locations, writes.wipes));
+        self.codes.write().unwrap().extend(writes.codes);
+    }
+
+    /// A read set is still valid if every location resolves to the version
+    /// that was observed during execution.
+    fn validate(&self, tx_idx: usize, reads: &[(Location, Option<Version>)]) -> bool {
+        reads.iter().all(|(location, observed)| {
+            self.read(location, tx_idx).map(|(version, _)| version) == *observed
+        })
+    }
+}
+
+#[derive(Default)]
+struct WriteSet {
+    values: Vec<(Location, MvValue)>,
+    wipes: Vec<Address>,
+    codes: Vec<(B256, Bytecode)>,
+}
+
+/// Database seen by one transaction: writes of earlier transactions from the
+/// multi-version memory, then the pre-block state. Records every read.
+struct VersionedDb<'a> {
+    base: &'a StateDb,
+    mv: &'a MvMemory,
+    tx_idx: usize,
+    coinbase: Address,
+    /// `None` version: the value came from the pre-block state.
+    reads: Vec<(Location, Option<Version>)>,
+    coinbase_balance: U256,
+}
+
+impl Database for VersionedDb<'_> {
+    type Error = Infallible;
+
+    fn basic(&mut self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        if address == self.coinbase {
+            // Every transaction credits the coinbase, so tracking it would
+            // serialise the block. Fees are applied as deltas at commit time
+            // and transactions that actually observe it are caught by
+            // `CoinbaseProbe`.
+            let info = self.base.basic(address)?;
+            self.coinbase_balance = info.as_ref().map(|i| i.balance).unwrap_or_default();
+            return Ok(info);
+        }
+
+        let location = Location::Basic(address);
+        match self.mv.read(&location, self.tx_idx) {
+            Some((version, MvValue::Basic(info))) => {
+                self.reads.push((location, Some(version)));
+                Ok(info)
+            }
+            _ => {
+                self.reads.push((location, None));
+                self.base.basic(address)
+            }
+        }
+    }
+
+    fn code_by_hash(&mut self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        if let Some(code) = self.mv.codes.read().unwrap().get(&code_hash) {
+            return Ok(code.clone());
+        }
+        self.base.code_by_hash(code_hash)
+    }
+
+    fn storage(&mut self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        let location = Location::Storage(address, index);
+        match self.mv.read(&location, self.tx_idx) {
+            Some((version, MvValue::Storage(value))) => {
+                self.reads.push((location, Some(version)));
+                Ok(value)
+            }
+            _ => {
+                self.reads.push((location, None));
+                self.base.storage(address, index)
+            }
+        }
+    }
+
+    fn block_hash(&mut self, number: U256) -> Result<B256, Self::Error> {
+        self.base.block_hash(number)
+    }
+}
+
+/// Flags transactions whose outcome may depend on the coinbase account,
+/// which is stale during parallel execution because fees are credited late.
+struct CoinbaseProbe {
+    coinbase: Address,
+    observed: bool,
+}
+
+impl<DB: Database> Inspector<DB> for CoinbaseProbe {
+    fn step(&mut self, interp: &mut Interpreter, _data: &mut EVMData<'_, DB>) -> InstructionResult {
+        let operand = |n: usize| {
+            interp
+                .stack
+                .peek(n)
+                .ok()
+                .map(|word| Address::from_word(B256::from(word)))
+        };
+        let observed = match interp.current_opcode() {
+            opcode::BALANCE | opcode::EXTCODEHASH | opcode::SELFDESTRUCT => {
+                operand(0) == Some(self.coinbase)
+            }
+            opcode::CALL | opcode::CALLCODE | opcode::DELEGATECALL | opcode::STATICCALL => {
+                operand(1) == Some(self.coinbase)
+            }
+            opcode::SELFBALANCE => interp.contract.address == self.coinbase,
+            _ => false,
+        };
+        self.observed |= observed;
+        InstructionResult::Continue
+    }
+
+    fn initialize_interp(
+        &mut self,
+        interp: &mut Interpreter,
+        data: &mut EVMData<'_, DB>,
+    ) -> InstructionResult {
+        PqPrecompiles.initialize_interp(interp, data)
+    }
+
+    fn call(
+        &mut self,
+        data: &mut EVMData<'_, DB>,
+        inputs: &mut CallInputs,
+    ) -> (InstructionResult, Gas, Bytes) {
+        if inputs.contract == self.coinbase || inputs.transfer.target == self.coinbase {
+            self.observed = true;
+        }
+        PqPrecompiles.call(data, inputs)
+    }
+}
+
+struct TxOutcome {
+    result: Result<ResultAndState, EVMError<Infallible>>,
+    reads: Vec<(Location, Option<Version>)>,
+    coinbase_balance: U256,
+    coinbase_observed: bool,
+}
+
+impl TxOutcome {
+    fn write_set(&self, coinbase: Address) -> WriteSet {
+        let mut writes = WriteSet::default();
+        let Ok(ResultAndState { state, .. }) = &self.result else {
+            return writes;
+        };
+
+        for (address, account) in state {
+            if *address == coinbase || !account.is_touched() {
+                continue;
+            }
+            if account.is_selfdestructed() {
+                writes.values.push((Location::Basic(*address), MvValue::Basic(None)));
+                writes.wipes.push(*address);
+                continue;
+            }
+            if account.is_created() {
+                writes.wipes.push(*address);
+            }
+            if let Some(code) = account.info.code.as_ref().filter(|code| !code.is_empty()) {
+                writes.codes.push((account.info.code_hash, code.clone()));
+            }
+            writes.values.push((
+                Location::Basic(*address),
+                MvValue::Basic(Some(account.info.clone())),
+            ));
+            for (slot, value) in &account.storage {
+                writes
+                    .values
+                    .push((Location::Storage(*address, *slot), MvValue::Storage(value.present_value)));
+            }
+        }
+        writes
+    }
+
+    /// Fees this transaction credited to the coinbase.
+    fn coinbase_fee(&self, coinbase: Address) -> U256 {
+        match &self.result {
+            Ok(ResultAndState { state, .. }) => state
+                .get(&coinbase)
+                .map(|account| account.info.balance.saturating_sub(self.coinbase_balance))
+                .unwrap_or_default(),
+            Err(_) => U256::ZERO,
+        }
+    }
+}
+
+fn execute_tx(base: &StateDb, mv: &MvMemory, env: &Env, tx: &TxEnv, tx_idx: usize) -> TxOutcome {
+    let coinbase = env.block.coinbase;
+    let mut env = env.clone();
+    env.tx = tx.clone();
+
+    let mut evm = EVM::with_env(env);
+    evm.database(VersionedDb {
+        base,
+        mv,
+        tx_idx,
+        coinbase,
+        reads: Vec::new(),
+        coinbase_balance: U256::ZERO,
+    });
+
+    let mut probe = CoinbaseProbe {
+        coinbase,
+        observed: tx.caller == coinbase,
+    };
+    let result = evm.inspect(&mut probe);
+    let db = evm.take_db();
+
+    TxOutcome {
+        result,
+        reads: db.reads,
+        coinbase_balance: db.coinbase_balance,
+        coinbase_observed: probe.observed,
+    }
+}
+
+/// Run `f` for every index on up to `workers` threads.
+fn run_on_workers<T: Send>(
+    indices: &[usize],
+    workers: usize,
+    f: impl Fn(usize) -> T + Sync,
+) -> Vec<(usize, T)> {
+    let next = AtomicUsize::new(0);
+    let done = Mutex::new(Vec::with_capacity(indices.len()));
+
+    std::thread::scope(|scope| {
+        for _ in 0..workers.min(indices.len()) {
+            scope.spawn(|| loop {
+                let Some(&idx) = indices.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                    break;
+                };
+                let out = f(idx);
+                done.lock().unwrap().push((idx, out));
+            });
+        }
+    });
+
+    done.into_inner().unwrap()
+}
+
+/// Block-STM style optimistic execution.
+///
+/// All transactions run in parallel against the multi-version memory. After
+/// each round the read sets are validated in block order and transactions
+/// that observed a stale value run again with a new incarnation. Every round
+/// finalises at least the lowest invalid transaction, so this terminates in
+/// at most `txs.len()` rounds. Results are then committed into `db` in block
+/// order, giving the same state and results as [`execute_sequential`].
+pub fn execute_parallel(
+    db: &mut StateDb,
+    env: &Env,
+    txs: &[TxEnv],
+    workers: usize,
+    pre: &mut PreImages,
+) -> Result<Vec<TxResult>, EVMError<Infallible>> {
+    let coinbase = env.block.coinbase;
+    let mv = MvMemory::default();
+    let mut incarnations = vec![0u32; txs.len()];
+    let mut outcomes: Vec<Option<TxOutcome>> = txs.iter().map(|_| None).collect();
+    let mut pending: Vec<usize> = (0..txs.len()).collect();
+
+    while !pending.is_empty() {
+        let base: &StateDb = db;
+        let executed = run_on_workers(&pending, workers, |idx| {
+            execute_tx(base, &mv, env, &txs[idx], idx)
+        });
+        for (idx, outcome) in executed {
+            mv.publish(idx, incarnations[idx], outcome.write_set(coinbase));
+            outcomes[idx] = Some(outcome);
+        }
+
+        pending = outcomes
+            .iter()
+            .enumerate()
+            .filter(|(idx, outcome)| {
+                let outcome = outcome.as_ref().expect("every transaction ran");
+                !mv.validate(*idx, &outcome.reads)
+            })
+            .map(|(idx, _)| idx)
+            .collect();
+        for idx in &pending {
+            incarnations[*idx] += 1;
+        }
+    }
+
+    let outcomes: Vec<TxOutcome> = outcomes.into_iter().flatten().collect();
+
+    // From the first transaction that observed the coinbase on, fall back to
+    // in-order execution on the committed state.
+    let fallback_from = outcomes
+        .iter()
+        .position(|outcome| outcome.coinbase_observed)
+        .unwrap_or(outcomes.len());
+
+    let mut results = Vec::with_capacity(txs.len());
+    for outcome in outcomes.into_iter().take(fallback_from) {
+        let fee = outcome.coinbase_fee(coinbase);
+        // Invalid transactions wrote nothing, so later ones never saw them.
+        let ResultAndState { result, mut state } = match outcome.result {
+            Ok(result_and_state) => result_and_state,
+            Err(EVMError::Transaction(invalid)) => {
+                results.push(Err(invalid));
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        state.remove(&coinbase);
+        pre.record(db, &state);
+        db.commit(state);
+        pre.record_account(db, coinbase);
+        credit(db, coinbase, fee);
+        results.push(Ok(result));
+    }
+    results.extend(execute_sequential(db, env, &txs[fallback_from..], pre)?);
+
+    Ok(results)
+}
+
+fn credit(db: &mut StateDb, address: Address, amount: U256) {
+    if amount == U256::ZERO {
+        return;
+    }
+    let account = match db.load_account(address) {
+        Ok(account) => account,
+        Err(never) => match never {},
+    };
+    account.info.balance += amount;
+    if account.account_state == DbAccountState::NotExisting {
+        account.account_state = DbAccountState::Touched;
+    }
+}
+
+/// Balance, nonce, code hash and non-zero storage of each non-empty account,
+/// in address order.
+pub type CanonicalState = BTreeMap<Address, (U256, u64, B256, BTreeMap<U256, U256>)>;
+
+/// Non-empty accounts with their non-zero storage, in address order. Used to
+/// compare states independently of cache layout.
+pub fn canonical_state(db: &StateDb) -> CanonicalState {
+    db.accounts
+        .iter()
+        .filter_map(|(address, account)| {
+            let info = account.info()?;
+            let storage: BTreeMap<U256, U256> = account
+                .storage
+                .iter()
+                .filter(|(_, value)| **value != U256::ZERO)
+                .map(|(slot, value)| (*slot, *value))
+                .collect();
+            if info.is_empty() && storage.is_empty() {
+                return None;
+            }
+            Some((*address, (info.balance, info.nonce, info.code_hash, storage)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use revm::db::{CacheDB, EmptyDB};
+    use revm::primitives::{SpecId, TransactTo};
+
+    const BASE_FEE: u64 = 7;
+
+    /// `slot = calldata[0..32]; storage[slot] += 1`
+    const COUNTER: [u8; 10] = [0x5f, 0x35, 0x80, 0x54, 0x60, 0x01, 0x01, 0x90, 0x55, 0x00];
+    /// `storage[0] = BALANCE(COINBASE)`: observes the coinbase.
+    const COINBASE_READER: [u8; 5] = [0x41, 0x31, 0x5f, 0x55, 0x00];
+
+    fn counter() -> Address {
+        Address::repeat_byte(0xc0)
+    }
+
+    fn coinbase_reader() -> Address {
+        Address::repeat_byte(0xcb)
+    }
+
+    fn sender(i: usize) -> Address {
+        Address::from_word(B256::from(U256::from(0x1000 + i)))
+    }
+
+    fn state(senders: usize) -> StateDb {
+        let mut db = CacheDB::new(EmptyDB::default());
+        for (address, code) in
+            [(counter(), &COUNTER[..]), (coinbase_reader(), &COINBASE_READER[..])]
+        {
+            let code = Bytecode::new_raw(code.to_vec().into());
+            db.insert_account_info(
+                address,
+                AccountInfo {
+                    code_hash: code.hash_slow(),
+                    code: Some(code),
+                    ..AccountInfo::default()
+                },
+            );
+        }
+        for i in 0..senders {
+            db.insert_account_info(
+                sender(i),
+                AccountInfo {
+                    balance: U256::from(10u64).pow(U256::from(18)),
+                    ..AccountInfo::default()
+                },
+            );
+        }
+        db
+    }
+
+    fn env() -> Env {
+        let mut env = Env::default();
+        env.cfg.spec_id = SpecId::CANCUN;
+        env.block.basefee = U256::from(BASE_FEE);
+        env.block.coinbase = Address::repeat_byte(0xfe);
+        env
+    }
+
+    /// `count` random transactions from `senders` accounts over `slots`
+    /// counter slots and `recipients` transfer targets: few of each make
+    /// transactions conflict, many keep them apart.
+    fn workload(
+        rng: &mut StdRng,
+        count: usize,
+        senders: usize,
+        slots: u64,
+        recipients: usize,
+    ) -> Vec<TxEnv> {
+        let mut nonces = vec![0u64; senders];
+        (0..count)
+            .map(|_| {
+                let from = rng.gen_range(0..senders);
+                let mut tx = TxEnv {
+                    caller: sender(from),
+                    gas_limit: 100_000,
+                    gas_price: U256::from(BASE_FEE + rng.gen_range(0..3)),
+                    nonce: Some(nonces[from]),
+                    ..TxEnv::default()
+                };
+                nonces[from] += 1;
+                match rng.gen_range(0..10) {
+                    0..=4 => {
+                        tx.transact_to = TransactTo::Call(counter());
+                        let slot = U256::from(rng.gen_range(0..slots));
+                        tx.data = slot.to_be_bytes::<32>().to_vec().into();
+                    }
+                    5..=8 => {
+                        // Senders double as recipients.
+                        let to = rng.gen_range(0..recipients);
+                        tx.transact_to = TransactTo::Call(sender(to));
+                        tx.value = U256::from(rng.gen_range(1..1_000u64));
+                    }
+                    _ => tx.transact_to = TransactTo::Call(coinbase_reader()),
+                }
+                tx
+            })
+            .collect()
+    }
+
+    fn assert_same_as_sequential(senders: usize, txs: &[TxEnv]) {
+        let env = env();
+        let mut sequential = state(senders);
+        let expected =
+            execute_sequential(&mut sequential, &env, txs, &mut PreImages::default()).unwrap();
+        let mut parallel = state(senders);
+        let mut pre = PreImages::default();
+        let results = execute_parallel(&mut parallel, &env, txs, 4, &mut pre).unwrap();
+
+        assert_eq!(results, expected);
+        assert_eq!(canonical_state(&parallel), canonical_state(&sequential));
+
+        pre.undo(&mut parallel);
+        assert_eq!(canonical_state(&parallel), canonical_state(&state(senders)));
+    }
+
+    #[test]
+    fn matches_sequential_on_conflicting_workloads() {
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let txs = workload(&mut rng, 48, 4, 3, 6);
+            assert_same_as_sequential(4, &txs);
+        }
+    }
+
+    #[test]
+    fn matches_sequential_on_sparse_workloads() {
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let txs = workload(&mut rng, 48, 48, 1 << 32, 1 << 16);
+            assert_same_as_sequential(48, &txs);
+        }
+    }
+}
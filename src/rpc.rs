@@ -1,12 +1,18 @@
+use crate::bridge::{BridgeManager, OutboxEntry};
+use crate::consensus;
 use crate::db::ChainStore;
-use crate::types::{HybridTx};
+use crate::evm::{self, AccountProof, EvmExecutor};
+use crate::merkle;
+use crate::inbound::InboundBridge;
+use crate::trace::TraceConfig;
+use crate::types::{Block, HybridTx, InboundMessage, SignedAttestation};
 use anyhow::Result;
 use jsonrpsee::{
     core::RpcResult,
     http_server::{HttpServerBuilder, HttpServerHandle},
     proc_macros::rpc,
 };
-use revm::primitives::B256;
+use revm::primitives::{Address, B256, U256};
 use std::{net::SocketAddr, sync::Arc};
 use tokio::sync::mpsc::Sender;
 use crate::types::ConsensusInput;
@@ -34,7 +40,10 @@ pub struct EthApiImpl {
 
 impl EthApiImpl {
     pub fn new(store: Arc<ChainStore>, consensus_tx: Sender<ConsensusInput>) -> Self {
-        Self { store, consensus_tx }
+        Self {
+            store,
+            consensus_tx,
+        }
     }
 }
 
@@ -58,6 +67,10 @@ impl EthApiServer for EthApiImpl {
             "hash": format!("0x{}", hex::encode(b.header.hash.0)),
             "parentHash": format!("0x{}", hex::encode(b.header.parent_hash.0)),
             "timestamp": format!("0x{:x}", b.header.timestamp),
+            "miner": format!("0x{}", hex::encode(b.header.coinbase)),
+            "gasLimit": format!("0x{:x}", b.header.gas_limit),
+            "gasUsed": format!("0x{:x}", b.header.gas_used),
+            "baseFeePerGas": format!("0x{:x}", b.header.base_fee_per_gas),
             "transactions": b.txs.iter().map(|tx| format!("0x{}", hex::encode(tx.hash.0))).collect::<Vec<_>>()
         })))
     }
@@ -66,6 +79,9 @@ impl EthApiServer for EthApiImpl {
         let bytes = hex::decode(tx_hex.trim_start_matches("0x"))
             .map_err(to_rpc_err)?;
         let tx: HybridTx = bincode::deserialize(&bytes).map_err(to_rpc_err)?;
+        if tx.is_system() {
+            return Err(to_rpc_err("system transactions are submitted with bridge_submitInbound"));
+        }
         let hash_str = format!("0x{}", hex::encode(tx.hash.0));
 
         self.consensus_tx
@@ -77,6 +93,309 @@ impl EthApiServer for EthApiImpl {
     }
 }
 
+#[rpc(server)]
+pub trait DebugApi {
+    /// debug_traceTransaction – re-executes the transaction on the state
+    /// before it; only recent blocks can be traced.
+    #[method(name = "debug_traceTransaction")]
+    async fn trace_transaction(
+        &self,
+        tx_hash: B256,
+        config: Option<TraceConfig>,
+    ) -> RpcResult<serde_json::Value>;
+
+    /// debug_traceBlockByNumber
+    #[method(name = "debug_traceBlockByNumber")]
+    async fn trace_block_by_number(
+        &self,
+        number_hex: String,
+        config: Option<TraceConfig>,
+    ) -> RpcResult<Vec<serde_json::Value>>;
+}
+
+pub struct DebugApiImpl {
+    store: Arc<ChainStore>,
+    executor: Arc<EvmExecutor>,
+}
+
+impl DebugApiImpl {
+    pub fn new(store: Arc<ChainStore>, executor: Arc<EvmExecutor>) -> Self {
+        Self { store, executor }
+    }
+}
+
+#[jsonrpsee::core::async_trait]
+impl DebugApiServer for DebugApiImpl {
+    async fn trace_transaction(
+        &self,
+        tx_hash: B256,
+        config: Option<TraceConfig>,
+    ) -> RpcResult<serde_json::Value> {
+        let (number, index) = self
+            .store
+            .get_tx_location(tx_hash)
+            .map_err(to_rpc_err)?
+            .ok_or_else(|| to_rpc_err(format!("transaction 0x{} not found", hex::encode(tx_hash))))?;
+        let block = self
+            .store
+            .get_block(number)
+            .map_err(to_rpc_err)?
+            .ok_or_else(|| to_rpc_err(format!("block {number} not found")))?;
+
+        let executor = self.executor.clone();
+        let config = config.unwrap_or_default();
+        let mut traces = tokio::task::spawn_blocking(move || {
+            executor.trace_block(&block, Some(index), &config)
+        })
+        .await
+        .map_err(to_rpc_err)?
+        .map_err(to_rpc_err)?;
+        Ok(traces.remove(0))
+    }
+
+    async fn trace_block_by_number(
+        &self,
+        number_hex: String,
+        config: Option<TraceConfig>,
+    ) -> RpcResult<Vec<serde_json::Value>> {
+        let n = u64::from_str_radix(number_hex.trim_start_matches("0x"), 16)
+            .map_err(to_rpc_err)?;
+        let block = self
+            .store
+            .get_block(n)
+            .map_err(to_rpc_err)?
+            .ok_or_else(|| to_rpc_err(format!("block {n} not found")))?;
+        let hashes: Vec<B256> = block.txs.iter().map(|tx| tx.hash).collect();
+
+        let executor = self.executor.clone();
+        let config = config.unwrap_or_default();
+        let traces = tokio::task::spawn_blocking(move || executor.trace_block(&block, None, &config))
+            .await
+            .map_err(to_rpc_err)?
+            .map_err(to_rpc_err)?;
+
+        Ok(hashes
+            .into_iter()
+            .zip(traces)
+            .map(|(hash, result)| serde_json::json!({
+                "txHash": format!("0x{}", hex::encode(hash.0)),
+                "result": result,
+            }))
+            .collect())
+    }
+}
+
+#[rpc(server)]
+pub trait BridgeApi {
+    /// bridge_getAttestation – validator attestation of a block, once 2f+1
+    /// of the stake signed it.
+    #[method(name = "bridge_getAttestation")]
+    async fn get_attestation(&self, number_hex: String) -> RpcResult<Option<SignedAttestation>>;
+
+    /// bridge_getDelivery – delivery record of a message (by idempotency
+    /// key) to one destination.
+    #[method(name = "bridge_getDelivery")]
+    async fn get_delivery(&self, adapter: String, key: String) -> RpcResult<Option<OutboxEntry>>;
+
+    /// bridge_deadLetters – messages that exhausted their retries.
+    #[method(name = "bridge_deadLetters")]
+    async fn dead_letters(&self) -> RpcResult<Vec<OutboxEntry>>;
+
+    /// bridge_getTransactionProof – Merkle proof of a transaction, its
+    /// bincode-encoded body being the leaf, against the `tx_root` of its
+    /// block header.
+    #[method(name = "bridge_getTransactionProof")]
+    async fn get_transaction_proof(&self, tx_hash: B256) -> RpcResult<serde_json::Value>;
+
+    /// bridge_getReceiptProof – Merkle proof of a receipt, and so of its
+    /// logs, against the receipts root its block was attested with.
+    #[method(name = "bridge_getReceiptProof")]
+    async fn get_receipt_proof(&self, tx_hash: B256) -> RpcResult<serde_json::Value>;
+
+    /// bridge_getStateProof – an account and some of its slots, with sparse
+    /// Merkle proofs against the state root of the latest or a recent block.
+    /// Not `eth_getProof`: the state is not a Patricia trie. The account is
+    /// proven under `keccak(address)` in the state tree, its leaf value
+    /// being `keccak(balance ‖ nonce ‖ codeHash ‖ storageHash)` (32, 8, 32
+    /// and 32 bytes); each slot under `keccak(slot)` in the storage tree,
+    /// with the 32-byte value as leaf value. A proof lists `siblings` from
+    /// the root down and the `leaf` (`key`, `valueHash`) the path ends at:
+    /// another key's leaf or `null` proves absence (see
+    /// `SparseProof::verify`).
+    #[method(name = "bridge_getStateProof")]
+    async fn get_state_proof(
+        &self,
+        address: Address,
+        storage_keys: Vec<U256>,
+        block: String,
+    ) -> RpcResult<AccountProof>;
+
+    /// bridge_submitInbound – a guardian-signed transfer from another
+    /// chain; returns the hash of the system transaction applying it.
+    #[method(name = "bridge_submitInbound")]
+    async fn submit_inbound(&self, msg: InboundMessage) -> RpcResult<String>;
+}
+
+pub struct BridgeApiImpl {
+    store: Arc<ChainStore>,
+    executor: Arc<EvmExecutor>,
+    bridge: Arc<BridgeManager>,
+    consensus_tx: Sender<ConsensusInput>,
+    inbound: Option<Arc<InboundBridge>>,
+}
+
+impl BridgeApiImpl {
+    pub fn new(
+        store: Arc<ChainStore>,
+        executor: Arc<EvmExecutor>,
+        bridge: Arc<BridgeManager>,
+        consensus_tx: Sender<ConsensusInput>,
+        inbound: Option<Arc<InboundBridge>>,
+    ) -> Self {
+        Self {
+            store,
+            executor,
+            bridge,
+            consensus_tx,
+            inbound,
+        }
+    }
+
+    /// The block including `tx_hash` and the transaction's index in it.
+    fn locate(&self, tx_hash: B256) -> RpcResult<(Block, usize)> {
+        let (number, index) = self
+            .store
+            .get_tx_location(tx_hash)
+            .map_err(to_rpc_err)?
+            .ok_or_else(|| to_rpc_err(format!("transaction 0x{} not found", hex::encode(tx_hash))))?;
+        let block = self
+            .store
+            .get_block(number)
+            .map_err(to_rpc_err)?
+            .ok_or_else(|| to_rpc_err(format!("block {number} not found")))?;
+        Ok((block, index))
+    }
+}
+
+#[jsonrpsee::core::async_trait]
+impl BridgeApiServer for BridgeApiImpl {
+    async fn get_attestation(&self, number_hex: String) -> RpcResult<Option<SignedAttestation>> {
+        let n = u64::from_str_radix(number_hex.trim_start_matches("0x"), 16)
+            .map_err(to_rpc_err)?;
+        self.store.get_attestation(n).map_err(to_rpc_err)
+    }
+
+    async fn get_delivery(&self, adapter: String, key: String) -> RpcResult<Option<OutboxEntry>> {
+        self.bridge.delivery(&adapter, &key).map_err(to_rpc_err)
+    }
+
+    async fn dead_letters(&self) -> RpcResult<Vec<OutboxEntry>> {
+        self.bridge.dead_letters().map_err(to_rpc_err)
+    }
+
+    async fn get_transaction_proof(&self, tx_hash: B256) -> RpcResult<serde_json::Value> {
+        let (block, index) = self.locate(tx_hash)?;
+        let proof = merkle::proof(&consensus::tx_leaves(&block.txs), index)
+            .ok_or_else(|| to_rpc_err(format!("block {} has no transaction {index}", block.header.number)))?;
+        let body = bincode::serialize(&block.txs[index]).map_err(to_rpc_err)?;
+        Ok(serde_json::json!({
+            "blockNumber": format!("0x{:x}", block.header.number),
+            "blockHash": block.header.hash,
+            "txRoot": block.header.tx_root,
+            "index": index,
+            "tx": format!("0x{}", hex::encode(body)),
+            "proof": proof,
+        }))
+    }
+
+    async fn get_receipt_proof(&self, tx_hash: B256) -> RpcResult<serde_json::Value> {
+        let (block, index) = self.locate(tx_hash)?;
+        let number = block.header.number;
+        let receipts = self
+            .store
+            .get_receipts(number)
+            .map_err(to_rpc_err)?
+            .ok_or_else(|| to_rpc_err(format!("block {number} has not been executed")))?;
+        let receipt = receipts
+            .get(index)
+            .ok_or_else(|| to_rpc_err(format!("block {number} has no receipt {index}")))?;
+        let leaves = evm::receipt_leaves(&receipts);
+        let attestation = self.store.get_attestation(number).map_err(to_rpc_err)?;
+        Ok(serde_json::json!({
+            "blockNumber": format!("0x{:x}", number),
+            "blockHash": block.header.hash,
+            "receiptsRoot": merkle::root(&leaves),
+            "index": index,
+            "receipt": format!("0x{}", hex::encode(receipt.encode())),
+            "logs": receipt.logs,
+            "proof": merkle::proof(&leaves, index).expect("index in range"),
+            "attestation": attestation,
+        }))
+    }
+
+    async fn get_state_proof(
+        &self,
+        address: Address,
+        storage_keys: Vec<U256>,
+        block: String,
+    ) -> RpcResult<AccountProof> {
+        let number = match block.as_str() {
+            "latest" | "pending" => None,
+            hex => Some(u64::from_str_radix(hex.trim_start_matches("0x"), 16).map_err(to_rpc_err)?),
+        };
+        let executor = self.executor.clone();
+        tokio::task::spawn_blocking(move || executor.get_proof(address, &storage_keys, number))
+            .await
+            .map_err(to_rpc_err)?
+            .map_err(to_rpc_err)
+    }
+
+    async fn submit_inbound(&self, msg: InboundMessage) -> RpcResult<String> {
+        let inbound = self
+            .inbound
+            .as_ref()
+            .ok_or_else(|| to_rpc_err("inbound transfers are disabled: genesis has no guardians"))?;
+        let tx = inbound.system_tx(&msg).map_err(|e| to_rpc_err(format!("{e:#}")))?;
+        if inbound.is_applied(&self.executor, &msg.transfer) {
+            return Err(to_rpc_err("transfer already applied"));
+        }
+        let hash_str = format!("0x{}", hex::encode(tx.hash.0));
+
+        self.consensus_tx
+            .send(ConsensusInput::NewTx(tx))
+            .await
+            .map_err(to_rpc_err)?;
+
+        Ok(hash_str)
+    }
+}
+
+/// Operator methods, served on `admin_rpc_listen` only.
+#[rpc(server)]
+pub trait AdminApi {
+    /// admin_retryDeadLetter – queue a bridge dead letter again; false if
+    /// there is none under that key.
+    #[method(name = "admin_retryDeadLetter")]
+    async fn retry_dead_letter(&self, adapter: String, key: String) -> RpcResult<bool>;
+}
+
+pub struct AdminApiImpl {
+    bridge: Arc<BridgeManager>,
+}
+
+impl AdminApiImpl {
+    pub fn new(bridge: Arc<BridgeManager>) -> Self {
+        Self { bridge }
+    }
+}
+
+#[jsonrpsee::core::async_trait]
+impl AdminApiServer for AdminApiImpl {
+    async fn retry_dead_letter(&self, adapter: String, key: String) -> RpcResult<bool> {
+        self.bridge.retry_dead_letter(&adapter, &key).map_err(to_rpc_err)
+    }
+}
+
 fn to_rpc_err<E: std::fmt::Display>(e: E) -> jsonrpsee::core::Error {
     jsonrpsee::core::Error::Custom(e.to_string())
 }
@@ -84,10 +403,22 @@ fn to_rpc_err<E: std::fmt::Display>(e: E) -> jsonrpsee::core::Error {
 pub async fn spawn_rpc(
     addr: SocketAddr,
     api_impl: EthApiImpl,
+    debug_impl: DebugApiImpl,
+    bridge_impl: BridgeApiImpl,
 ) -> Result<HttpServerHandle> {
     let server = HttpServerBuilder::default().build(addr).await?;
     let mut module = EthApiServer::into_rpc(api_impl);
+    module.merge(DebugApiServer::into_rpc(debug_impl))?;
+    module.merge(BridgeApiServer::into_rpc(bridge_impl))?;
     let handle = server.start(module)?;
     Ok(handle)
 }
 
+pub async fn spawn_admin_rpc(
+    addr: SocketAddr,
+    admin_impl: AdminApiImpl,
+) -> Result<HttpServerHandle> {
+    let server = HttpServerBuilder::default().build(addr).await?;
+    let handle = server.start(AdminApiServer::into_rpc(admin_impl))?;
+    Ok(handle)
+}
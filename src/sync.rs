@@ -0,0 +1,398 @@
+use crate::consensus::{block_hash, tx_root};
+use crate::db::ChainStore;
+use crate::identity::ValidatorSet;
+use crate::types::{Block, BlockHeader, CommitCertificate};
+use crate::validation::verify_tx_body;
+use anyhow::{ensure, Context, Result};
+use async_trait::async_trait;
+use futures::{io, AsyncRead, AsyncWrite, AsyncWriteExt};
+use libp2p::{
+    core::upgrade::{read_length_prefixed, write_length_prefixed, ProtocolName},
+    request_response::RequestResponseCodec,
+};
+use revm::primitives::B256;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+/// Headers served per request.
+pub const MAX_HEADERS_PER_REQUEST: u64 = 512;
+/// Blocks served per request; a full block of ML-DSA-signed txs runs to
+/// megabytes.
+pub const MAX_BLOCKS_PER_REQUEST: u64 = 16;
+const MAX_REQUEST_SIZE: usize = 1024;
+const MAX_RESPONSE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Chain data a peer can ask for.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SyncRequest {
+    /// Highest certified block of the peer.
+    Status,
+    /// Headers `from..from + count`, with their commit certificates.
+    Headers { from: u64, count: u64 },
+    /// Blocks `from..from + count`.
+    Blocks { from: u64, count: u64 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SyncResponse {
+    Status {
+        certified_head: u64,
+    },
+    /// Stops early at the first block the peer does not have.
+    Headers(Vec<(BlockHeader, Option<CommitCertificate>)>),
+    Blocks(Vec<Block>),
+}
+
+/// Answer a peer's request from the local store.
+pub fn serve(store: &ChainStore, request: &SyncRequest) -> Result<SyncResponse> {
+    Ok(match *request {
+        SyncRequest::Status => SyncResponse::Status {
+            certified_head: store.get_certified_head()?,
+        },
+        SyncRequest::Headers { from, count } => {
+            let mut headers = Vec::new();
+            for number in from..from.saturating_add(count.min(MAX_HEADERS_PER_REQUEST)) {
+                let Some(block) = store.get_block(number)? else {
+                    break;
+                };
+                headers.push((block.header, store.get_certificate(number)?));
+            }
+            SyncResponse::Headers(headers)
+        }
+        SyncRequest::Blocks { from, count } => {
+            let mut blocks = Vec::new();
+            for number in from..from.saturating_add(count.min(MAX_BLOCKS_PER_REQUEST)) {
+                let Some(block) = store.get_block(number)? else {
+                    break;
+                };
+                blocks.push(block);
+            }
+            SyncResponse::Blocks(blocks)
+        }
+    })
+}
+
+/// Check headers served for the blocks after `local`, whose hash is
+/// `parent_hash`, and keep the certified ones that extend it.
+pub fn verify_headers(
+    validators: &ValidatorSet,
+    local: u64,
+    mut parent_hash: B256,
+    headers: Vec<(BlockHeader, Option<CommitCertificate>)>,
+) -> Result<Vec<(BlockHeader, CommitCertificate)>> {
+    let mut certified = Vec::new();
+    for (number, (header, cert)) in (local + 1..).zip(headers) {
+        ensure!(
+            header.number == number,
+            "expected header {number}, got {}",
+            header.number
+        );
+        ensure!(
+            header.parent_hash == parent_hash,
+            "header {number} does not extend the local chain"
+        );
+        ensure!(
+            block_hash(&header) == header.hash,
+            "header {number} has a wrong hash"
+        );
+        // Blocks past the peer's last certificate cannot be checked yet.
+        let Some(cert) = cert else {
+            break;
+        };
+        ensure!(
+            cert.number == number && cert.block_hash == header.hash,
+            "certificate does not match header {number}"
+        );
+        validators.verify_certificate(&cert)?;
+        parent_hash = header.hash;
+        certified.push((header, cert));
+    }
+    ensure!(
+        !certified.is_empty(),
+        "peer sent no certified headers after block {local}"
+    );
+    Ok(certified)
+}
+
+/// Check the bodies served for a run of certified headers.
+pub fn verify_bodies(
+    certified: &[(BlockHeader, CommitCertificate)],
+    bodies: Vec<Block>,
+) -> Result<Vec<(Block, CommitCertificate)>> {
+    let Some((first, _)) = certified.first() else {
+        return Ok(Vec::new());
+    };
+    ensure!(
+        bodies.len() == certified.len(),
+        "peer sent {} of {} blocks from {}",
+        bodies.len(),
+        certified.len(),
+        first.number
+    );
+    let mut blocks = Vec::with_capacity(bodies.len());
+    for ((header, cert), block) in certified.iter().zip(bodies) {
+        ensure!(
+            block_hash(&block.header) == header.hash && tx_root(&block.txs) == header.tx_root,
+            "block {} does not match its certified header",
+            header.number
+        );
+        for tx in &block.txs {
+            verify_tx_body(tx)
+                .with_context(|| format!("block {} carries a bad tx", header.number))?;
+        }
+        blocks.push((block, cert.clone()));
+    }
+    Ok(blocks)
+}
+
+/// Requests from the sync manager to the p2p task, which picks the peer.
+pub enum SyncCommand {
+    /// Highest certified block reported by any connected peer.
+    NetworkHead(oneshot::Sender<Option<u64>>),
+    /// Send a request to a peer whose certified head is at least `min_head`.
+    Fetch {
+        request: SyncRequest,
+        min_head: u64,
+        reply: oneshot::Sender<Result<SyncResponse>>,
+    },
+    /// Sync is over and consensus starts: gossip held for it meanwhile is
+    /// handed over.
+    Synced,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncProtocol(pub String);
+
+impl ProtocolName for SyncProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+/// Length-prefixed bincode requests and responses.
+#[derive(Debug, Clone)]
+pub struct SyncCodec;
+
+async fn read_bincode<T, M>(io: &mut T, max_size: usize) -> io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: DeserializeOwned,
+{
+    let bytes = read_length_prefixed(io, max_size).await?;
+    bincode::deserialize(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn write_bincode<T, M>(io: &mut T, msg: &M) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+    M: Serialize,
+{
+    let bytes =
+        bincode::serialize(msg).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    write_length_prefixed(io, bytes).await?;
+    io.close().await
+}
+
+#[async_trait]
+impl RequestResponseCodec for SyncCodec {
+    type Protocol = SyncProtocol;
+    type Request = SyncRequest;
+    type Response = SyncResponse;
+
+    async fn read_request<T>(&mut self, _: &SyncProtocol, io: &mut T) -> io::Result<SyncRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_bincode(io, MAX_REQUEST_SIZE).await
+    }
+
+    async fn read_response<T>(&mut self, _: &SyncProtocol, io: &mut T) -> io::Result<SyncResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_bincode(io, MAX_RESPONSE_SIZE).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &SyncProtocol,
+        io: &mut T,
+        request: SyncRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_bincode(io, &request).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &SyncProtocol,
+        io: &mut T,
+        response: SyncResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_bincode(io, &response).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::ValidatorKey;
+    use crate::types::HybridTx;
+    use revm::primitives::{Address, Bytes, U256};
+
+    const CHAIN_ID: u64 = 1337;
+
+    /// Four validators of equal stake, so a certificate needs three votes.
+    fn validators() -> (ValidatorSet, Vec<ValidatorKey>) {
+        let (keys, configs): (Vec<_>, Vec<_>) = ["a", "b", "c", "d"]
+            .into_iter()
+            .map(|id| ValidatorKey::generate(CHAIN_ID, id, 1))
+            .unzip();
+        (ValidatorSet::new(CHAIN_ID, &configs).unwrap(), keys)
+    }
+
+    fn block(number: u64, parent_hash: B256, txs: Vec<HybridTx>) -> Block {
+        let mut header = BlockHeader {
+            number,
+            hash: B256::ZERO,
+            parent_hash,
+            state_root: B256::repeat_byte(0x55),
+            tx_root: tx_root(&txs),
+            timestamp: 1_700_000_000 + number,
+            coinbase: Address::repeat_byte(0xcb),
+            prevrandao: B256::repeat_byte(number as u8),
+            gas_limit: 30_000_000,
+            gas_used: 0,
+            base_fee_per_gas: U256::from(7),
+        };
+        header.hash = block_hash(&header);
+        Block { header, txs }
+    }
+
+    fn certificate(keys: &[ValidatorKey], number: u64, block_hash: B256) -> CommitCertificate {
+        let votes = keys
+            .iter()
+            .map(|key| {
+                let vote = key.sign_commit(number, block_hash).unwrap();
+                (vote.validator, vote.signature)
+            })
+            .collect();
+        CommitCertificate {
+            number,
+            block_hash,
+            votes,
+        }
+    }
+
+    /// Blocks 2 and 3 on top of a local head 1 with hash `parent`.
+    fn chain(parent: B256) -> Vec<Block> {
+        let second = block(2, parent, vec![]);
+        let third = block(3, second.header.hash, vec![]);
+        vec![second, third]
+    }
+
+    fn certified(
+        keys: &[ValidatorKey],
+        blocks: &[Block],
+    ) -> Vec<(BlockHeader, Option<CommitCertificate>)> {
+        blocks
+            .iter()
+            .map(|block| {
+                let header = block.header.clone();
+                let cert = certificate(&keys[..3], header.number, header.hash);
+                (header, Some(cert))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn accepts_a_certified_range() {
+        let (validators, keys) = validators();
+        let parent = B256::repeat_byte(1);
+        let mut blocks = chain(parent);
+        let mut headers = certified(&keys, &blocks);
+        // The peer has not certified block 4 yet.
+        let fourth = block(4, blocks[1].header.hash, vec![]);
+        headers.push((fourth.header, None));
+
+        let certified = verify_headers(&validators, 1, parent, headers).unwrap();
+        assert_eq!(certified.len(), 2);
+        // Bodies come from a node that executed them: state roots differ.
+        blocks[0].header.state_root = B256::repeat_byte(0xaa);
+        let synced = verify_bodies(&certified, blocks.clone()).unwrap();
+        assert_eq!(synced.len(), 2);
+        assert_eq!(synced[1].0.header.hash, blocks[1].header.hash);
+        assert_eq!(synced[1].1.block_hash, blocks[1].header.hash);
+    }
+
+    #[test]
+    fn rejects_a_header_with_a_wrong_hash() {
+        let (validators, keys) = validators();
+        let parent = B256::repeat_byte(1);
+        let mut headers = certified(&keys, &chain(parent));
+        headers[1].0.gas_limit += 1;
+        let err = verify_headers(&validators, 1, parent, headers).unwrap_err();
+        assert_eq!(err.to_string(), "header 3 has a wrong hash");
+    }
+
+    #[test]
+    fn rejects_a_body_not_matching_the_tx_root() {
+        let (validators, keys) = validators();
+        let parent = B256::repeat_byte(1);
+        let mut blocks = chain(parent);
+        let headers = certified(&keys, &blocks);
+        let certified = verify_headers(&validators, 1, parent, headers).unwrap();
+
+        let mut tx = HybridTx {
+            hash: B256::ZERO,
+            from: Address::repeat_byte(0xca),
+            to: None,
+            nonce: U256::ZERO,
+            gas_limit: 53_000,
+            max_fee_per_gas: U256::from(7),
+            max_priority_fee_per_gas: U256::ZERO,
+            value: U256::ZERO,
+            data: Bytes::new(),
+            chain_id: CHAIN_ID,
+            sig: None,
+            pq_sig: None,
+            pq_pubkey: None,
+        };
+        tx.hash = tx.tx_hash();
+        blocks[0].txs.push(tx);
+        let err = verify_bodies(&certified, blocks).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "block 2 does not match its certified header"
+        );
+    }
+
+    #[test]
+    fn rejects_a_certificate_for_another_block() {
+        let (validators, keys) = validators();
+        let parent = B256::repeat_byte(1);
+        let mut headers = certified(&keys, &chain(parent));
+        headers[0].1 = Some(certificate(&keys[..3], 2, B256::repeat_byte(2)));
+        let err = verify_headers(&validators, 1, parent, headers).unwrap_err();
+        assert_eq!(err.to_string(), "certificate does not match header 2");
+    }
+
+    #[test]
+    fn rejects_a_certificate_below_quorum() {
+        let (validators, keys) = validators();
+        let parent = B256::repeat_byte(1);
+        let mut headers = certified(&keys, &chain(parent));
+        let hash = headers[1].0.hash;
+        headers[1].1 = Some(certificate(&keys[..2], 3, hash));
+        let err = verify_headers(&validators, 1, parent, headers).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "certificate for block 3 has 2 of the 3 stake needed"
+        );
+    }
+}
@@ -0,0 +1,40 @@
+use crate::db::ChainStore;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A fresh path under the system temp dir, removed together with anything
+/// written to it when the guard is dropped.
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    pub fn new(name: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("{}-{name}", uuid::Uuid::new_v4())))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn to_str(&self) -> &str {
+        self.0.to_str().expect("temp path is not UTF-8")
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        // Best effort: a failed test may not have created anything.
+        let _ = if self.0.is_dir() {
+            std::fs::remove_dir_all(&self.0)
+        } else {
+            std::fs::remove_file(&self.0)
+        };
+    }
+}
+
+/// Opens a store in a fresh temp dir. Bind the guard before the store so it
+/// is dropped after every handle to the store.
+pub fn open_store(name: &str) -> (TempPath, Arc<ChainStore>) {
+    let dir = TempPath::new(name);
+    let store = Arc::new(ChainStore::open(dir.to_str()).unwrap());
+    (dir, store)
+}
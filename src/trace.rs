@@ -0,0 +1,843 @@
+use crate::evm::StateDb;
+use crate::precompile::{PqPrecompiles, MLDSA_VERIFY_ADDRESS};
+use anyhow::{bail, Result};
+use revm::{
+    db::DatabaseRef,
+    interpreter::{
+        opcode, CallInputs, CallScheme, CreateInputs, Gas, InstructionResult, Interpreter,
+    },
+    primitives::{
+        Address, Bytes, CreateScheme, Env, ExecutionResult, ResultAndState, State, B256, U256,
+    },
+    Database, DatabaseCommit, EVMData, Inspector, EVM,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+/// Options of `debug_trace*` calls, as in Geth's `TraceConfig`. No `tracer`
+/// selects the struct (opcode) logger.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TraceConfig {
+    pub tracer: Option<String>,
+    pub tracer_config: Option<Value>,
+    pub disable_stack: bool,
+    pub enable_memory: bool,
+    pub disable_storage: bool,
+    pub enable_return_data: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct CallTracerConfig {
+    only_top_call: bool,
+    with_log: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct PrestateTracerConfig {
+    diff_mode: bool,
+}
+
+/// Execute `env.tx` on `db` under the tracer selected by `config`, commit its
+/// state changes and return the trace in Geth's JSON format.
+pub fn trace_tx(db: &mut StateDb, env: &Env, config: &TraceConfig) -> Result<Value> {
+    let tracer_config = config.tracer_config.clone().unwrap_or(Value::Null);
+
+    let (trace, state) = match config.tracer.as_deref() {
+        None | Some("") => {
+            let mut logger = StructLogger::new(config);
+            let res = run(db, env, &mut logger)?;
+            (logger.into_json(&res.result)?, res.state)
+        }
+        Some("callTracer") => {
+            let opts: CallTracerConfig = parse_tracer_config(tracer_config)?;
+            let mut tracer = CallTracer::new(opts);
+            let res = run(db, env, &mut tracer)?;
+            (tracer.into_json(env, &res.result)?, res.state)
+        }
+        Some("prestateTracer") => {
+            let opts: PrestateTracerConfig = parse_tracer_config(tracer_config)?;
+            let res = run(db, env, PqPrecompiles)?;
+            (prestate(db, &res.state, opts.diff_mode)?, res.state)
+        }
+        Some(other) => bail!("unknown tracer {other:?}"),
+    };
+
+    db.commit(state);
+    Ok(trace)
+}
+
+fn parse_tracer_config<T: Default + serde::de::DeserializeOwned>(value: Value) -> Result<T> {
+    if value.is_null() {
+        return Ok(T::default());
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+fn run<I>(db: &mut StateDb, env: &Env, inspector: I) -> Result<ResultAndState>
+where
+    I: for<'a> Inspector<&'a mut StateDb>,
+{
+    let mut evm = EVM::with_env(env.clone());
+    evm.database(db);
+    Ok(evm.inspect(inspector)?)
+}
+
+/// One entry of the struct logger, as Geth's `StructLogRes`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StructLog {
+    pc: u64,
+    op: String,
+    gas: u64,
+    gas_cost: u64,
+    depth: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stack: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    return_data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    storage: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "is_zero")]
+    refund: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+/// An opcode whose `step_end` has not been seen yet. Calls and creates
+/// nest, so these form a stack.
+struct PendingStep {
+    log: usize,
+    gas_before: u64,
+    sload_key: Option<U256>,
+}
+
+/// Geth's default tracer: one entry per executed opcode.
+struct StructLogger {
+    disable_stack: bool,
+    enable_memory: bool,
+    disable_storage: bool,
+    enable_return_data: bool,
+    logs: Vec<StructLog>,
+    pending: Vec<PendingStep>,
+    /// Slots seen so far per contract, reported on SLOAD/SSTORE.
+    storage: HashMap<Address, BTreeMap<String, String>>,
+}
+
+impl StructLogger {
+    fn new(config: &TraceConfig) -> Self {
+        Self {
+            disable_stack: config.disable_stack,
+            enable_memory: config.enable_memory,
+            disable_storage: config.disable_storage,
+            enable_return_data: config.enable_return_data,
+            logs: Vec::new(),
+            pending: Vec::new(),
+            storage: HashMap::new(),
+        }
+    }
+
+    fn into_json(self, result: &ExecutionResult) -> Result<Value> {
+        Ok(serde_json::json!({
+            "gas": result.gas_used(),
+            "failed": !result.is_success(),
+            "returnValue": hex::encode(result.output().cloned().unwrap_or_default()),
+            "structLogs": serde_json::to_value(self.logs)?,
+        }))
+    }
+}
+
+fn word(value: U256) -> String {
+    hex::encode(value.to_be_bytes::<32>())
+}
+
+impl<DB: Database> Inspector<DB> for StructLogger {
+    fn initialize_interp(
+        &mut self,
+        interp: &mut Interpreter,
+        data: &mut EVMData<'_, DB>,
+    ) -> InstructionResult {
+        Inspector::<DB>::initialize_interp(&mut PqPrecompiles, interp, data)
+    }
+
+    fn step(&mut self, interp: &mut Interpreter, data: &mut EVMData<'_, DB>) -> InstructionResult {
+        let op = interp.current_opcode();
+        let mut storage = None;
+        let mut sload_key = None;
+
+        if !self.disable_storage {
+            let contract = self.storage.entry(interp.contract.address).or_default();
+            match op {
+                opcode::SSTORE => {
+                    if let (Ok(key), Ok(value)) = (interp.stack.peek(0), interp.stack.peek(1)) {
+                        contract.insert(word(key), word(value));
+                    }
+                    storage = Some(contract.clone());
+                }
+                // The loaded value is only known once the opcode has run.
+                opcode::SLOAD => sload_key = interp.stack.peek(0).ok(),
+                _ => {}
+            }
+        }
+
+        self.pending.push(PendingStep {
+            log: self.logs.len(),
+            gas_before: interp.gas.remaining(),
+            sload_key,
+        });
+        self.logs.push(StructLog {
+            pc: interp.program_counter() as u64,
+            op: match opcode::OPCODE_JUMPMAP[op as usize] {
+                Some(name) => name.to_string(),
+                None => format!("opcode {op:#04x} not defined"),
+            },
+            gas: interp.gas.remaining(),
+            gas_cost: 0,
+            depth: data.journaled_state.depth(),
+            error: None,
+            stack: (!self.disable_stack).then(|| {
+                interp
+                    .stack
+                    .data()
+                    .iter()
+                    .map(|v| format!("{v:#x}"))
+                    .collect()
+            }),
+            return_data: self
+                .enable_return_data
+                .then(|| format!("0x{}", hex::encode(&interp.return_data_buffer))),
+            memory: self
+                .enable_memory
+                .then(|| interp.memory.data().chunks(32).map(hex::encode).collect()),
+            storage,
+            refund: interp.gas.refunded().max(0) as u64,
+        });
+        InstructionResult::Continue
+    }
+
+    fn step_end(
+        &mut self,
+        interp: &mut Interpreter,
+        _data: &mut EVMData<'_, DB>,
+        eval: InstructionResult,
+    ) -> InstructionResult {
+        let Some(step) = self.pending.pop() else {
+            return InstructionResult::Continue;
+        };
+        let log = &mut self.logs[step.log];
+        log.gas_cost = step.gas_before.saturating_sub(interp.gas.remaining());
+        if eval.is_error() {
+            log.error = Some(format!("{eval:?}"));
+        }
+
+        if let (Some(key), Ok(value)) = (step.sload_key, interp.stack.peek(0)) {
+            let contract = self.storage.entry(interp.contract.address).or_default();
+            contract.insert(word(key), word(value));
+            log.storage = Some(contract.clone());
+        }
+        InstructionResult::Continue
+    }
+
+    fn call(
+        &mut self,
+        data: &mut EVMData<'_, DB>,
+        inputs: &mut CallInputs,
+    ) -> (InstructionResult, Gas, Bytes) {
+        Inspector::<DB>::call(&mut PqPrecompiles, data, inputs)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct CallLog {
+    address: Address,
+    topics: Vec<B256>,
+    data: Bytes,
+}
+
+/// A call frame of Geth's `callTracer`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CallFrame {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    from: Address,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<Address>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<U256>,
+    gas: U256,
+    gas_used: U256,
+    input: Bytes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<Bytes>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revert_reason: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    logs: Vec<CallLog>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    calls: Vec<CallFrame>,
+}
+
+impl CallFrame {
+    fn finish(&mut self, ret: InstructionResult, gas: &Gas, out: &Bytes) {
+        self.gas_used = U256::from(gas.spend());
+        if !out.is_empty() {
+            self.output = Some(out.clone());
+        }
+        if ret.is_revert() {
+            self.error = Some("execution reverted".to_string());
+            self.revert_reason = revert_reason(out);
+        } else if !ret.is_ok() {
+            self.error = Some(format!("{ret:?}"));
+        }
+        if self.error.is_some() {
+            self.clear_logs();
+        }
+    }
+
+    /// Logs of failed frames are discarded by the EVM, so they are dropped
+    /// from the trace as well.
+    fn clear_logs(&mut self) {
+        self.logs.clear();
+        self.calls.iter_mut().for_each(CallFrame::clear_logs);
+    }
+}
+
+/// Decodes the message of a Solidity `Error(string)` revert.
+fn revert_reason(out: &[u8]) -> Option<String> {
+    const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+    let body = out.strip_prefix(&ERROR_SELECTOR)?;
+    let len = U256::try_from_be_slice(body.get(32..64)?)?.saturating_to::<usize>();
+    let reason = body.get(64..64usize.checked_add(len)?)?;
+    String::from_utf8(reason.to_vec()).ok()
+}
+
+/// Geth's `callTracer`: the tree of calls and creates made by the transaction.
+struct CallTracer {
+    config: CallTracerConfig,
+    stack: Vec<CallFrame>,
+    root: Option<CallFrame>,
+}
+
+impl CallTracer {
+    fn new(config: CallTracerConfig) -> Self {
+        Self {
+            config,
+            stack: Vec::new(),
+            root: None,
+        }
+    }
+
+    fn enter(&mut self, frame: CallFrame) {
+        self.stack.push(frame);
+    }
+
+    fn exit(&mut self, ret: InstructionResult, gas: &Gas, out: &Bytes, created: Option<Address>) {
+        let Some(mut frame) = self.stack.pop() else {
+            return;
+        };
+        frame.finish(ret, gas, out);
+        if created.is_some() {
+            frame.to = created;
+        }
+        match self.stack.last_mut() {
+            Some(parent) if !self.config.only_top_call => parent.calls.push(frame),
+            Some(_) => {}
+            None => self.root = Some(frame),
+        }
+    }
+
+    fn into_json(self, env: &Env, result: &ExecutionResult) -> Result<Value> {
+        let Some(mut root) = self.root else {
+            bail!("transaction produced no call frame");
+        };
+        // The outer frame covers the whole transaction, intrinsic gas included.
+        root.gas = U256::from(env.tx.gas_limit);
+        root.gas_used = U256::from(result.gas_used());
+        Ok(serde_json::to_value(root)?)
+    }
+}
+
+impl<DB: Database> Inspector<DB> for CallTracer {
+    fn initialize_interp(
+        &mut self,
+        interp: &mut Interpreter,
+        data: &mut EVMData<'_, DB>,
+    ) -> InstructionResult {
+        Inspector::<DB>::initialize_interp(&mut PqPrecompiles, interp, data)
+    }
+
+    fn log(
+        &mut self,
+        _data: &mut EVMData<'_, DB>,
+        address: &Address,
+        topics: &[B256],
+        data: &Bytes,
+    ) {
+        if !self.config.with_log {
+            return;
+        }
+        if let Some(frame) = self.stack.last_mut() {
+            frame.logs.push(CallLog {
+                address: *address,
+                topics: topics.to_vec(),
+                data: data.clone(),
+            });
+        }
+    }
+
+    fn call(
+        &mut self,
+        data: &mut EVMData<'_, DB>,
+        inputs: &mut CallInputs,
+    ) -> (InstructionResult, Gas, Bytes) {
+        let (kind, value) = match inputs.context.scheme {
+            CallScheme::Call => ("CALL", Some(inputs.transfer.value)),
+            CallScheme::CallCode => ("CALLCODE", Some(inputs.transfer.value)),
+            CallScheme::DelegateCall => ("DELEGATECALL", None),
+            CallScheme::StaticCall => ("STATICCALL", None),
+        };
+        self.enter(CallFrame {
+            kind,
+            from: inputs.context.caller,
+            to: Some(inputs.contract),
+            value,
+            gas: U256::from(inputs.gas_limit),
+            gas_used: U256::ZERO,
+            input: inputs.input.clone(),
+            output: None,
+            error: None,
+            revert_reason: None,
+            logs: Vec::new(),
+            calls: Vec::new(),
+        });
+        Inspector::<DB>::call(&mut PqPrecompiles, data, inputs)
+    }
+
+    fn call_end(
+        &mut self,
+        _data: &mut EVMData<'_, DB>,
+        _inputs: &CallInputs,
+        remaining_gas: Gas,
+        ret: InstructionResult,
+        out: Bytes,
+    ) -> (InstructionResult, Gas, Bytes) {
+        self.exit(ret, &remaining_gas, &out, None);
+        (ret, remaining_gas, out)
+    }
+
+    fn create(
+        &mut self,
+        _data: &mut EVMData<'_, DB>,
+        inputs: &mut CreateInputs,
+    ) -> (InstructionResult, Option<Address>, Gas, Bytes) {
+        let kind = match inputs.scheme {
+            CreateScheme::Create => "CREATE",
+            CreateScheme::Create2 { .. } => "CREATE2",
+        };
+        self.enter(CallFrame {
+            kind,
+            from: inputs.caller,
+            to: None,
+            value: Some(inputs.value),
+            gas: U256::from(inputs.gas_limit),
+            gas_used: U256::ZERO,
+            input: inputs.init_code.clone(),
+            output: None,
+            error: None,
+            revert_reason: None,
+            logs: Vec::new(),
+            calls: Vec::new(),
+        });
+        (InstructionResult::Continue, None, Gas::new(0), Bytes::new())
+    }
+
+    fn create_end(
+        &mut self,
+        _data: &mut EVMData<'_, DB>,
+        _inputs: &CreateInputs,
+        ret: InstructionResult,
+        address: Option<Address>,
+        remaining_gas: Gas,
+        out: Bytes,
+    ) -> (InstructionResult, Option<Address>, Gas, Bytes) {
+        self.exit(ret, &remaining_gas, &out, address);
+        (ret, address, remaining_gas, out)
+    }
+}
+
+/// An account in Geth's `prestateTracer` output; absent fields are omitted.
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct AccountDump {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    balance: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<Bytes>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    storage: BTreeMap<B256, B256>,
+}
+
+/// Geth's `prestateTracer`. `db` holds the state before the transaction and
+/// `state` the accounts it loaded, with their values after it. In diff mode
+/// only modified fields are reported, as `{pre, post}`.
+fn prestate(db: &StateDb, state: &State, diff_mode: bool) -> Result<Value> {
+    let mut pre = BTreeMap::new();
+    let mut post = BTreeMap::new();
+
+    for (address, account) in state {
+        // Loaded into every transaction to keep it warm, not read by it.
+        if *address == MLDSA_VERIFY_ADDRESS && !account.is_touched() {
+            continue;
+        }
+
+        let before = DatabaseRef::basic(db, *address)?;
+        let before_code = match &before {
+            Some(info) => match &info.code {
+                Some(code) => code.original_bytes(),
+                None => DatabaseRef::code_by_hash(db, info.code_hash)?.original_bytes(),
+            },
+            None => Bytes::new(),
+        };
+
+        let mut pre_storage = BTreeMap::new();
+        let mut post_storage = BTreeMap::new();
+        for (slot, value) in &account.storage {
+            let original = DatabaseRef::storage(db, *address, *slot)?;
+            if !diff_mode || original != value.present_value {
+                pre_storage.insert(
+                    B256::from(slot.to_be_bytes()),
+                    B256::from(original.to_be_bytes()),
+                );
+            }
+            if original != value.present_value {
+                post_storage.insert(
+                    B256::from(slot.to_be_bytes()),
+                    B256::from(value.present_value.to_be_bytes()),
+                );
+            }
+        }
+
+        let pre_dump = match &before {
+            Some(info) => Some(AccountDump {
+                balance: Some(info.balance),
+                nonce: (info.nonce > 0).then_some(info.nonce),
+                code: (!before_code.is_empty()).then(|| before_code.clone()),
+                storage: pre_storage,
+            }),
+            // Like Geth, accounts the transaction brings into existence are
+            // dumped with a zero balance.
+            None if !diff_mode => Some(AccountDump {
+                balance: Some(U256::ZERO),
+                storage: pre_storage,
+                ..AccountDump::default()
+            }),
+            None => None,
+        };
+
+        if !diff_mode {
+            pre.insert(*address, pre_dump.unwrap_or_default());
+            continue;
+        }
+
+        // Touched but still empty, e.g. a coinbase paid nothing: never existed.
+        if before.is_none() && account.info.is_empty() {
+            continue;
+        }
+
+        if account.is_selfdestructed() {
+            if let Some(dump) = pre_dump {
+                pre.insert(*address, dump);
+            }
+            continue;
+        }
+
+        let info = &account.info;
+        let after_code = info
+            .code
+            .as_ref()
+            .map(|code| code.original_bytes())
+            .unwrap_or_default();
+        let post_dump = AccountDump {
+            balance: (before.as_ref().map(|b| b.balance) != Some(info.balance))
+                .then_some(info.balance),
+            nonce: (before.as_ref().map_or(0, |b| b.nonce) != info.nonce).then_some(info.nonce),
+            code: (before_code != after_code).then_some(after_code),
+            storage: post_storage,
+        };
+        if post_dump == AccountDump::default() {
+            continue;
+        }
+        if let Some(dump) = pre_dump {
+            pre.insert(*address, dump);
+        }
+        post.insert(*address, post_dump);
+    }
+
+    Ok(if diff_mode {
+        serde_json::json!({ "pre": pre, "post": post })
+    } else {
+        serde_json::to_value(pre)?
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use revm::primitives::{AccountInfo, Bytecode, SpecId, TransactTo};
+    use serde_json::json;
+
+    const CALLER: Address = Address::repeat_byte(0xca);
+    const COINBASE: Address = Address::repeat_byte(0xcb);
+    const STORE: Address = Address::repeat_byte(0x51);
+    const PROXY: Address = Address::repeat_byte(0x9a);
+    const REVERTER: Address = Address::repeat_byte(0xee);
+
+    /// SSTORE(1, 0x2a); RETURN(0, 32)
+    const STORE_CODE: &[u8] = &[0x60, 0x2a, 0x60, 0x01, 0x55, 0x60, 0x20, 0x5f, 0xf3];
+
+    fn ether() -> U256 {
+        U256::from(10u64).pow(U256::from(18))
+    }
+
+    /// CALL(GAS, STORE, 0, 0, 0, 0, 32); STOP
+    fn proxy_code() -> Vec<u8> {
+        let mut code = vec![0x60, 0x20, 0x5f, 0x5f, 0x5f, 0x5f, 0x73];
+        code.extend_from_slice(STORE.as_slice());
+        code.extend_from_slice(&[0x5a, 0xf1, 0x00]);
+        code
+    }
+
+    /// Reverts with `Error("nope")`, copied from the code's tail.
+    fn reverter_code() -> Vec<u8> {
+        let mut code = vec![0x60, 0x64, 0x60, 0x0a, 0x5f, 0x39, 0x60, 0x64, 0x5f, 0xfd];
+        code.extend_from_slice(&revert_data());
+        code
+    }
+
+    fn revert_data() -> Vec<u8> {
+        let mut data = vec![0x08, 0xc3, 0x79, 0xa0];
+        data.extend_from_slice(&U256::from(32).to_be_bytes::<32>());
+        data.extend_from_slice(&U256::from(4).to_be_bytes::<32>());
+        data.extend_from_slice(b"nope");
+        data.resize(4 + 3 * 32, 0);
+        data
+    }
+
+    fn state() -> StateDb {
+        let mut db = StateDb::new(Default::default());
+        db.insert_account_info(
+            CALLER,
+            AccountInfo {
+                balance: ether(),
+                ..AccountInfo::default()
+            },
+        );
+        for (address, code) in [
+            (STORE, STORE_CODE.to_vec()),
+            (PROXY, proxy_code()),
+            (REVERTER, reverter_code()),
+        ] {
+            let code = Bytecode::new_raw(code.into());
+            db.insert_account_info(
+                address,
+                AccountInfo {
+                    code_hash: code.hash_slow(),
+                    code: Some(code),
+                    ..AccountInfo::default()
+                },
+            );
+        }
+        db
+    }
+
+    fn env(to: TransactTo, data: &[u8]) -> Env {
+        let mut env = Env::default();
+        env.cfg.spec_id = SpecId::CANCUN;
+        env.block.coinbase = COINBASE;
+        env.tx.caller = CALLER;
+        env.tx.transact_to = to;
+        env.tx.data = data.to_vec().into();
+        env.tx.gas_limit = 100_000;
+        env.tx.gas_price = U256::ZERO;
+        env
+    }
+
+    fn tracer(name: &str, config: Value) -> TraceConfig {
+        TraceConfig {
+            tracer: Some(name.to_string()),
+            tracer_config: Some(config),
+            ..TraceConfig::default()
+        }
+    }
+
+    fn key(address: Address) -> String {
+        format!("{address:?}")
+    }
+
+    fn trace(to: Address, config: &TraceConfig) -> Value {
+        trace_tx(&mut state(), &env(TransactTo::Call(to), &[]), config).unwrap()
+    }
+
+    #[test]
+    fn struct_logger() {
+        let slot = |v: u8| word(U256::from(v));
+        let log = |pc, op: &str, gas, cost, stack: &[&str]| {
+            json!({
+                "pc": pc,
+                "op": op,
+                "gas": gas,
+                "gasCost": cost,
+                "depth": 1,
+                "stack": stack,
+            })
+        };
+        let mut sstore = log(4, "SSTORE", 78_994, 22_100, &["0x2a", "0x1"]);
+        sstore["storage"] = json!({ slot(1): slot(0x2a) });
+
+        assert_eq!(
+            trace(STORE, &TraceConfig::default()),
+            json!({
+                "gas": 43_114,
+                "failed": false,
+                "returnValue": slot(0),
+                "structLogs": [
+                    log(0, "PUSH1", 79_000, 3, &[]),
+                    log(2, "PUSH1", 78_997, 3, &["0x2a"]),
+                    sstore,
+                    log(5, "PUSH1", 56_894, 3, &[]),
+                    log(7, "PUSH0", 56_891, 2, &["0x20"]),
+                    log(8, "RETURN", 56_889, 3, &["0x20", "0x0"]),
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn call_tracer_nests_calls() {
+        assert_eq!(
+            trace(PROXY, &tracer("callTracer", Value::Null)),
+            json!({
+                "type": "CALL",
+                "from": CALLER,
+                "to": PROXY,
+                "value": "0x0",
+                "gas": "0x186a0",
+                "gasUsed": format!("{:#x}", 21_000 + 16 + 2_603 + 22_114),
+                "input": "0x",
+                "calls": [{
+                    "type": "CALL",
+                    "from": PROXY,
+                    "to": STORE,
+                    "value": "0x0",
+                    "gas": format!("{:#x}", 76_381 - 76_381 / 64),
+                    "gasUsed": format!("{:#x}", 22_114),
+                    "input": "0x",
+                    "output": format!("0x{}", word(U256::ZERO)),
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn call_tracer_decodes_revert_reasons() {
+        assert_eq!(
+            trace(REVERTER, &tracer("callTracer", Value::Null)),
+            json!({
+                "type": "CALL",
+                "from": CALLER,
+                "to": REVERTER,
+                "value": "0x0",
+                "gas": "0x186a0",
+                "gasUsed": format!("{:#x}", 21_000 + 40),
+                "input": "0x",
+                "output": format!("0x{}", hex::encode(revert_data())),
+                "error": "execution reverted",
+                "revertReason": "nope",
+            })
+        );
+    }
+
+    #[test]
+    fn call_tracer_reports_created_address() {
+        // CODECOPY(0, 10, 1); RETURN(0, 1), deploying a single STOP.
+        let init = [
+            0x60, 0x01, 0x60, 0x0a, 0x5f, 0x39, 0x60, 0x01, 0x5f, 0xf3, 0x00,
+        ];
+        let env = env(TransactTo::Create(CreateScheme::Create), &init);
+        let trace = trace_tx(&mut state(), &env, &tracer("callTracer", Value::Null)).unwrap();
+
+        // 53000 + 16 per non-zero and 4 per zero byte of calldata + 2 per
+        // initcode word, then 5 pushes, the copy and the 200 per byte deposit.
+        let gas_used = 53_000 + 10 * 16 + 4 + 2 + (3 * 3 + 2 * 2 + 3 + 3 + 3) + 200;
+        assert_eq!(
+            trace,
+            json!({
+                "type": "CREATE",
+                "from": CALLER,
+                "to": CALLER.create(0),
+                "value": "0x0",
+                "gas": "0x186a0",
+                "gasUsed": format!("{gas_used:#x}"),
+                "input": format!("0x{}", hex::encode(init)),
+                "output": "0x00",
+            })
+        );
+    }
+
+    #[test]
+    fn prestate_tracer_dumps_touched_accounts() {
+        let code = format!("0x{}", hex::encode(STORE_CODE));
+        let slot = |v: u8| B256::with_last_byte(v);
+        assert_eq!(
+            trace(STORE, &tracer("prestateTracer", Value::Null)),
+            json!({
+                key(CALLER): { "balance": ether() },
+                key(COINBASE): { "balance": "0x0" },
+                key(STORE): {
+                    "balance": "0x0",
+                    "code": code,
+                    "storage": { format!("{:?}", slot(1)): slot(0) },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn prestate_tracer_diff_mode() {
+        let code = format!("0x{}", hex::encode(STORE_CODE));
+        let slot = |v: u8| B256::with_last_byte(v);
+        assert_eq!(
+            trace(
+                STORE,
+                &tracer("prestateTracer", json!({ "diffMode": true }))
+            ),
+            json!({
+                "pre": {
+                    key(CALLER): { "balance": ether() },
+                    key(STORE): {
+                        "balance": "0x0",
+                        "code": code,
+                        "storage": { format!("{:?}", slot(1)): slot(0) },
+                    },
+                },
+                "post": {
+                    key(CALLER): { "nonce": 1 },
+                    key(STORE): { "storage": { format!("{:?}", slot(1)): slot(0x2a) } },
+                },
+            })
+        );
+    }
+}
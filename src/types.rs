@@ -1,7 +1,13 @@
-use revm::primitives::{Address, B256, Bytes, U256};
+use alloy_rlp::{Encodable, Header as RlpHeader, EMPTY_LIST_CODE, EMPTY_STRING_CODE};
+use revm::primitives::{address, keccak256, Address, B256, Bytes, Log, U256};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use uuid::Uuid;
 
+/// Sender of system transactions, which carry no signature and are applied
+/// by the node itself (EIP-4788 uses the same address).
+pub const SYSTEM_ADDRESS: Address = address!("fffffffffffffffffffffffffffffffffffffffe");
+
 /// Simplified transaction with optional PQ metadata.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HybridTx {
@@ -24,14 +30,113 @@ pub struct HybridTx {
     pub pq_pubkey: Option<Vec<u8>>,
 }
 
+/// EIP-2718 type byte of EIP-1559 transactions.
+const EIP1559_TX_TYPE: u8 = 0x02;
+
+impl HybridTx {
+    /// EIP-1559 signing payload, `0x02 || rlp([chain_id, nonce,
+    /// max_priority_fee_per_gas, max_fee_per_gas, gas_limit, to, value,
+    /// data, access_list])` with an empty access list, so standard wallets
+    /// can produce the ECDSA signature.
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let mut fields = Vec::new();
+        self.chain_id.encode(&mut fields);
+        self.nonce.encode(&mut fields);
+        self.max_priority_fee_per_gas.encode(&mut fields);
+        self.max_fee_per_gas.encode(&mut fields);
+        self.gas_limit.encode(&mut fields);
+        match &self.to {
+            Some(to) => to.encode(&mut fields),
+            None => fields.push(EMPTY_STRING_CODE),
+        }
+        self.value.encode(&mut fields);
+        self.data.encode(&mut fields);
+        fields.push(EMPTY_LIST_CODE);
+
+        let mut payload = vec![EIP1559_TX_TYPE];
+        let header = RlpHeader {
+            list: true,
+            payload_length: fields.len(),
+        };
+        header.encode(&mut payload);
+        payload.extend_from_slice(&fields);
+        payload
+    }
+
+    /// Hash the signatures commit to (see `signing_payload`). Like
+    /// Ethereum's, it leaves out the sender, which ECDSA recovers and an
+    /// ML-DSA key owns.
+    pub fn signing_hash(&self) -> B256 {
+        keccak256(self.signing_payload())
+    }
+
+    /// Identity of the transaction, carried as `hash`: the signing hash
+    /// bound to the sender, so identical transactions of two senders differ.
+    pub fn tx_hash(&self) -> B256 {
+        keccak256([self.signing_hash().as_slice(), self.from.as_slice()].concat())
+    }
+
+    /// Whether this is a system transaction: no signature, no fees, and only
+    /// valid at the start of a block.
+    pub fn is_system(&self) -> bool {
+        self.from == SYSTEM_ADDRESS
+    }
+
+    /// Price actually paid per unit of gas under `base_fee` (EIP-1559).
+    pub fn effective_gas_price(&self, base_fee: U256) -> U256 {
+        self.max_fee_per_gas
+            .min(base_fee.saturating_add(self.max_priority_fee_per_gas))
+    }
+}
+
+/// EIP-1559 base fee of the first block (1 gwei).
+pub const INITIAL_BASE_FEE: u64 = 1_000_000_000;
+/// Blocks target half of their gas limit.
+pub const ELASTICITY_MULTIPLIER: u64 = 2;
+/// Bounds the base fee change between two blocks to 1/8 (12.5%).
+pub const BASE_FEE_MAX_CHANGE_DENOMINATOR: u64 = 8;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockHeader {
     pub number: u64,
     pub hash: B256,
     pub parent_hash: B256,
+    /// Post-state root of this block, filled in by the executor. It is
+    /// not part of the consensus hash: execution runs behind consensus, and
+    /// validators sign the root in their block attestations instead.
     pub state_root: B256,
     pub tx_root: B256,
     pub timestamp: u64,
+    /// Fee recipient of the committing leader; receives the priority fees.
+    pub coinbase: Address,
+    /// Consensus-derived randomness beacon exposed as `block.prevrandao`.
+    pub prevrandao: B256,
+    pub gas_limit: u64,
+    /// Filled in by the executor once the block has run.
+    pub gas_used: u64,
+    /// Filled in by the executor from the parent block (EIP-1559).
+    pub base_fee_per_gas: U256,
+}
+
+impl BlockHeader {
+    /// EIP-1559 base fee for the child of this block.
+    pub fn next_base_fee(&self) -> U256 {
+        let gas_target = self.gas_limit / ELASTICITY_MULTIPLIER;
+        if gas_target == 0 || self.gas_used == gas_target {
+            return self.base_fee_per_gas;
+        }
+
+        let denominator = U256::from(gas_target) * U256::from(BASE_FEE_MAX_CHANGE_DENOMINATOR);
+        if self.gas_used > gas_target {
+            let delta = U256::from(self.gas_used - gas_target);
+            let change = (self.base_fee_per_gas * delta / denominator).max(U256::from(1));
+            self.base_fee_per_gas + change
+        } else {
+            let delta = U256::from(gas_target - self.gas_used);
+            let change = self.base_fee_per_gas * delta / denominator;
+            self.base_fee_per_gas.saturating_sub(change)
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -40,6 +145,49 @@ pub struct Block {
     pub txs: Vec<HybridTx>,
 }
 
+/// Receipt of a transaction as committed to by `receipts_root` and kept in
+/// `ChainStore`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub tx_hash: B256,
+    /// False if the tx reverted or halted, or was skipped as invalid.
+    pub success: bool,
+    pub cumulative_gas_used: u64,
+    pub logs: Vec<Log>,
+}
+
+impl Receipt {
+    /// Canonical bytes of the receipt, the Merkle leaf data: tx hash, status
+    /// byte, cumulative gas, then the logs, each as address, topics and data.
+    /// Counts and lengths are big-endian u64s.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(self.tx_hash.as_slice());
+        out.push(self.success as u8);
+        out.extend_from_slice(&self.cumulative_gas_used.to_be_bytes());
+        out.extend_from_slice(&(self.logs.len() as u64).to_be_bytes());
+        for log in &self.logs {
+            out.extend_from_slice(log.address.as_slice());
+            out.extend_from_slice(&(log.topics.len() as u64).to_be_bytes());
+            for topic in &log.topics {
+                out.extend_from_slice(topic.as_slice());
+            }
+            out.extend_from_slice(&(log.data.len() as u64).to_be_bytes());
+            out.extend_from_slice(&log.data);
+        }
+        out
+    }
+}
+
+/// Account as persisted in `ChainStore` (genesis allocations).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccountState {
+    pub balance: U256,
+    pub nonce: u64,
+    pub code: Bytes,
+    pub storage: BTreeMap<U256, U256>,
+}
+
 /// Narwhal “batch” node in the DAG.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NarwhalBatch {
@@ -48,6 +196,101 @@ pub struct NarwhalBatch {
     pub author: String, // validator id
     pub parents: Vec<Uuid>,
     pub txs: Vec<HybridTx>,
+    /// Author's clock (unix seconds) when it built the batch; blocks take
+    /// their timestamp from the batches they commit.
+    pub timestamp: u64,
+    /// Author's ML-DSA signature over `digest()`; empty if the node building
+    /// the batch is not a validator.
+    #[serde(default)]
+    pub signature: Vec<u8>,
+}
+
+impl NarwhalBatch {
+    /// Hash of everything the author signs.
+    pub fn digest(&self) -> B256 {
+        let tx_hashes: Vec<B256> = self.txs.iter().map(|tx| tx.hash).collect();
+        let fields = (
+            self.id,
+            self.round,
+            &self.author,
+            &self.parents,
+            tx_hashes,
+            self.timestamp,
+        );
+        keccak256(bincode::serialize(&fields).expect("batch fields serialize"))
+    }
+}
+
+/// A validator's signed statement that it committed `block_hash` at `number`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommitVote {
+    pub number: u64,
+    pub block_hash: B256,
+    pub validator: String,
+    pub signature: Vec<u8>,
+}
+
+/// Commit votes for one block from validators holding at least 2f+1 of the
+/// stake. Lets a node that was not online at the time accept the block.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommitCertificate {
+    pub number: u64,
+    pub block_hash: B256,
+    /// `(validator id, signature)`, one per validator.
+    pub votes: Vec<(String, Vec<u8>)>,
+}
+
+/// What validators attest to once they executed a block: enough for a
+/// destination chain to check the block's outcome without following
+/// consensus.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockAttestation {
+    pub chain_id: u64,
+    pub number: u64,
+    pub block_hash: B256,
+    /// Post-state root of the block.
+    pub state_root: B256,
+    pub receipts_root: B256,
+}
+
+/// One validator's ML-DSA signature over a `BlockAttestation`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttestationVote {
+    pub attestation: BlockAttestation,
+    pub validator: String,
+    pub signature: Vec<u8>,
+}
+
+/// An attestation signed by validators holding at least 2f+1 of the stake;
+/// what bridges deliver to destination chains.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedAttestation {
+    pub attestation: BlockAttestation,
+    /// `(validator id, signature)`, one per validator.
+    pub signatures: Vec<(String, Vec<u8>)>,
+}
+
+/// A burn or lock on another chain, to be minted or unlocked here.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InboundTransfer {
+    /// Bridge id of the source chain, as in `Deposit::dest_chain`.
+    pub source_chain: u64,
+    /// Unique per source chain; each is applied at most once.
+    pub nonce: u64,
+    /// `Address::ZERO` for native ETH.
+    pub token: Address,
+    pub recipient: Address,
+    pub amount: U256,
+    /// Mint `token` rather than release it from the bridge's custody.
+    pub mint: bool,
+}
+
+/// An inbound transfer signed by the guardian set, as submitted by relayers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InboundMessage {
+    pub transfer: InboundTransfer,
+    /// `(guardian id, signature)`, one per guardian.
+    pub signatures: Vec<(String, Vec<u8>)>,
 }
 
 /// Consensus events sent from P2P to consensus engine.
@@ -55,6 +298,7 @@ pub struct NarwhalBatch {
 pub enum ConsensusInput {
     NewTx(HybridTx),
     NarwhalBatch(NarwhalBatch),
+    CommitVote(CommitVote),
 }
 
 /// Outputs of consensus into the executor / block pipeline.
@@ -63,3 +307,138 @@ pub enum ConsensusOutput {
     CommittedBlock(Block),
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::verify_tx_signatures;
+    use k256::ecdsa::SigningKey;
+
+    const GAS_LIMIT: u64 = 30_000_000;
+    const GWEI: u64 = 1_000_000_000;
+
+    fn parent(gas_limit: u64, gas_used: u64) -> BlockHeader {
+        BlockHeader {
+            number: 1,
+            hash: B256::ZERO,
+            parent_hash: B256::ZERO,
+            state_root: B256::ZERO,
+            tx_root: B256::ZERO,
+            timestamp: 0,
+            coinbase: Address::ZERO,
+            prevrandao: B256::ZERO,
+            gas_limit,
+            gas_used,
+            base_fee_per_gas: U256::from(GWEI),
+        }
+    }
+
+    #[test]
+    fn base_fee_is_unchanged_at_target() {
+        let header = parent(GAS_LIMIT, GAS_LIMIT / 2);
+        assert_eq!(header.next_base_fee(), U256::from(GWEI));
+    }
+
+    #[test]
+    fn base_fee_rises_above_target() {
+        // A full block raises it by the maximum eighth.
+        let full = parent(GAS_LIMIT, GAS_LIMIT);
+        assert_eq!(full.next_base_fee(), U256::from(GWEI + GWEI / 8));
+
+        // Halfway between target and limit: a sixteenth.
+        let busy = parent(GAS_LIMIT, GAS_LIMIT * 3 / 4);
+        assert_eq!(busy.next_base_fee(), U256::from(GWEI + GWEI / 16));
+
+        // Any excess raises it by at least 1 wei.
+        let mut barely = parent(GAS_LIMIT, GAS_LIMIT / 2 + 1);
+        barely.base_fee_per_gas = U256::from(7);
+        assert_eq!(barely.next_base_fee(), U256::from(8));
+    }
+
+    #[test]
+    fn base_fee_falls_below_target() {
+        let quiet = parent(GAS_LIMIT, GAS_LIMIT / 4);
+        assert_eq!(quiet.next_base_fee(), U256::from(GWEI - GWEI / 16));
+    }
+
+    #[test]
+    fn base_fee_falls_by_an_eighth_on_empty_blocks() {
+        let empty = parent(GAS_LIMIT, 0);
+        assert_eq!(empty.next_base_fee(), U256::from(GWEI - GWEI / 8));
+
+        // ... and never below zero.
+        let mut free = parent(GAS_LIMIT, 0);
+        free.base_fee_per_gas = U256::ZERO;
+        assert_eq!(free.next_base_fee(), U256::ZERO);
+    }
+
+    #[test]
+    fn base_fee_is_unchanged_without_gas_limit() {
+        let header = parent(0, 0);
+        assert_eq!(header.next_base_fee(), U256::from(GWEI));
+    }
+
+    fn transfer() -> HybridTx {
+        HybridTx {
+            hash: B256::ZERO,
+            from: Address::ZERO,
+            to: Some(Address::repeat_byte(0x11)),
+            nonce: U256::ZERO,
+            gas_limit: 21_000,
+            max_fee_per_gas: U256::from(2),
+            max_priority_fee_per_gas: U256::from(1),
+            value: U256::ZERO,
+            data: Bytes::new(),
+            chain_id: 1,
+            sig: None,
+            pq_sig: None,
+            pq_pubkey: None,
+        }
+    }
+
+    #[test]
+    fn signing_payload_is_eip1559() {
+        let mut expected = hex::decode("02df0180010282520894").unwrap();
+        expected.extend([0x11; 20]);
+        expected.extend([0x80, 0x80, 0xc0]);
+        assert_eq!(transfer().signing_payload(), expected);
+
+        // Contract creation: `to` is the empty string.
+        let mut create = transfer();
+        create.to = None;
+        create.nonce = U256::from(0x0400);
+        create.data = Bytes::from(vec![0xaa; 56]);
+        let mut expected = hex::decode("02f846018204000102825208").unwrap();
+        expected.extend([0x80, 0x80, 0xb8, 56]);
+        expected.extend([0xaa; 56]);
+        expected.push(0xc0);
+        assert_eq!(create.signing_payload(), expected);
+    }
+
+    #[test]
+    fn prehash_signature_over_the_payload_verifies() {
+        let key = SigningKey::from_slice(&[7; 32]).unwrap();
+        let point = key.verifying_key().to_encoded_point(false);
+        let mut tx = transfer();
+        tx.from = Address::from_slice(&keccak256(&point.as_bytes()[1..])[12..]);
+        tx.hash = tx.tx_hash();
+
+        let (signature, recid) = key
+            .sign_prehash_recoverable(tx.signing_hash().as_slice())
+            .unwrap();
+        let mut sig = signature.to_bytes().to_vec();
+        sig.push(recid.to_byte());
+        tx.sig = Some(sig.into());
+        assert!(verify_tx_signatures(&tx).is_ok());
+
+        tx.value = U256::from(1);
+        assert!(verify_tx_signatures(&tx).is_err());
+    }
+
+    #[test]
+    fn tx_hash_binds_the_sender() {
+        let mut other = transfer();
+        other.from = Address::repeat_byte(1);
+        assert_eq!(other.signing_hash(), transfer().signing_hash());
+        assert_ne!(other.tx_hash(), transfer().tx_hash());
+    }
+}
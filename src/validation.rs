@@ -0,0 +1,430 @@
+use crate::config::NodeConfig;
+use crate::consensus::unix_time;
+use crate::crypto::verify_tx_signatures;
+use crate::evm::EvmExecutor;
+use crate::identity::ValidatorSet;
+use crate::inbound::InboundBridge;
+use crate::types::{AttestationVote, CommitVote, HybridTx, NarwhalBatch};
+use anyhow::{anyhow, ensure, Result};
+use revm::primitives::{B256, U256};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+const TX_BASE_GAS: u64 = 21_000;
+const TX_CREATE_GAS: u64 = 32_000;
+const TX_DATA_ZERO_GAS: u64 = 4;
+const TX_DATA_NONZERO_GAS: u64 = 16;
+/// How far ahead of the local clock a batch timestamp may be.
+const MAX_BATCH_CLOCK_DRIFT_SECS: u64 = 15;
+/// How far behind the newest round (or voted block) accepted from a
+/// validator its gossip may arrive, out of order, before it is stale.
+const MAX_SEQUENCE_LAG: u64 = 16;
+/// Hashes of accepted txs remembered to ignore copies relayed again.
+const SEEN_TXS: usize = 65_536;
+
+/// Outcome of checking a gossiped message, mirroring gossipsub's
+/// `MessageAcceptance`. Rejections count against the peer's score.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    /// Not forwarded, but not held against the sender.
+    Ignore(String),
+    /// Provably invalid; the sender is penalised.
+    Reject(String),
+}
+
+/// Application-level checks applied before gossip is forwarded or handed to
+/// consensus.
+pub struct GossipValidator {
+    validators: Arc<ValidatorSet>,
+    inbound: Option<Arc<InboundBridge>>,
+    /// Latest executed state, for the nonces inbound transfers used.
+    executor: Arc<EvmExecutor>,
+    chain_id: u64,
+    block_gas_limit: u64,
+    min_gas_price: U256,
+    seen: Mutex<Seen>,
+}
+
+/// Gossip accepted so far. Copies are ignored rather than rejected: peers
+/// relaying a message a second time are not at fault.
+#[derive(Default)]
+struct Seen {
+    txs: HashSet<B256>,
+    tx_order: VecDeque<B256>,
+    batches: Sequences,
+    commit_votes: Sequences,
+    attestations: Sequences,
+}
+
+impl Seen {
+    fn insert_tx(&mut self, hash: B256) {
+        if self.tx_order.len() == SEEN_TXS {
+            if let Some(oldest) = self.tx_order.pop_front() {
+                self.txs.remove(&oldest);
+            }
+        }
+        self.txs.insert(hash);
+        self.tx_order.push_back(hash);
+    }
+}
+
+/// Batch rounds or voted block numbers accepted per validator, within
+/// `MAX_SEQUENCE_LAG` of the newest.
+#[derive(Default)]
+struct Sequences(HashMap<String, BTreeSet<u64>>);
+
+impl Sequences {
+    /// Why gossip of `validator` at `height` is not worth relaying, if so.
+    fn check(&self, validator: &str, height: u64) -> Option<&'static str> {
+        let heights = self.0.get(validator)?;
+        if heights.contains(&height) {
+            return Some("a duplicate");
+        }
+        let newest = *heights.last()?;
+        (height.saturating_add(MAX_SEQUENCE_LAG) < newest).then_some("stale")
+    }
+
+    fn insert(&mut self, validator: &str, height: u64) {
+        let heights = self.0.entry(validator.to_string()).or_default();
+        heights.insert(height);
+        if let Some(&newest) = heights.last() {
+            heights.retain(|&h| h.saturating_add(MAX_SEQUENCE_LAG) >= newest);
+        }
+    }
+}
+
+impl GossipValidator {
+    pub fn new(
+        cfg: &NodeConfig,
+        validators: Arc<ValidatorSet>,
+        inbound: Option<Arc<InboundBridge>>,
+        executor: Arc<EvmExecutor>,
+    ) -> Self {
+        Self {
+            validators,
+            inbound,
+            executor,
+            chain_id: cfg.chain_id,
+            block_gas_limit: cfg.block_gas_limit,
+            min_gas_price: U256::from(cfg.min_gas_price),
+            seen: Mutex::new(Seen::default()),
+        }
+    }
+
+    fn seen(&self) -> MutexGuard<'_, Seen> {
+        self.seen.lock().unwrap()
+    }
+
+    pub fn validate_tx(&self, tx: &HybridTx) -> Verdict {
+        if self.seen().txs.contains(&tx.hash) {
+            return Verdict::Ignore(format!("tx {} is a duplicate", tx.hash));
+        }
+        let verdict = self.check_tx(tx);
+        if verdict == Verdict::Accept {
+            self.seen().insert_tx(tx.hash);
+        }
+        verdict
+    }
+
+    fn check_tx(&self, tx: &HybridTx) -> Verdict {
+        if tx.is_system() {
+            return self.validate_system_tx(tx);
+        }
+        if tx.chain_id != self.chain_id {
+            return Verdict::Reject(format!("tx {} is for chain {}", tx.hash, tx.chain_id));
+        }
+        if let Err(e) = verify_tx_body(tx) {
+            return Verdict::Reject(format!("{e:#}"));
+        }
+        if tx.gas_limit > self.block_gas_limit {
+            return Verdict::Reject(format!(
+                "tx {} gas limit {} exceeds the block gas limit",
+                tx.hash, tx.gas_limit
+            ));
+        }
+        if tx.gas_limit < intrinsic_gas(tx) {
+            return Verdict::Reject(format!("tx {} gas limit is below intrinsic gas", tx.hash));
+        }
+        if tx.max_priority_fee_per_gas > tx.max_fee_per_gas {
+            return Verdict::Reject(format!("tx {} priority fee exceeds its max fee", tx.hash));
+        }
+        if tx.max_fee_per_gas < self.min_gas_price {
+            return Verdict::Ignore(format!("tx {} is underpriced", tx.hash));
+        }
+        Verdict::Accept
+    }
+
+    /// System txs are free and unsigned; what vouches for them is the
+    /// guardian signatures on the transfer they carry. Transfers already
+    /// applied would only revert, burning block gas for free, so they are
+    /// not relayed; a batch including one is not held against its author,
+    /// whose node may have executed fewer blocks.
+    fn validate_system_tx(&self, tx: &HybridTx) -> Verdict {
+        let Some(inbound) = &self.inbound else {
+            return Verdict::Reject(format!(
+                "system tx {}: inbound transfers are disabled",
+                tx.hash
+            ));
+        };
+        match inbound.verify_system_tx(tx) {
+            Ok(msg) if inbound.is_applied(&self.executor, &msg.transfer) => {
+                Verdict::Ignore(format!("system tx {}: transfer already applied", tx.hash))
+            }
+            Ok(_) => Verdict::Accept,
+            Err(e) => Verdict::Reject(format!("{e:#}")),
+        }
+    }
+
+    /// A batch must be signed by the validator it names, and every tx in it
+    /// must pass `validate_tx`: a validator cannot vouch for invalid txs.
+    /// Only one batch per author and round is relayed.
+    pub fn validate_batch(&self, batch: &NarwhalBatch) -> Verdict {
+        if let Some(reason) = self.seen().batches.check(&batch.author, batch.round) {
+            return Verdict::Ignore(format!(
+                "batch {} of {} for round {} is {reason}",
+                batch.id, batch.author, batch.round
+            ));
+        }
+        if let Err(e) = self.validators.verify_batch(batch) {
+            return Verdict::Reject(format!("{e:#}"));
+        }
+        let now = unix_time().unwrap_or(0);
+        if batch.timestamp > now + MAX_BATCH_CLOCK_DRIFT_SECS {
+            return Verdict::Ignore(format!(
+                "batch {} is timestamped {}s in the future",
+                batch.id,
+                batch.timestamp - now
+            ));
+        }
+        for tx in &batch.txs {
+            if let Verdict::Reject(reason) = self.check_tx(tx) {
+                return Verdict::Reject(format!("batch {}: {reason}", batch.id));
+            }
+        }
+        self.seen().batches.insert(&batch.author, batch.round);
+        Verdict::Accept
+    }
+
+    pub fn validate_commit_vote(&self, vote: &CommitVote) -> Verdict {
+        let mut seen = self.seen();
+        if let Some(reason) = seen.commit_votes.check(&vote.validator, vote.number) {
+            return Verdict::Ignore(format!(
+                "commit vote of {} for block {} is {reason}",
+                vote.validator, vote.number
+            ));
+        }
+        match self.validators.verify_commit_vote(vote) {
+            Ok(()) => {
+                seen.commit_votes.insert(&vote.validator, vote.number);
+                Verdict::Accept
+            }
+            Err(e) => Verdict::Reject(format!("{e:#}")),
+        }
+    }
+
+    pub fn validate_attestation(&self, vote: &AttestationVote) -> Verdict {
+        let number = vote.attestation.number;
+        let mut seen = self.seen();
+        if let Some(reason) = seen.attestations.check(&vote.validator, number) {
+            return Verdict::Ignore(format!(
+                "attestation of {} for block {number} is {reason}",
+                vote.validator
+            ));
+        }
+        match self.validators.verify_attestation_vote(vote) {
+            Ok(()) => {
+                seen.attestations.insert(&vote.validator, number);
+                Verdict::Accept
+            }
+            Err(e) => Verdict::Reject(format!("{e:#}")),
+        }
+    }
+}
+
+/// Check that a transaction is what its hash and signatures claim. This
+/// does not depend on local policy, so it also holds for transactions of
+/// certified blocks. System transactions carry no signatures; the executor
+/// checks them against the guardians.
+pub fn verify_tx_body(tx: &HybridTx) -> Result<()> {
+    ensure!(tx.hash == tx.tx_hash(), "tx {} does not hash to its hash", tx.hash);
+    if !tx.is_system() {
+        verify_tx_signatures(tx).map_err(|e| anyhow!("tx {}: {e}", tx.hash))?;
+    }
+    Ok(())
+}
+
+/// Gas charged before execution starts; txs below it can never be included.
+fn intrinsic_gas(tx: &HybridTx) -> u64 {
+    let data: u64 = tx
+        .data
+        .iter()
+        .map(|&b| if b == 0 { TX_DATA_ZERO_GAS } else { TX_DATA_NONZERO_GAS })
+        .sum();
+    let create = if tx.to.is_none() { TX_CREATE_GAS } else { 0 };
+    TX_BASE_GAS + create + data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genesis::Genesis;
+    use crate::identity::ValidatorKey;
+    use crate::types::BlockAttestation;
+    use k256::ecdsa::SigningKey;
+    use revm::primitives::{keccak256, Address, Bytes};
+    use uuid::Uuid;
+
+    fn setup() -> (GossipValidator, ValidatorKey) {
+        let (key, config) = ValidatorKey::generate(1337, "a", 1);
+        let cfg = NodeConfig {
+            validators: vec![config],
+            ..NodeConfig::default()
+        };
+        let set = Arc::new(ValidatorSet::new(cfg.chain_id, &cfg.validators).unwrap());
+        let executor = Arc::new(EvmExecutor::new(Genesis::dev(&cfg).config, 1, 0, None));
+        (GossipValidator::new(&cfg, set, None, executor), key)
+    }
+
+    fn signed_tx(nonce: u64) -> HybridTx {
+        let key = SigningKey::from_slice(&[7; 32]).unwrap();
+        let point = key.verifying_key().to_encoded_point(false);
+        let mut tx = HybridTx {
+            hash: B256::ZERO,
+            from: Address::from_slice(&keccak256(&point.as_bytes()[1..])[12..]),
+            to: Some(Address::repeat_byte(0x11)),
+            nonce: U256::from(nonce),
+            gas_limit: 21_000,
+            max_fee_per_gas: U256::from(2),
+            max_priority_fee_per_gas: U256::from(1),
+            value: U256::ZERO,
+            data: Bytes::new(),
+            chain_id: 1337,
+            sig: None,
+            pq_sig: None,
+            pq_pubkey: None,
+        };
+        tx.hash = tx.tx_hash();
+        let (signature, recid) = key
+            .sign_prehash_recoverable(tx.signing_hash().as_slice())
+            .unwrap();
+        let mut sig = signature.to_bytes().to_vec();
+        sig.push(recid.to_byte());
+        tx.sig = Some(sig.into());
+        tx
+    }
+
+    fn batch(key: &ValidatorKey, round: u64) -> NarwhalBatch {
+        let mut batch = NarwhalBatch {
+            id: Uuid::new_v4(),
+            round,
+            author: key.id.clone(),
+            parents: vec![],
+            txs: vec![signed_tx(0)],
+            timestamp: unix_time().unwrap(),
+            signature: vec![],
+        };
+        batch.signature = key.sign_batch(&batch).unwrap();
+        batch
+    }
+
+    fn attestation(key: &ValidatorKey, number: u64) -> AttestationVote {
+        key.sign_attestation(BlockAttestation {
+            chain_id: 1337,
+            number,
+            block_hash: B256::repeat_byte(1),
+            state_root: B256::repeat_byte(2),
+            receipts_root: B256::repeat_byte(3),
+        })
+        .unwrap()
+    }
+
+    fn is_reject(verdict: Verdict) -> bool {
+        matches!(verdict, Verdict::Reject(_))
+    }
+
+    fn is_ignore(verdict: Verdict) -> bool {
+        matches!(verdict, Verdict::Ignore(_))
+    }
+
+    #[test]
+    fn tx_verdicts() {
+        let (validator, _) = setup();
+        let tx = signed_tx(0);
+        assert_eq!(validator.validate_tx(&tx), Verdict::Accept);
+        assert!(is_ignore(validator.validate_tx(&tx)));
+
+        let mut forged = signed_tx(1);
+        if let Some(sig) = &mut forged.sig {
+            let mut bytes = sig.to_vec();
+            bytes[10] ^= 1;
+            *sig = bytes.into();
+        }
+        assert!(is_reject(validator.validate_tx(&forged)));
+        assert_eq!(validator.validate_tx(&signed_tx(1)), Verdict::Accept);
+    }
+
+    #[test]
+    fn batch_verdicts() {
+        let (validator, key) = setup();
+        assert_eq!(validator.validate_batch(&batch(&key, 30)), Verdict::Accept);
+        // Another batch for the same round, or one too far behind.
+        assert!(is_ignore(validator.validate_batch(&batch(&key, 30))));
+        assert!(is_ignore(
+            validator.validate_batch(&batch(&key, 30 - MAX_SEQUENCE_LAG - 1))
+        ));
+        // Late, but still in time for consensus.
+        assert_eq!(
+            validator.validate_batch(&batch(&key, 30 - MAX_SEQUENCE_LAG)),
+            Verdict::Accept
+        );
+
+        let mut forged = batch(&key, 31);
+        forged.timestamp += 1;
+        assert!(is_reject(validator.validate_batch(&forged)));
+        let (stranger, _) = ValidatorKey::generate(1337, "b", 1);
+        assert!(is_reject(validator.validate_batch(&batch(&stranger, 31))));
+        assert_eq!(validator.validate_batch(&batch(&key, 31)), Verdict::Accept);
+    }
+
+    #[test]
+    fn commit_vote_verdicts() {
+        let (validator, key) = setup();
+        let vote = key.sign_commit(5, B256::repeat_byte(1)).unwrap();
+        assert_eq!(validator.validate_commit_vote(&vote), Verdict::Accept);
+        assert!(is_ignore(validator.validate_commit_vote(&vote)));
+
+        let mut forged = key.sign_commit(6, B256::repeat_byte(1)).unwrap();
+        forged.block_hash = B256::repeat_byte(2);
+        assert!(is_reject(validator.validate_commit_vote(&forged)));
+        let mut unknown = key.sign_commit(6, B256::repeat_byte(1)).unwrap();
+        unknown.validator = "b".to_string();
+        assert!(is_reject(validator.validate_commit_vote(&unknown)));
+    }
+
+    #[test]
+    fn attestation_verdicts() {
+        let (validator, key) = setup();
+        assert_eq!(
+            validator.validate_attestation(&attestation(&key, 40)),
+            Verdict::Accept
+        );
+        // A copy, and an attestation far behind the newest.
+        for number in [40, 2] {
+            let vote = attestation(&key, number);
+            assert!(is_ignore(validator.validate_attestation(&vote)));
+        }
+
+        let mut forged = attestation(&key, 41);
+        forged.attestation.state_root = B256::repeat_byte(9);
+        assert!(is_reject(validator.validate_attestation(&forged)));
+        let (stranger, _) = ValidatorKey::generate(1337, "b", 1);
+        assert!(is_reject(
+            validator.validate_attestation(&attestation(&stranger, 41))
+        ));
+        assert_eq!(
+            validator.validate_attestation(&attestation(&key, 41)),
+            Verdict::Accept
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use crate::types::{AttestationVote, CommitVote, HybridTx, NarwhalBatch};
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+/// Version of the gossip encoding; bumped on any incompatible change to
+/// `Envelope` or `GossipMessage`.
+pub const PROTOCOL_VERSION: u16 = 1;
+/// Payloads smaller than this are sent uncompressed.
+const COMPRESSION_THRESHOLD: usize = 512;
+/// Upper bound on a decoded payload, so a small compressed message cannot
+/// expand into an arbitrarily large allocation.
+const MAX_PAYLOAD_SIZE: u64 = 8 * 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum GossipMessage {
+    Tx(HybridTx),
+    Batch(NarwhalBatch),
+    CommitVote(CommitVote),
+    Attestation(AttestationVote),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum Compression {
+    None,
+    Snappy,
+}
+
+/// Frame of every gossip message: bincode of the message, optionally
+/// snappy-compressed, tagged with the version and network it was meant for.
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+    version: u16,
+    chain_id: u64,
+    compression: Compression,
+    payload: Vec<u8>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WireError {
+    #[error("malformed message: {0}")]
+    Malformed(String),
+    #[error("unsupported protocol version {0}")]
+    UnsupportedVersion(u16),
+    #[error("message for chain {0}")]
+    WrongChain(u64),
+}
+
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new().with_limit(MAX_PAYLOAD_SIZE)
+}
+
+/// Serialises gossip for one network.
+pub struct WireCodec {
+    chain_id: u64,
+    compress: bool,
+}
+
+impl WireCodec {
+    pub fn new(chain_id: u64, compress: bool) -> Self {
+        Self { chain_id, compress }
+    }
+
+    pub fn encode(&self, msg: &GossipMessage) -> Result<Vec<u8>, WireError> {
+        let raw = bincode_options()
+            .serialize(msg)
+            .map_err(|e| WireError::Malformed(e.to_string()))?;
+
+        let (compression, payload) = match self.compress && raw.len() >= COMPRESSION_THRESHOLD {
+            true => {
+                let compressed = snap::raw::Encoder::new()
+                    .compress_vec(&raw)
+                    .map_err(|e| WireError::Malformed(e.to_string()))?;
+                match compressed.len() < raw.len() {
+                    true => (Compression::Snappy, compressed),
+                    false => (Compression::None, raw),
+                }
+            }
+            false => (Compression::None, raw),
+        };
+
+        let envelope = Envelope {
+            version: PROTOCOL_VERSION,
+            chain_id: self.chain_id,
+            compression,
+            payload,
+        };
+        bincode_options()
+            .serialize(&envelope)
+            .map_err(|e| WireError::Malformed(e.to_string()))
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Result<GossipMessage, WireError> {
+        let envelope: Envelope = bincode_options()
+            .deserialize(bytes)
+            .map_err(|e| WireError::Malformed(e.to_string()))?;
+        if envelope.version != PROTOCOL_VERSION {
+            return Err(WireError::UnsupportedVersion(envelope.version));
+        }
+        if envelope.chain_id != self.chain_id {
+            return Err(WireError::WrongChain(envelope.chain_id));
+        }
+
+        let payload = match envelope.compression {
+            Compression::None => envelope.payload,
+            Compression::Snappy => {
+                let len = snap::raw::decompress_len(&envelope.payload)
+                    .map_err(|e| WireError::Malformed(e.to_string()))?;
+                if len as u64 > MAX_PAYLOAD_SIZE {
+                    return Err(WireError::Malformed(format!("{len} byte payload")));
+                }
+                snap::raw::Decoder::new()
+                    .decompress_vec(&envelope.payload)
+                    .map_err(|e| WireError::Malformed(e.to_string()))?
+            }
+        };
+        bincode_options()
+            .deserialize(&payload)
+            .map_err(|e| WireError::Malformed(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::BlockAttestation;
+    use revm::primitives::{Address, Bytes, B256, U256};
+    use uuid::Uuid;
+
+    const CHAIN_ID: u64 = 1337;
+
+    fn tx(nonce: u64) -> HybridTx {
+        HybridTx {
+            hash: B256::with_last_byte(nonce as u8),
+            from: Address::with_last_byte(1),
+            to: Some(Address::with_last_byte(2)),
+            nonce: U256::from(nonce),
+            gas_limit: 21_000,
+            max_fee_per_gas: U256::from(7),
+            max_priority_fee_per_gas: U256::from(1),
+            value: U256::from(1_000),
+            data: Bytes::from(vec![0xab; 40]),
+            chain_id: CHAIN_ID,
+            sig: Some(Bytes::from(vec![0x11; 65])),
+            pq_sig: Some(vec![0x22; 64]),
+            pq_pubkey: None,
+        }
+    }
+
+    fn messages() -> Vec<GossipMessage> {
+        let block_hash = B256::repeat_byte(0xbb);
+        vec![
+            GossipMessage::Tx(tx(0)),
+            GossipMessage::Batch(NarwhalBatch {
+                id: Uuid::new_v4(),
+                round: 4,
+                author: "a".to_string(),
+                parents: vec![Uuid::new_v4()],
+                // Large enough to be compressed.
+                txs: (0..20).map(tx).collect(),
+                timestamp: 1_700_000_000,
+                signature: vec![0x33; 64],
+            }),
+            GossipMessage::CommitVote(CommitVote {
+                number: 9,
+                block_hash,
+                validator: "b".to_string(),
+                signature: vec![0x44; 64],
+            }),
+            GossipMessage::Attestation(AttestationVote {
+                attestation: BlockAttestation {
+                    chain_id: CHAIN_ID,
+                    number: 9,
+                    block_hash,
+                    state_root: B256::repeat_byte(0x55),
+                    receipts_root: B256::repeat_byte(0x66),
+                },
+                validator: "c".to_string(),
+                signature: vec![0x77; 64],
+            }),
+        ]
+    }
+
+    #[test]
+    fn every_message_round_trips() {
+        for compress in [false, true] {
+            let codec = WireCodec::new(CHAIN_ID, compress);
+            for msg in messages() {
+                let bytes = codec.encode(&msg).unwrap();
+                let decoded = codec.decode(&bytes).unwrap();
+                assert_eq!(format!("{decoded:?}"), format!("{msg:?}"));
+            }
+        }
+    }
+
+    #[test]
+    fn large_messages_are_compressed() {
+        let batch = messages().swap_remove(1);
+        let plain = WireCodec::new(CHAIN_ID, false).encode(&batch).unwrap();
+        let compressed = WireCodec::new(CHAIN_ID, true).encode(&batch).unwrap();
+        assert!(compressed.len() < plain.len());
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let codec = WireCodec::new(CHAIN_ID, false);
+        let mut bytes = codec.encode(&GossipMessage::Tx(tx(0))).unwrap();
+        // The varint version is the first byte of the envelope.
+        assert_eq!(bytes[0], PROTOCOL_VERSION as u8);
+        bytes[0] = PROTOCOL_VERSION as u8 + 1;
+        assert!(matches!(
+            codec.decode(&bytes),
+            Err(WireError::UnsupportedVersion(v)) if v == PROTOCOL_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn rejects_other_chains() {
+        let bytes = WireCodec::new(CHAIN_ID + 1, false)
+            .encode(&GossipMessage::Tx(tx(0)))
+            .unwrap();
+        assert!(matches!(
+            WireCodec::new(CHAIN_ID, false).decode(&bytes),
+            Err(WireError::WrongChain(id)) if id == CHAIN_ID + 1
+        ));
+    }
+
+    #[test]
+    fn rejects_oversized_snappy_payloads() {
+        // A snappy block starts with the varint of its decompressed length.
+        let mut payload = Vec::new();
+        let mut len = MAX_PAYLOAD_SIZE + 1;
+        while len >= 0x80 {
+            payload.push(len as u8 | 0x80);
+            len >>= 7;
+        }
+        payload.push(len as u8);
+        payload.extend_from_slice(&[0; 16]);
+        assert_eq!(
+            snap::raw::decompress_len(&payload).unwrap() as u64,
+            MAX_PAYLOAD_SIZE + 1
+        );
+
+        let envelope = Envelope {
+            version: PROTOCOL_VERSION,
+            chain_id: CHAIN_ID,
+            compression: Compression::Snappy,
+            payload,
+        };
+        let bytes = bincode_options().serialize(&envelope).unwrap();
+        assert!(matches!(
+            WireCodec::new(CHAIN_ID, true).decode(&bytes),
+            Err(WireError::Malformed(msg)) if msg == format!("{} byte payload", MAX_PAYLOAD_SIZE + 1)
+        ));
+    }
+}